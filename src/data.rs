@@ -1,7 +1,7 @@
 use crate::rendering::Camera;
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Matrix4, SquareMatrix};
-use crevice::std140::AsStd140;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3, Vector4};
+use crevice::std140::{AsStd140, Writer};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
@@ -21,29 +21,114 @@ pub struct Vertex3 {
 #[derive(Debug, Copy, Clone, AsStd140)]
 pub struct Uniforms {
     model_view_proj: Matrix4<f32>,
+    eye_volume_coord: Vector3<f32>, // camera position in volume coordinates, the cube spans [0, 1]
+    znear: f32,
 }
 
 impl Uniforms {
     pub fn new() -> Self {
         Self {
             model_view_proj: Matrix4::identity(),
+            eye_volume_coord: Vector3::new(0.0, 0.0, 0.0),
+            znear: 0.0,
         }
     }
     pub fn update_model_view_proj(&mut self, camera: &Camera, model_transformation: Matrix4<f32>) {
         self.model_view_proj = camera.build_view_projection_matrix(model_transformation);
+        // the cube of create_cube_fbo spans [-0.5, 0.5] before the model transformation,
+        // a singular one flattens the cube, so the eye position inside it hardly matters
+        let inverse = model_transformation.invert().unwrap_or_else(|| {
+            log::warn!(
+                "Model transformation is not invertible, using the eye position in world space"
+            );
+            Matrix4::identity()
+        });
+        let eye = inverse * camera.eye.to_homogeneous();
+        self.eye_volume_coord = eye.truncate() / eye.w + Vector3::new(0.5, 0.5, 0.5);
+        self.znear = camera.znear;
+    }
+
+    pub fn model_view_proj(&self) -> Matrix4<f32> {
+        self.model_view_proj
     }
 }
 
-#[repr(C)]
+/// Uniforms of slice.wgsl
 #[derive(Debug, Copy, Clone, AsStd140)]
+pub struct SliceUniforms {
+    pub axis: u32,     // a crate::rendering::SliceAxis
+    pub position: f32, // along the axis in volume texture coordinates
+}
+
+/// Ratio of the largest to the smallest non-zero scalar the logarithmic remap spreads over [0, 1], LOG_SCALE_RANGE of canvas_shader.wgsl
+const LOG_SCALE_RANGE: f32 = 1000.0;
+
+/// Uniforms of canvas_shader.wgsl, see [`CanvasShaderUniforms::as_std140_bytes`] for the buffer layout
+#[derive(Debug, Copy, Clone)]
 pub struct CanvasShaderUniforms {
     pub step_size: f32,
+    /// Distance between samples the opacities of the transfer function are meant for. A sample standing for
+    /// a segment of length `step` is composited with the corrected opacity `1 - (1 - alpha)^(step / base_distance)`,
+    /// so the apparent density does not change with `step_size`
     pub base_distance: f32,
+    /// Ray marching stops once the accumulated opacity exceeds this (early ray termination), in [0, 1]
     pub opacity_threshold: f32,
     pub ambient_intensity: f32,
     pub diffuse_intensity: f32,
     pub specular_intensity: f32,
     pub shininess: f32,
+    pub min_opacity: f32, // samples with transfer function opacity below this are skipped
+    pub silhouette_enhance: f32, // exponent k of the (1 - |normal . view|)^k opacity modulation, 0 disables it
+    pub mask_threshold: f32, // samples whose mask value is below this are skipped
+    pub render_mode: u32,        // a crate::rendering::RenderMode
+    pub precomputed_gradient: u32, // 1 if a gradient volume is bound, see CanvasPass::with_gradients
+    pub occupancy_block_size: f32, // block size in voxels of the occupancy grid, 0 disables empty-space skipping
+    pub clip_plane_enabled: u32,   // 1 if samples behind clip_plane are skipped
+    /// Normal (xyz) and distance (w) of the clip plane in volume texture coordinates,
+    /// samples with `dot(normal, position) < distance` are skipped
+    pub clip_plane: Vector4<f32>,
+    /// Corners of the region of interest in volume texture coordinates, samples outside of it are skipped
+    pub roi_min: Vector3<f32>,
+    pub roi_max: Vector3<f32>,
+    /// Window/level applied to the scalar before the transfer function lookup,
+    /// `(s - (center - width / 2)) / width` clamped to [0, 1]
+    pub window_center: f32,
+    pub window_width: f32,
+    /// Direction the light travels in volume texture coordinates, used unless `headlight` is 1
+    pub light_direction: Vector3<f32>,
+    pub headlight: u32, // 1 if the light travels along the viewing rays, following the camera
+    pub rgba_volume: u32, // 1 if the volume stores color and opacity instead of a scalar, see CanvasPass::new
+    pub lod_per_distance: f32, // see CanvasPass::set_level_of_detail
+    pub adaptive_min_step: f32, // see CanvasPass::set_adaptive_step
+    pub adaptive_max_step: f32, // 0 disables the adaptive step
+    pub transfer_function_2d: u32, // 1 if the 2D transfer function is looked up, see CanvasPass::update_transfer_function_2d
+    pub max_gradient_magnitude: f32, // gradient magnitude per voxel mapped to the last row of the 2D transfer function
+    pub interpolation: u32,          // a crate::rendering::Interpolation
+    pub jitter: u32, // 1 if the ray starts are offset per pixel, see CanvasPass::set_jitter_enabled
+    pub frame_index: u32, // varies the jitter over time, see CanvasPass::set_frame_index
+    pub pick_opacity: f32, // composited opacity at which CanvasPass::pick finds the hit
+    /// Distance along the viewing direction in world units as `dot(xyz, position) + w` of a position in
+    /// volume texture coordinates, see [`CanvasShaderUniforms::update_view_depth_plane`]
+    pub view_depth_plane: Vector4<f32>,
+    pub ray_near: f32, // samples closer than this view depth are skipped, see CanvasPass::set_ray_range
+    pub ray_far: f32,  // samples farther than this view depth are skipped
+    pub background_mode: u32, // 1 if fragments are composited over the gradient below, see CanvasPass::set_background
+    /// Premultiplied colors of the vertical background gradient at the top and the bottom of the canvas
+    pub background_top: Vector4<f32>,
+    pub background_bottom: Vector4<f32>,
+    /// Upper bound of the ray marching iterations, so that a tiny step size cannot stall the GPU
+    pub max_steps: u32,
+    pub exposure: f32, // multiplies the composited color before tone mapping, see CanvasPass::set_exposure
+    pub tonemap_mode: u32, // a crate::rendering::ToneMapping
+    pub label_overlay: u32, // 1 if a label volume is bound, see CanvasPass::with_labels
+    pub preintegrated: u32, // 1 to classify the segments between samples, see CanvasPass::set_preintegration_enabled
+    pub scalar_gamma: f32, // windowed scalars are raised to this power before the transfer function lookup
+    pub scalar_log_scale: u32, // 1 to remap windowed scalars logarithmically before the gamma
+    pub ao_strength: f32, // how much occluding material around a sample darkens it, 0 disables ambient occlusion
+    pub ao_radius: f32,   // distance in volume texture coordinates searched for occluding material
+    pub face_buffer_view: u32, // a crate::rendering::FaceBufferView
+    pub opacity_scale: f32, // multiplies the opacity of the transfer function before the opacity correction
+    pub voxel_spacing: Vector3<f32>, // physical size of a voxel, normals and light directions are scaled by it
 }
 
 impl Default for CanvasShaderUniforms {
@@ -56,6 +141,354 @@ impl Default for CanvasShaderUniforms {
             diffuse_intensity: 0.5,
             specular_intensity: 0.5,
             shininess: 32.0,
+            min_opacity: 0.0,
+            silhouette_enhance: 0.0,
+            mask_threshold: 0.5,
+            render_mode: 0,
+            precomputed_gradient: 0,
+            occupancy_block_size: 0.0,
+            clip_plane_enabled: 0,
+            clip_plane: Vector4::new(0.0, 0.0, 1.0, 0.0),
+            roi_min: Vector3::new(0.0, 0.0, 0.0),
+            roi_max: Vector3::new(1.0, 1.0, 1.0),
+            window_center: 0.5,
+            window_width: 1.0,
+            light_direction: Vector3::new(0.0, 0.0, -1.0),
+            headlight: 1,
+            rgba_volume: 0,
+            lod_per_distance: 0.0,
+            adaptive_min_step: 0.0,
+            adaptive_max_step: 0.0,
+            transfer_function_2d: 0,
+            max_gradient_magnitude: 0.25,
+            interpolation: 0,
+            jitter: 0,
+            frame_index: 0,
+            pick_opacity: 0.5,
+            view_depth_plane: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            ray_near: 0.0,
+            ray_far: f32::MAX,
+            background_mode: 0,
+            background_top: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            background_bottom: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            // enough for the diagonal of a 2048^3 volume at half a voxel per step
+            max_steps: 8192,
+            exposure: 1.0,
+            tonemap_mode: 0,
+            label_overlay: 0,
+            preintegrated: 0,
+            scalar_gamma: 1.0,
+            scalar_log_scale: 0,
+            ao_strength: 0.0,
+            ao_radius: 0.02,
+            face_buffer_view: 0,
+            opacity_scale: 1.0,
+            voxel_spacing: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl CanvasShaderUniforms {
+    /// Default uniforms with a step size of half a voxel along the largest dimension,
+    /// so that small volumes are not over-sampled and large ones are not under-sampled
+    pub fn for_volume(dims: (usize, usize, usize)) -> Self {
+        let max_dim = dims.0.max(dims.1).max(dims.2).max(1) as f32;
+        let step_size = 0.5 / max_dim;
+        Self {
+            step_size,
+            base_distance: step_size,
+            ..Default::default()
+        }
+    }
+
+    /// Scalar looked up in the transfer function for a sample of the volume, `remap_scalar(apply_window(scalar))`
+    /// of canvas_shader.wgsl. Never decreases with `scalar`, so the occupancy grid classifies the range of a block through it
+    pub fn transfer_function_scalar(&self, scalar: f32) -> f32 {
+        let window_min = self.window_center - self.window_width / 2.0;
+        let mut remapped = ((scalar - window_min) / self.window_width).clamp(0.0, 1.0);
+        if self.scalar_log_scale != 0 {
+            remapped = (1.0 + remapped * LOG_SCALE_RANGE).ln() / (1.0 + LOG_SCALE_RANGE).ln();
+        }
+        if remapped <= 0.0 {
+            0.0
+        } else {
+            remapped.powf(self.scalar_gamma)
+        }
+    }
+
+    /// Bytes of FragmentUniforms in canvas_shader.wgsl in std140 layout.
+    /// The fields are written one by one because deriving AsStd140 takes exponential compile time in the number of fields
+    pub fn as_std140_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // writing into a Vec never fails
+        self.write_std140(&mut Writer::new(&mut bytes)).unwrap();
+        // the size of a uniform struct is a multiple of 16 bytes
+        bytes.resize(bytes.len().next_multiple_of(16), 0);
+        bytes
+    }
+
+    /// Fields in the order of FragmentUniforms in canvas_shader.wgsl
+    fn write_std140(&self, writer: &mut Writer<&mut Vec<u8>>) -> std::io::Result<()> {
+        writer.write(&self.step_size)?;
+        writer.write(&self.base_distance)?;
+        writer.write(&self.opacity_threshold)?;
+        writer.write(&self.ambient_intensity)?;
+        writer.write(&self.diffuse_intensity)?;
+        writer.write(&self.specular_intensity)?;
+        writer.write(&self.shininess)?;
+        writer.write(&self.min_opacity)?;
+        writer.write(&self.silhouette_enhance)?;
+        writer.write(&self.mask_threshold)?;
+        writer.write(&self.render_mode)?;
+        writer.write(&self.precomputed_gradient)?;
+        writer.write(&self.occupancy_block_size)?;
+        writer.write(&self.clip_plane_enabled)?;
+        writer.write(&self.clip_plane)?;
+        writer.write(&self.roi_min)?;
+        writer.write(&self.roi_max)?;
+        writer.write(&self.window_center)?;
+        writer.write(&self.window_width)?;
+        writer.write(&self.light_direction)?;
+        writer.write(&self.headlight)?;
+        writer.write(&self.rgba_volume)?;
+        writer.write(&self.lod_per_distance)?;
+        writer.write(&self.adaptive_min_step)?;
+        writer.write(&self.adaptive_max_step)?;
+        writer.write(&self.transfer_function_2d)?;
+        writer.write(&self.max_gradient_magnitude)?;
+        writer.write(&self.interpolation)?;
+        writer.write(&self.jitter)?;
+        writer.write(&self.frame_index)?;
+        writer.write(&self.pick_opacity)?;
+        writer.write(&self.view_depth_plane)?;
+        writer.write(&self.ray_near)?;
+        writer.write(&self.ray_far)?;
+        writer.write(&self.background_mode)?;
+        writer.write(&self.background_top)?;
+        writer.write(&self.background_bottom)?;
+        writer.write(&self.max_steps)?;
+        writer.write(&self.exposure)?;
+        writer.write(&self.tonemap_mode)?;
+        writer.write(&self.label_overlay)?;
+        writer.write(&self.preintegrated)?;
+        writer.write(&self.scalar_gamma)?;
+        writer.write(&self.scalar_log_scale)?;
+        writer.write(&self.ao_strength)?;
+        writer.write(&self.ao_radius)?;
+        writer.write(&self.face_buffer_view)?;
+        writer.write(&self.opacity_scale)?;
+        writer.write(&self.voxel_spacing)?;
+        Ok(())
+    }
+
+    /// Measures view depths from `camera` in the world space of `model_transformation`,
+    /// which maps the unit cube centered at the origin onto the volume like in [`Uniforms::update_model_view_proj`]
+    pub fn update_view_depth_plane(&mut self, camera: &Camera, model_transformation: Matrix4<f32>) {
+        let view_direction = (camera.center - camera.eye).normalize();
+        // depth(p) = dot(M * (p - 0.5) - eye, view_direction) is affine in the volume texture coordinate p
+        let normal = (model_transformation.transpose() * view_direction.extend(0.0)).truncate();
+        let origin = model_transformation * Vector4::new(-0.5, -0.5, -0.5, 1.0);
+        let distance = (origin.truncate() - camera.eye.to_vec()).dot(view_direction);
+        self.view_depth_plane = normal.extend(distance);
+    }
+
+    /// Early ray termination threshold, lower values trade accuracy for speed
+    pub fn with_opacity_threshold(mut self, opacity_threshold: f32) -> Self {
+        self.opacity_threshold = opacity_threshold;
+        self
+    }
+}
+
+#[cfg(test)]
+mod data_tests {
+    use super::*;
+    #[test]
+    fn test_opacity_threshold_std140_offset() {
+        let uniforms = CanvasShaderUniforms::default().with_opacity_threshold(0.75);
+        let bytes = uniforms.as_std140_bytes();
+        // third f32 of FragmentUniforms in canvas_shader.wgsl
+        assert_eq!(f32::from_ne_bytes(bytes[8..12].try_into().unwrap()), 0.75);
+    }
+
+    #[test]
+    fn test_eye_volume_coord() {
+        let camera = Camera {
+            eye: (0.0, -2.0, 1.0).into(),
+            center: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut uniforms = Uniforms::new();
+        uniforms.update_model_view_proj(&camera, Matrix4::from_nonuniform_scale(1.0, 2.0, 0.5));
+        assert_eq!(uniforms.eye_volume_coord, Vector3::new(0.5, -0.5, 2.5));
+        assert_eq!(uniforms.znear, 0.1);
+        // a volume flattened to a plane falls back to the world space eye
+        uniforms.update_model_view_proj(&camera, Matrix4::from_nonuniform_scale(1.0, 1.0, 0.0));
+        assert_eq!(uniforms.eye_volume_coord, Vector3::new(0.5, -1.5, 1.5));
+    }
+
+    #[test]
+    fn test_face_uniforms_std140_size() {
+        // znear fills the padding after the vec3 like in shader3d.wgsl
+        assert_eq!(Uniforms::std140_size_static(), 80);
+    }
+
+    #[test]
+    fn test_std140_size() {
+        // voxel_spacing at offset 304 is the last field, the size is rounded up to 16 bytes
+        assert_eq!(CanvasShaderUniforms::default().as_std140_bytes().len(), 320);
+    }
+
+    #[test]
+    fn test_std140_offsets() {
+        // distinct non-zero values, so that a field written at the wrong offset or into padding is caught
+        let uniforms = CanvasShaderUniforms {
+            step_size: 0.01,
+            base_distance: 0.02,
+            opacity_threshold: 0.03,
+            ambient_intensity: 0.04,
+            diffuse_intensity: 0.05,
+            specular_intensity: 0.06,
+            shininess: 0.07,
+            min_opacity: 0.08,
+            silhouette_enhance: 0.09,
+            mask_threshold: 0.10,
+            render_mode: 11,
+            precomputed_gradient: 12,
+            occupancy_block_size: 0.13,
+            clip_plane_enabled: 14,
+            clip_plane: Vector4::new(0.15, 0.16, 0.17, 0.18),
+            roi_min: Vector3::new(0.19, 0.20, 0.21),
+            roi_max: Vector3::new(0.22, 0.23, 0.24),
+            window_center: 0.25,
+            window_width: 0.26,
+            light_direction: Vector3::new(0.27, 0.28, 0.29),
+            headlight: 30,
+            rgba_volume: 31,
+            lod_per_distance: 0.32,
+            adaptive_min_step: 0.33,
+            adaptive_max_step: 0.34,
+            transfer_function_2d: 35,
+            max_gradient_magnitude: 0.36,
+            interpolation: 37,
+            jitter: 38,
+            frame_index: 39,
+            pick_opacity: 0.40,
+            view_depth_plane: Vector4::new(0.41, 0.42, 0.43, 0.44),
+            ray_near: 0.45,
+            ray_far: 0.46,
+            background_mode: 47,
+            background_top: Vector4::new(0.48, 0.49, 0.50, 0.51),
+            background_bottom: Vector4::new(0.52, 0.53, 0.54, 0.55),
+            max_steps: 56,
+            exposure: 0.57,
+            tonemap_mode: 58,
+            label_overlay: 59,
+            preintegrated: 60,
+            scalar_gamma: 0.61,
+            scalar_log_scale: 62,
+            ao_strength: 0.63,
+            ao_radius: 0.64,
+            face_buffer_view: 65,
+            opacity_scale: 0.66,
+            voxel_spacing: Vector3::new(0.67, 0.68, 0.69),
+        };
+        let f = |v: f32| v.to_ne_bytes().to_vec();
+        let u = |v: u32| v.to_ne_bytes().to_vec();
+        let v3 = |v: Vector3<f32>| bytemuck::cast::<[f32; 3], [u8; 12]>(v.into()).to_vec();
+        let v4 = |v: Vector4<f32>| bytemuck::cast::<[f32; 4], [u8; 16]>(v.into()).to_vec();
+        // offsets of the members of FragmentUniforms in canvas_shader.wgsl, vec3 and vec4 are aligned to 16 bytes
+        let expected = [
+            ("step_size", 0, f(uniforms.step_size)),
+            ("base_distance", 4, f(uniforms.base_distance)),
+            ("opacity_threshold", 8, f(uniforms.opacity_threshold)),
+            ("ambient", 12, f(uniforms.ambient_intensity)),
+            ("diffuse", 16, f(uniforms.diffuse_intensity)),
+            ("specular", 20, f(uniforms.specular_intensity)),
+            ("shininess", 24, f(uniforms.shininess)),
+            ("min_opacity", 28, f(uniforms.min_opacity)),
+            ("silhouette_enhance", 32, f(uniforms.silhouette_enhance)),
+            ("mask_threshold", 36, f(uniforms.mask_threshold)),
+            ("render_mode", 40, u(uniforms.render_mode)),
+            ("precomputed_gradient", 44, u(uniforms.precomputed_gradient)),
+            ("occupancy_block_size", 48, f(uniforms.occupancy_block_size)),
+            ("clip_plane_enabled", 52, u(uniforms.clip_plane_enabled)),
+            ("clip_plane", 64, v4(uniforms.clip_plane)),
+            ("roi_min", 80, v3(uniforms.roi_min)),
+            ("roi_max", 96, v3(uniforms.roi_max)),
+            ("window_center", 108, f(uniforms.window_center)),
+            ("window_width", 112, f(uniforms.window_width)),
+            ("light_direction", 128, v3(uniforms.light_direction)),
+            ("headlight", 140, u(uniforms.headlight)),
+            ("rgba_volume", 144, u(uniforms.rgba_volume)),
+            ("lod_per_distance", 148, f(uniforms.lod_per_distance)),
+            ("adaptive_min_step", 152, f(uniforms.adaptive_min_step)),
+            ("adaptive_max_step", 156, f(uniforms.adaptive_max_step)),
+            (
+                "transfer_function_2d",
+                160,
+                u(uniforms.transfer_function_2d),
+            ),
+            (
+                "max_gradient_magnitude",
+                164,
+                f(uniforms.max_gradient_magnitude),
+            ),
+            ("interpolation", 168, u(uniforms.interpolation)),
+            ("jitter", 172, u(uniforms.jitter)),
+            ("frame_index", 176, u(uniforms.frame_index)),
+            ("pick_opacity", 180, f(uniforms.pick_opacity)),
+            ("view_depth_plane", 192, v4(uniforms.view_depth_plane)),
+            ("ray_near", 208, f(uniforms.ray_near)),
+            ("ray_far", 212, f(uniforms.ray_far)),
+            ("background_mode", 216, u(uniforms.background_mode)),
+            ("background_top", 224, v4(uniforms.background_top)),
+            ("background_bottom", 240, v4(uniforms.background_bottom)),
+            ("max_steps", 256, u(uniforms.max_steps)),
+            ("exposure", 260, f(uniforms.exposure)),
+            ("tonemap_mode", 264, u(uniforms.tonemap_mode)),
+            ("label_overlay", 268, u(uniforms.label_overlay)),
+            ("preintegrated", 272, u(uniforms.preintegrated)),
+            ("scalar_gamma", 276, f(uniforms.scalar_gamma)),
+            ("scalar_log_scale", 280, u(uniforms.scalar_log_scale)),
+            ("ao_strength", 284, f(uniforms.ao_strength)),
+            ("ao_radius", 288, f(uniforms.ao_radius)),
+            ("face_buffer_view", 292, u(uniforms.face_buffer_view)),
+            ("opacity_scale", 296, f(uniforms.opacity_scale)),
+            ("voxel_spacing", 304, v3(uniforms.voxel_spacing)),
+        ];
+        let bytes = uniforms.as_std140_bytes();
+        let mut padding = vec![true; bytes.len()];
+        for (name, offset, value) in expected {
+            assert_eq!(&bytes[offset..offset + value.len()], &value[..], "{}", name);
+            padding[offset..offset + value.len()].fill(false);
+        }
+        // a field written into the padding between the members would be read as a different one by the shader
+        for (offset, _) in padding.iter().enumerate().filter(|(_, padding)| **padding) {
+            assert_eq!(bytes[offset], 0, "padding at offset {}", offset);
         }
     }
+
+    #[test]
+    fn test_view_depth_plane() {
+        let camera = Camera {
+            eye: (0.0, -2.0, 0.0).into(),
+            center: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut uniforms = CanvasShaderUniforms::default();
+        uniforms.update_view_depth_plane(&camera, Matrix4::from_nonuniform_scale(1.0, 2.0, 0.5));
+        let plane = uniforms.view_depth_plane;
+        let depth = |p: Vector3<f32>| plane.truncate().dot(p) + plane.w;
+        // the volume spans y in [-1, 1] in world units, its front face is 1 unit from the eye
+        assert_eq!(depth(Vector3::new(0.5, 0.0, 0.5)), 1.0);
+        assert_eq!(depth(Vector3::new(0.0, 1.0, 1.0)), 3.0);
+    }
 }
@@ -0,0 +1,174 @@
+use egui::{Context, Slider, Ui, ViewportId};
+use egui_wgpu::{Renderer, ScreenDescriptor};
+use wgpu::*;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use crate::data::CanvasShaderUniforms;
+use crate::viewer::Viewer;
+
+///
+/// egui overlay with sliders for the canvas shader uniforms
+///
+/// Forward window events with [`ControlPanel::on_window_event`] and call [`ControlPanel::render`]
+/// after [`Viewer::render`] with the same encoder and surface texture view.
+///
+pub struct ControlPanel {
+    context: Context,
+    state: egui_winit::State,
+    renderer: Renderer,
+}
+
+impl ControlPanel {
+    pub fn new(device: &Device, target_format: &TextureFormat, window: &Window) -> Self {
+        let context = Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        // the panel is drawn directly into the resolved surface texture, so it is not multisampled
+        let renderer = Renderer::new(device, *target_format, None, 1, false);
+        Self {
+            context,
+            state,
+            renderer,
+        }
+    }
+
+    /// Returns true if the panel consumed the event, e.g. a click on a slider, so the camera should ignore it.
+    /// Requests a redraw if the panel changes, e.g. when hovering a slider
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        let response = self.state.on_window_event(window, event);
+        // egui asks to repaint on every redraw, which would never let the event loop rest
+        if response.repaint && !matches!(event, WindowEvent::RedrawRequested) {
+            window.request_redraw();
+        }
+        response.consumed
+    }
+
+    /// Runs the panel, applies changed uniforms to the canvas pass of `viewer` and draws the panel over `render_into_view`.
+    /// Returns true if the panel needs another frame right away
+    pub fn render(
+        &mut self,
+        window: &Window,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        render_into_view: &TextureView,
+        viewer: &mut Viewer,
+    ) -> bool {
+        let raw_input = self.state.take_egui_input(window);
+        let mut uniforms = *viewer.canvas_pass().uniforms();
+        let mut changed = false;
+        let full_output = self.context.run(raw_input, |ctx| {
+            egui::Window::new("Rendering").show(ctx, |ui| {
+                changed = uniform_sliders(ui, &mut uniforms);
+            });
+        });
+        if changed {
+            viewer.canvas_pass_mut().set_uniforms(&uniforms, queue);
+        }
+        self.state
+            .handle_platform_output(window, full_output.platform_output);
+
+        let paint_jobs = self
+            .context
+            .tessellate(full_output.shapes, full_output.pixels_per_point);
+        let size = window.inner_size();
+        let screen_descriptor = ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: full_output.pixels_per_point,
+        };
+        for (id, image_delta) in &full_output.textures_delta.set {
+            self.renderer
+                .update_texture(device, queue, *id, image_delta);
+        }
+        // only paint callbacks record their own command buffers, the panel has none but they must run first
+        let callback_buffers =
+            self.renderer
+                .update_buffers(device, queue, encoder, &paint_jobs, &screen_descriptor);
+        queue.submit(callback_buffers);
+        {
+            let mut render_pass = encoder
+                .begin_render_pass(&RenderPassDescriptor {
+                    label: Some("Control Panel Render Pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: render_into_view,
+                        resolve_target: None,
+                        ops: Operations {
+                            // the panel is drawn over the rendered volume
+                            load: LoadOp::Load,
+                            store: StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                })
+                .forget_lifetime();
+            self.renderer
+                .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+        }
+        for id in &full_output.textures_delta.free {
+            self.renderer.free_texture(id);
+        }
+        full_output
+            .viewport_output
+            .get(&ViewportId::ROOT)
+            .is_some_and(|output| output.repaint_delay.is_zero())
+    }
+}
+
+/// Sliders for the ray marching and lighting uniforms, returns true if any of them changed
+fn uniform_sliders(ui: &mut Ui, uniforms: &mut CanvasShaderUniforms) -> bool {
+    let sliders = [
+        Slider::new(&mut uniforms.step_size, 0.0005..=0.02)
+            .logarithmic(true)
+            .text("step size"),
+        Slider::new(&mut uniforms.base_distance, 0.0005..=0.02)
+            .logarithmic(true)
+            .text("base distance"),
+        Slider::new(&mut uniforms.opacity_threshold, 0.0..=1.0).text("opacity threshold"),
+        Slider::new(&mut uniforms.opacity_scale, 0.1..=10.0)
+            .logarithmic(true)
+            .text("opacity scale"),
+        Slider::new(&mut uniforms.ambient_intensity, 0.0..=1.0).text("ambient"),
+        Slider::new(&mut uniforms.diffuse_intensity, 0.0..=1.0).text("diffuse"),
+        Slider::new(&mut uniforms.specular_intensity, 0.0..=1.0).text("specular"),
+        Slider::new(&mut uniforms.ao_strength, 0.0..=1.0).text("ambient occlusion"),
+        Slider::new(&mut uniforms.ao_radius, 0.005..=0.1)
+            .logarithmic(true)
+            .text("occlusion radius"),
+        Slider::new(&mut uniforms.shininess, 1.0..=128.0)
+            .logarithmic(true)
+            .text("shininess"),
+        Slider::new(&mut uniforms.exposure, 0.1..=10.0)
+            .logarithmic(true)
+            .text("exposure"),
+        Slider::new(&mut uniforms.scalar_gamma, 0.1..=10.0)
+            .logarithmic(true)
+            .text("scalar gamma"),
+    ];
+    let mut changed = false;
+    for slider in sliders {
+        changed |= ui.add(slider).changed();
+    }
+    let mut headlight = uniforms.headlight != 0;
+    if ui
+        .checkbox(&mut headlight, "headlight follows camera")
+        .changed()
+    {
+        uniforms.headlight = headlight as u32;
+        changed = true;
+    }
+    let mut jitter = uniforms.jitter != 0;
+    if ui.checkbox(&mut jitter, "jitter ray starts").changed() {
+        uniforms.jitter = jitter as u32;
+        changed = true;
+    }
+    changed
+}
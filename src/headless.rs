@@ -0,0 +1,71 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use futures::executor::block_on;
+use wgpu::*;
+
+use crate::rendering::Camera;
+use crate::utils::{save_frame_png, ExportAlpha};
+use crate::viewer::{RenderConfigs, Viewer};
+
+const SAMPLE_COUNT: u32 = 4;
+/// sRGB like the usual surface formats, so images look the same as in the window
+const OUTPUT_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+///
+/// Renders a volume into an image without a window or surface
+///
+/// The aspect ratio of `camera` is replaced by the one of `resolution`.
+/// Color in the returned image is premultiplied by alpha, see [`crate::utils::save_frame_png`].
+///
+pub fn render_to_image<P: AsRef<Path>>(
+    volume_path: P,
+    camera: &Camera,
+    resolution: (u32, u32),
+) -> Result<image::RgbaImage> {
+    let mut render_configs = RenderConfigs {
+        volume_path: volume_path.as_ref().to_path_buf(),
+        camera: *camera,
+        sample_count: NonZeroU32::new(SAMPLE_COUNT).unwrap(),
+        ..Default::default()
+    };
+    let instance = Instance::new(render_configs.instance_descriptor());
+    let adapter = block_on(instance.request_adapter(&RequestAdapterOptions {
+        power_preference: PowerPreference::default(),
+        force_fallback_adapter: false,
+        compatible_surface: None,
+    }))
+    .ok_or_else(|| anyhow!("No GPU adapter available for headless rendering"))?;
+    let (device, queue) = block_on(adapter.request_device(
+        &DeviceDescriptor {
+            label: Some("Headless device"),
+            // the zero border of RenderConfigs::default is optional
+            required_features: adapter.features()
+                & (Features::ADDRESS_MODE_CLAMP_TO_ZERO | Features::ADDRESS_MODE_CLAMP_TO_BORDER),
+            required_limits: Limits::default(),
+            memory_hints: MemoryHints::Performance,
+        },
+        None,
+    ))?;
+    render_configs.clamp_sample_count(&adapter, &device, &OUTPUT_FORMAT);
+    render_configs.clamp_gradients_on_gpu(&adapter);
+    let viewer = Viewer::new(&render_configs, &device, &queue, resolution, &OUTPUT_FORMAT)?;
+    viewer.capture(&device, &queue)
+}
+
+///
+/// Renders a volume like [`render_to_image`] and saves it as a PNG file
+///
+/// `alpha` selects whether the file stores straight or premultiplied color.
+///
+pub fn render_to_png<P: AsRef<Path>, Q: AsRef<Path>>(
+    volume_path: P,
+    camera: &Camera,
+    resolution: (u32, u32),
+    output_path: Q,
+    alpha: ExportAlpha,
+) -> Result<()> {
+    let image = render_to_image(volume_path, camera, resolution)?;
+    save_frame_png(&image, output_path.as_ref(), alpha)
+}
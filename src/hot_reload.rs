@@ -0,0 +1,92 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use wgpu::Device;
+
+use crate::viewer::Viewer;
+
+/// The WGSL sources of this crate, only present where it was built from source
+pub fn shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src")
+        .join("shaders")
+}
+
+///
+/// Watches a directory of WGSL files and rebuilds the pipelines of a [`Viewer`] when they change
+///
+/// Call [`ShaderWatcher::reload_all`] once the viewer is built, so that it runs the files on disk
+/// rather than the ones compiled into the binary, and [`ShaderWatcher::reload_changed`] before every frame.
+/// Shaders that do not compile are logged and the viewer keeps its previous pipelines.
+///
+pub struct ShaderWatcher {
+    shader_dir: PathBuf,
+    _watcher: RecommendedWatcher, // watching stops when it is dropped
+    changed_files: Receiver<PathBuf>,
+}
+
+impl ShaderWatcher {
+    /// `on_change` runs on the watcher thread after a file changed, e.g. to request a redraw
+    pub fn new(shader_dir: PathBuf, on_change: impl Fn() + Send + 'static) -> Result<Self> {
+        let (sender, changed_files) = channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    if path.extension() == Some(OsStr::new("wgsl")) {
+                        // fails only once the watcher is dropped
+                        let _ = sender.send(path);
+                    }
+                }
+                on_change();
+            })?;
+        watcher.watch(&shader_dir, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            shader_dir,
+            _watcher: watcher,
+            changed_files,
+        })
+    }
+
+    /// Loads every WGSL file of the watched directory into `viewer`
+    pub fn reload_all(&self, viewer: &mut Viewer, device: &Device) -> Result<()> {
+        for entry in std::fs::read_dir(&self.shader_dir)? {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("wgsl")) {
+                reload_file(&path, viewer, device);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the files changed since the last call into `viewer`, returns true if there were any
+    pub fn reload_changed(&self, viewer: &mut Viewer, device: &Device) -> bool {
+        let mut changed: Vec<PathBuf> = self.changed_files.try_iter().collect();
+        // editors often save a file in several writes
+        changed.sort();
+        changed.dedup();
+        for path in &changed {
+            reload_file(path, viewer, device);
+        }
+        !changed.is_empty()
+    }
+}
+
+fn reload_file(path: &Path, viewer: &mut Viewer, device: &Device) {
+    let file_name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+    let reloaded = std::fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|source| viewer.reload_shader(file_name, &source, device));
+    match reloaded {
+        Ok(()) => log::info!("reloaded {}", path.display()),
+        Err(e) => log::error!("failed to reload {}: {:?}", path.display(), e),
+    }
+}
@@ -1,5 +1,18 @@
 pub mod data;
 pub mod geometries;
+#[cfg(feature = "egui")]
+pub mod gui;
+pub mod headless;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
 pub mod rendering;
 pub mod shading;
+#[cfg(test)]
+mod test_utils;
+pub mod timing;
 pub mod utils;
+pub mod viewer;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+pub use viewer::{RenderConfigs, Viewer, VolumeData};
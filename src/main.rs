@@ -1,59 +1,137 @@
 use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use cgmath::Matrix4;
+use cgmath::{MetricSpace, Point3};
 use futures::executor::block_on;
-use half::f16;
-use rayon::prelude::*;
-use wgpu::{CompositeAlphaMode, Extent3d, MemoryHints, SurfaceConfiguration, TextureFormat, TextureUsages, TextureViewDescriptor, TextureViewDimension};
+use wgpu::{Color, CompositeAlphaMode, DeviceLostReason, MemoryHints, PresentMode, SurfaceConfiguration, TextureUsages, TextureViewDescriptor, TextureViewDimension};
 use winit::{
     event::*,
     event_loop::EventLoop,
     window::Window,
 };
 use winit::application::ApplicationHandler;
-use winit::dpi::PhysicalSize;
-use winit::event_loop::{ActiveEventLoop, ControlFlow};
-use winit::keyboard::KeyCode;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoopProxy};
+use winit::keyboard::{KeyCode, ModifiersState};
 use winit::keyboard::PhysicalKey::Code;
 use winit::window::WindowId;
 
-use wenderer::rendering::{Camera, CanvasPass, D3Pass, RenderPass};
-use wenderer::shading::Tex;
-use wenderer::utils::{CameraController, load_volume_data};
+#[cfg(feature = "egui")]
+use wenderer::gui::ControlPanel;
+#[cfg(feature = "hot-reload")]
+use wenderer::hot_reload::{shader_dir, ShaderWatcher};
+use wenderer::timing::FrameTimer;
+use wenderer::utils::{CameraController, ExportAlpha, RawSampleType, choose_surface_format, interpolate_transfer_functions, save_frame_png};
+use wenderer::rendering::{Camera, SliceAxis};
+use wenderer::{RenderConfigs, Viewer, VolumeData};
 
-/// This is 1 because render buffer textures for front-face and back-face rendering is the resolved target
-/// not the multisampled target
-const FACE_RENDER_BUFFER_SAMPLE_COUNT: u32 = 1;
+/// Number of frames averaged for the frame time in the window title
+const FRAME_TIMER_WINDOW: usize = 60;
+/// The window title is not updated more often than this
+const TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+/// Distance between the eyes in stereo mode, the volume spans about 1
+const STEREO_EYE_SEPARATION: f32 = 0.06;
+/// Number of frames of the 360° turntable animation, 4 seconds at 30 fps
+const TURNTABLE_FRAMES: u32 = 120;
+/// Duration of fading from one transfer function preset to the next
+const TRANSFER_FUNCTION_FADE_DURATION: Duration = Duration::from_secs(3);
+const USAGE: &str = "\
+Usage: wenderer [OPTIONS]
 
-struct RenderConfigs {
-    sample_count: NonZeroU32,
+Options:
+  --volume <PATH>     raw volume file [default: ./data/stagbeetle277x277x164.dat]
+  --dims <XxYxZ>      dimensions of a headerless volume, e.g. 256x256x128
+  --sample-type <TYPE>
+                      u8, u16 or f32 samples of a headerless volume, picks the texture format [default: u16]
+  --axes <ORDER>      file axes along the x, y and z axes of the volume, e.g. zyx to transpose [default: xyz]
+  --flip <AXES>       axes of the volume to mirror, e.g. z or xy
+  --downsample <N>    average blocks of NxNxN voxels on load, e.g. 2 for half resolution on GPUs with little memory
+  --size <WxH>        window size in pixels [default: 1000x1000]
+  --samples <COUNT>   MSAA sample count, lowered to what the device supports [default: 4]
+  --present-mode <MODE>
+                      fifo (vsync), mailbox or immediate, falls back to fifo if unsupported [default: fifo]
+  --export-alpha <ALPHA>
+                      straight or premultiplied alpha in saved screenshots [default: straight]
+  --gpu-debug         enable the wgpu validation layers and debug labels, e.g. for RenderDoc
+  --continuous        render frames continuously instead of only when something changed, e.g. for benchmarking
+  -h, --help          print this help";
+
+/// The next (`forward`) or previous raw volume file in the directory of `path`, wrapping around
+fn neighbouring_volume_file(path: &Path, forward: bool) -> Option<PathBuf> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let is_raw_volume = |file: &PathBuf| {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        name.ends_with(".dat") || name.ends_with(".dat.gz")
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(is_raw_volume)
+        .collect();
+    files.sort();
+    let count = files.len();
+    let next = match files.iter().position(|file| file.file_name() == path.file_name()) {
+        Some(index) if forward => (index + 1) % count,
+        Some(index) => (index + count - 1) % count,
+        None => 0,
+    };
+    files.into_iter().nth(next)
+}
+
+/// Index of the axis named `x`, `y` or `z`
+fn axis_index(axis: char) -> Option<usize> {
+    "xyz".find(axis.to_ascii_lowercase())
+}
+
+/// Maps the digit keys 1 to 9 to bookmark slot indices
+fn bookmark_slot(key: KeyCode) -> Option<usize> {
+    let slot = match key {
+        KeyCode::Digit1 => 0,
+        KeyCode::Digit2 => 1,
+        KeyCode::Digit3 => 2,
+        KeyCode::Digit4 => 3,
+        KeyCode::Digit5 => 4,
+        KeyCode::Digit6 => 5,
+        KeyCode::Digit7 => 6,
+        KeyCode::Digit8 => 7,
+        KeyCode::Digit9 => 8,
+        _ => return None,
+    };
+    Some(slot)
 }
 
+/// The window with its surface driving a [`Viewer`]
 struct RenderState {
     window: Arc<Window>,
     surface: wgpu::Surface<'static>,
-    surface_configs: SurfaceConfiguration,
+    surface_configs: SurfaceConfiguration, // its only view format is the sRGB format the viewer renders into
+    present_modes: Vec<PresentMode>, // supported by the surface, for toggling vsync
     surface_view_desc: TextureViewDescriptor<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     size: PhysicalSize<u32>,
-    camera: Camera,
     camera_controller: CameraController,
-    cube_scaling: Matrix4<f32>,
-    front_face_pass: D3Pass,
-    front_face_render_buffer: Tex,
-    back_face_pass: D3Pass,
-    back_face_render_buffer: Tex,
-    canvas_pass: CanvasPass,
+    viewer: Option<Viewer>, // None while the volume is loading
+    /// set by the device lost callback, the event loop then recreates the render state
+    device_lost: Arc<AtomicBool>,
+    #[cfg(feature = "egui")]
+    control_panel: ControlPanel,
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<ShaderWatcher>,
 }
 
 impl RenderState {
-    async fn new(window: Arc<Window>, sample_count: NonZeroU32) -> Self {
+    /// Creates the device and surface, the viewer follows once the volume is loaded, see [`App::user_event`]
+    async fn new(window: Arc<Window>, render_configs: &mut RenderConfigs) -> Self {
+        // the window may be minimized when the device is recreated, it is resized to its real size once restored
         let size = window.inner_size();
+        let size = PhysicalSize::new(size.width.max(1), size.height.max(1));
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::default();
+        let instance = wgpu::Instance::new(render_configs.instance_descriptor());
         let surface = instance.create_surface(window.clone()).expect("Failed to create surface");
         // need adapter to create the device and queue
         let adapter = instance
@@ -67,8 +145,14 @@ impl RenderState {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::empty(), //The device you have limits the features you can use
+                    label: Some("Wenderer Device"),
+                    // timestamp queries for measuring the canvas pass, the higher precision volume formats
+                    // and the volume border are optional
+                    required_features: adapter.features() & (wgpu::Features::TIMESTAMP_QUERY
+                        | wgpu::Features::TEXTURE_FORMAT_16BIT_NORM
+                        | wgpu::Features::FLOAT32_FILTERABLE
+                        | wgpu::Features::ADDRESS_MODE_CLAMP_TO_ZERO
+                        | wgpu::Features::ADDRESS_MODE_CLAMP_TO_BORDER), //The device you have limits the features you can use
                     required_limits: wgpu::Limits::default(), //The limits field describes the limit of certain types of resource we can create
                     memory_hints: MemoryHints::Performance,
                 },
@@ -76,21 +160,38 @@ impl RenderState {
             )
             .await
             .unwrap();
-        let preferred_format = surface.get_capabilities(&adapter).formats[0];
+        let device_lost = Arc::new(AtomicBool::new(false));
+        {
+            let device_lost = device_lost.clone();
+            let window = window.clone();
+            device.set_device_lost_callback(move |reason, message| {
+                // dropping the device when recreating it reports a loss as well
+                if matches!(reason, DeviceLostReason::Dropped | DeviceLostReason::ReplacedCallback) {
+                    return;
+                }
+                eprintln!("Device lost ({:?}): {}", reason, message);
+                device_lost.store(true, Ordering::Release);
+                window.request_redraw();
+            });
+        }
+        // the passes output linear color, which an sRGB view of the surface encodes
+        let surface_capabilities = surface.get_capabilities(&adapter);
+        let (surface_format, render_format) = choose_surface_format(&surface_capabilities.formats);
+        render_configs.clamp_present_mode(&surface_capabilities.present_modes);
         let surface_configs = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
-            format: preferred_format,
+            format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: render_configs.present_mode,
             desired_maximum_frame_latency: 2, // 2 is the default value
             alpha_mode: CompositeAlphaMode::Auto,
-            view_formats: vec![preferred_format],
+            view_formats: vec![render_format],
         };
         surface.configure(&device, &surface_configs);
         let surface_view_desc = TextureViewDescriptor {
             label: Some("Render Texture View"),
-            format: Some(surface_configs.format),
+            format: Some(render_format),
             dimension: Some(TextureViewDimension::D2),
             aspect: Default::default(),
             base_mip_level: 0,
@@ -98,189 +199,225 @@ impl RenderState {
             base_array_layer: 0,
             array_layer_count: None,
         };
-        // rendering configurations
-        let camera = Camera {
-            eye: (0.0, -2.5, 1.0).into(),
-            center: (0.0, 0.0, 0.0).into(),
-            up: cgmath::Vector3::unit_z(),
-            aspect: (size.width as f32) / (size.height as f32),
-            fovy: 45.0,
-            znear: 0.1,
-            zfar: 100.0,
+        let mut camera_controller = CameraController::new(0.2);
+        camera_controller.resize(size.width, size.height);
+        // all passes are built with this sample count, so an unsupported one has to be caught here
+        render_configs.clamp_sample_count(&adapter, &device, &render_format);
+        render_configs.clamp_gradients_on_gpu(&adapter);
+        #[cfg(feature = "egui")]
+        let control_panel = ControlPanel::new(&device, &render_format, &window);
+        #[cfg(feature = "hot-reload")]
+        let shader_watcher = {
+            let window = window.clone();
+            ShaderWatcher::new(shader_dir(), move || window.request_redraw())
+                .map_err(|e| eprintln!("Failed to watch the shaders: {:?}", e))
+                .ok()
         };
-        // load volume into textures
-        let ((x, y, z), data, _uint_data) = load_volume_data("./data/stagbeetle277x277x164.dat");
-        let data_f16: Vec<f16> = data.into_par_iter().map(f16::from_f32).collect();
-        let extent = Extent3d {
-            width: x as u32,
-            height: y as u32,
-            depth_or_array_layers: z as u32,
-        };
-        // prepare volume cube scaling for correct shape
-        let mut dims = vec![x, y, z];
-        dims.sort();
-        let mid_val = *dims.get(1).unwrap() as f32;
-        let volume_texture =
-            Tex::create_3d_texture_red_f16(&extent, &data_f16, &device, &queue, "Volume");
-        let cube_scaling = Matrix4::from_nonuniform_scale(
-            x as f32 / mid_val,
-            y as f32 / mid_val,
-            z as f32 / mid_val,
-        );
-
-        // prepare front-face and back-face passes
-        let face_buffer_format = TextureFormat::Rgba16Float; // filterable format with highest precision
-        let front_face_render_buffer = Tex::create_render_buffer(
-            (size.width, size.height),
-            &device,
-            Some("Front face render buffer texture"),
-            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
-            &face_buffer_format,
-        );
-        let front_face_pass = D3Pass::new(
-            &device,
-            size.width,
-            size.height,
-            &front_face_render_buffer.format,
-            true,
-            &camera,
-            sample_count.clone(),
-            cube_scaling.clone(),
-        );
-        let back_face_render_buffer = Tex::create_render_buffer(
-            (size.width, size.height),
-            &device,
-            Some("Back face render buffer texture"),
-            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
-            &face_buffer_format,
-        );
-        let back_face_pass = D3Pass::new(
-            &device,
-            size.width,
-            size.height,
-            &back_face_render_buffer.format,
-            false,
-            &camera,
-            sample_count.clone(),
-            cube_scaling.clone(),
-        );
-        let canvas_pass = CanvasPass::new(
-            &front_face_render_buffer,
-            &back_face_render_buffer,
-            &volume_texture,
-            &device,
-            &queue,
-            (size.width, size.height),
-            &preferred_format,
-            sample_count,
-        );
         Self {
             window,
             surface,
             surface_configs,
+            present_modes: surface_capabilities.present_modes,
             surface_view_desc,
             device,
             queue,
             size,
-            camera,
-            camera_controller: CameraController::new(0.2),
-            cube_scaling,
-            front_face_pass,
-            front_face_render_buffer,
-            back_face_pass,
-            back_face_render_buffer,
-            canvas_pass,
+            camera_controller,
+            viewer: None,
+            device_lost,
+            #[cfg(feature = "egui")]
+            control_panel,
+            #[cfg(feature = "hot-reload")]
+            shader_watcher,
         }
     }
 }
 
+/// Morphs the transfer function of the canvas pass from one to another over time
+struct TransferFunctionFade {
+    from: Vec<cgmath::Vector4<u8>>,
+    to: Vec<cgmath::Vector4<u8>>,
+    start_time: Instant,
+    duration: Duration,
+}
+
+impl TransferFunctionFade {
+    /// Uploads the transfer function of the current time, returns false once the fade is finished
+    fn step(&self, viewer: &mut Viewer, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let t = (self.start_time.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t); // smoothstep easing
+        let transfer_function = interpolate_transfer_functions(&self.from, &self.to, t);
+        viewer.canvas_pass_mut().update_transfer_function(&transfer_function, device, queue);
+        t < 1.0
+    }
+}
+
 struct App {
     render_configs: RenderConfigs,
     render_state: Option<RenderState>,
+    modifiers: ModifiersState,
     window_size: PhysicalSize<u32>,
     title: String,
+    /// save the next rendered frame to `capture_path`
+    capture_next_frame: bool,
+    capture_path: PathBuf,
+    /// wall-clock time of update() and render()
+    frame_timer: FrameTimer,
+    last_title_update: Instant,
+    /// hands the volume loaded on a background thread to the event loop
+    event_loop_proxy: EventLoopProxy<anyhow::Result<VolumeData>>,
+    volume_loading: bool,
+    cursor_position: PhysicalPosition<f64>,
+    /// the last picked position, the next pick measures the distance to it
+    last_pick: Option<Point3<f32>>,
+    /// camera of the viewer lost with its device, applied to the viewer built on the new device
+    restored_camera: Option<Camera>,
+    transfer_function_fade: Option<TransferFunctionFade>,
+    /// redraw every frame instead of only on input and while animating, which keeps the GPU busy
+    continuous_rendering: bool,
 }
 
 impl App {
     // need async because we need to await some struct creation here
     fn new(render_configs: RenderConfigs,
            window_size: PhysicalSize<u32>,
-           title: String) -> Self {
+           title: String,
+           event_loop_proxy: EventLoopProxy<anyhow::Result<VolumeData>>,
+           continuous_rendering: bool) -> Self {
         Self {
             render_configs,
             render_state: None,
+            modifiers: ModifiersState::empty(),
             window_size,
             title,
+            capture_next_frame: false,
+            capture_path: PathBuf::from("screenshot.png"),
+            frame_timer: FrameTimer::new(FRAME_TIMER_WINDOW),
+            last_title_update: Instant::now(),
+            event_loop_proxy,
+            volume_loading: false,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            last_pick: None,
+            restored_camera: None,
+            transfer_function_fade: None,
+            continuous_rendering,
         }
     }
 
+    /// Loads `render_configs.volume_path` on another thread, parsing large volumes takes seconds.
+    /// The volume arrives in [`App::user_event`]
+    fn load_volume_in_background(&mut self) {
+        self.volume_loading = true;
+        let render_configs = self.render_configs.clone();
+        let event_loop_proxy = self.event_loop_proxy.clone();
+        thread::spawn(move || {
+            // fails only if the event loop is gone already
+            let _ = event_loop_proxy.send_event(VolumeData::load(&render_configs));
+        });
+    }
+
+    /// Replaces a lost device and its surface with new ones for the same window.
+    /// All GPU resources went with the device, so the volume is loaded again and the viewer rebuilt in [`App::user_event`]
+    fn recreate_render_state(&mut self) {
+        let lost = self.render_state.take().unwrap();
+        self.restored_camera = lost.viewer.as_ref().map(|viewer| *viewer.camera());
+        let window = lost.window.clone();
+        // the old surface has to be released before the window gets a new one
+        drop(lost);
+        self.render_state = Some(block_on(RenderState::new(window.clone(), &mut self.render_configs)));
+        // a volume that is still loading arrives for the new device
+        if !self.volume_loading {
+            self.load_volume_in_background();
+        }
+        window.request_redraw();
+    }
+
     // If we want to support resizing in our application, we're going to need to recreate the swap_chain everytime the window's size changes.
     // That's the reason we stored the physical size and the sc_desc used to create the swap chain.
     fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // minimized windows report a zero size, which can neither configure the surface nor give an aspect ratio
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
         let rs = self.render_state.as_mut().unwrap();
         rs.size = new_size;
         rs.surface_configs.width = new_size.width;
         rs.surface_configs.height = new_size.height;
-
-        rs.camera.aspect = rs.size.width as f32 / rs.size.height as f32;
         rs.surface.configure(&rs.device, &rs.surface_configs);
-        rs.front_face_pass
-            .resize(&rs.device, rs.size.width, rs.size.height);
-        rs.back_face_pass
-            .resize(&rs.device, rs.size.width, rs.size.height);
-        rs.front_face_pass.update_model_view_proj_uniform(
-            rs.cube_scaling.clone(),
-            &rs.camera,
-            &rs.queue,
-        );
-        rs.back_face_pass.update_model_view_proj_uniform(
-            rs.cube_scaling.clone(),
-            &rs.camera,
-            &rs.queue,
-        );
-        rs.canvas_pass
-            .resize(&rs.device, rs.size.width, rs.size.height);
+        rs.camera_controller.resize(new_size.width, new_size.height);
+        if let Some(viewer) = rs.viewer.as_mut() {
+            viewer.resize(&rs.device, &rs.queue, new_size.width, new_size.height);
+        }
+        // frames are skipped while minimized, resume drawing once restored
+        rs.window.request_redraw();
+    }
 
-        rs.front_face_render_buffer = Tex::create_render_buffer(
-            (rs.size.width, rs.size.height),
-            &rs.device,
-            Some("Front Face Render Buffer"),
-            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
-            &rs.front_face_render_buffer.format,
-        );
-        rs.back_face_render_buffer = Tex::create_render_buffer(
-            (rs.size.width, rs.size.height),
-            &rs.device,
-            Some("Back Face Render Buffer"),
-            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
-            &rs.back_face_render_buffer.format,
-        );
-        rs.canvas_pass.change_bound_face_textures(
-            &rs.device,
-            &rs.front_face_render_buffer,
-            &rs.back_face_render_buffer,
-        );
+    /// Switches between vsync and the uncapped present mode, the configured one if it is not `Fifo`
+    fn toggle_vsync(&mut self) {
+        let rs = self.render_state.as_mut().unwrap();
+        let present_mode = if rs.surface_configs.present_mode != PresentMode::Fifo {
+            PresentMode::Fifo
+        } else {
+            let Some(present_mode) = [self.render_configs.present_mode, PresentMode::Immediate, PresentMode::Mailbox]
+                .into_iter()
+                .find(|mode| *mode != PresentMode::Fifo && rs.present_modes.contains(mode)) else {
+                println!("The surface only supports vsync");
+                return;
+            };
+            present_mode
+        };
+        rs.surface_configs.present_mode = present_mode;
+        rs.surface.configure(&rs.device, &rs.surface_configs);
+        println!("Present mode: {:?}", present_mode);
+        rs.window.request_redraw();
     }
+
     // input() returns a bool to indicate whether an event has been fully processed.
     // If the method returns true, the main loop won't process the event any further.
-    fn input(&mut self, event: &KeyEvent) -> bool {
+    fn input(&mut self, event: &WindowEvent) -> bool {
         self.render_state.as_mut().unwrap().camera_controller.process_events(event)
     }
 
     fn update(&mut self) {
         let rs = self.render_state.as_mut().unwrap();
-        rs.camera_controller.update_camera(&mut rs.camera);
-        rs.front_face_pass.update_model_view_proj_uniform(
-            rs.cube_scaling.clone(),
-            &rs.camera,
-            &rs.queue,
-        );
-        rs.back_face_pass.update_model_view_proj_uniform(
-            rs.cube_scaling.clone(),
-            &rs.camera,
-            &rs.queue,
-        );
+        let Some(viewer) = rs.viewer.as_mut() else {
+            return;
+        };
+        let mut camera = *viewer.camera();
+        rs.camera_controller.update_camera(&mut camera, viewer.volume_center());
+        viewer.set_camera(camera);
+        #[cfg(feature = "hot-reload")]
+        if let Some(shader_watcher) = &rs.shader_watcher {
+            shader_watcher.reload_changed(viewer, &rs.device);
+        }
+        if let Some(fade) = &self.transfer_function_fade {
+            if !fade.step(viewer, &rs.device, &rs.queue) {
+                self.transfer_function_fade = None;
+            }
+        }
+        viewer.update(&rs.queue);
     }
+    /// Shows the average frame time and, if available, the GPU time of the canvas pass in the window title
+    fn update_title(&mut self) {
+        if self.last_title_update.elapsed() < TITLE_UPDATE_INTERVAL {
+            return;
+        }
+        self.last_title_update = Instant::now();
+        let rs = self.render_state.as_ref().unwrap();
+        let frame_time = self.frame_timer.average().as_secs_f64() * 1000.0;
+        let mut title = format!("{} | frame {:.2} ms ({:.0} fps)", self.title, frame_time, 1000.0 / frame_time);
+        if let Some(canvas_pass_time) = rs.viewer.as_ref().and_then(Viewer::canvas_pass_time) {
+            title += &format!(" | canvas pass {:.2} ms", canvas_pass_time.as_secs_f64() * 1000.0);
+        }
+        if let Some(accumulated_frames) = rs.viewer.as_ref().and_then(Viewer::accumulated_frames) {
+            title += &format!(" | {} frames averaged", accumulated_frames);
+        }
+        if self.volume_loading {
+            title += " | loading volume";
+        }
+        rs.window.set_title(&title);
+    }
+
     // We also need to create a CommandEncoder to create the actual commands to send to the gpu.
     // Most modern graphics frameworks expect commands to be stored in a command buffer before being sent to the gpu.
     // The encoder builds a command buffer that we can then send to the gpu.
@@ -294,62 +431,358 @@ impl App {
                 label: Some("Render Encoder"),
             });
 
-        render_state.front_face_pass
-            .render(&render_state.front_face_render_buffer.view, None, &mut encoder);
-        render_state.back_face_pass
-            .render(&render_state.back_face_render_buffer.view, None, &mut encoder);
-        render_state.canvas_pass.render(&frame_tex_view, None, &mut encoder);
+        let Some(viewer) = render_state.viewer.as_mut() else {
+            // placeholder until the volume is loaded
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Loading Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &frame_tex_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.render_configs.background_color),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_state.queue.submit(std::iter::once(encoder.finish()));
+            frame.present();
+            return Ok(());
+        };
+        viewer.render(&frame_tex_view, &mut encoder);
+        #[cfg(feature = "egui")]
+        let panel_needs_repaint = render_state.control_panel.render(&render_state.window,
+                                                                    &render_state.device,
+                                                                    &render_state.queue,
+                                                                    &mut encoder,
+                                                                    &frame_tex_view,
+                                                                    viewer);
         render_state.queue.submit(std::iter::once(encoder.finish()));
+        viewer.frame_submitted(&render_state.device);
         frame.present();
+        #[cfg(feature = "egui")]
+        if panel_needs_repaint {
+            render_state.window.request_redraw();
+        }
+        if self.capture_next_frame {
+            self.capture_next_frame = false;
+            let saved = viewer
+                .capture(&render_state.device, &render_state.queue)
+                .and_then(|image| save_frame_png(&image, &self.capture_path, self.render_configs.export_alpha));
+            match saved {
+                Ok(_) => println!("Saved screenshot to {}", self.capture_path.display()),
+                Err(e) => eprintln!("Failed to save screenshot: {:?}", e),
+            }
+        }
         Ok(())
     }
 }
 
-impl ApplicationHandler for App {
+impl ApplicationHandler<anyhow::Result<VolumeData>> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         println!("Resumed");
         let window_attributes = Window::default_attributes()
             .with_inner_size(self.window_size)
             .with_title(self.title.clone());
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
-        self.render_state = Some(block_on(RenderState::new(window.clone(), self.render_configs.sample_count)));
+        self.render_state = Some(block_on(RenderState::new(window.clone(), &mut self.render_configs)));
+        // the window shows the background until the volume is loaded
+        self.load_volume_in_background();
         // to trigger the first render
         window.request_redraw();
     }
 
+    /// The volume finished loading, builds the viewer or swaps the volume on the main thread
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, volume: anyhow::Result<VolumeData>) {
+        self.volume_loading = false;
+        let rs = self.render_state.as_mut().unwrap();
+        let volume = match volume {
+            Ok(volume) => volume,
+            Err(e) => {
+                eprintln!("Failed to load the volume: {:?}", e);
+                // the shown volume stays, without one there is nothing to show
+                if rs.viewer.is_none() {
+                    event_loop.exit();
+                }
+                return;
+            }
+        };
+        match rs.viewer.as_mut() {
+            Some(viewer) => {
+                // the shown volume stays if the new one does not fit
+                if let Err(e) = viewer.set_volume(volume, &rs.device, &rs.queue) {
+                    eprintln!("Volume too large for this GPU, try --downsample: {:?}", e);
+                    return;
+                }
+            }
+            None => {
+                let viewer = Viewer::from_volume(&self.render_configs,
+                                                 volume,
+                                                 &rs.device,
+                                                 &rs.queue,
+                                                 (rs.size.width, rs.size.height),
+                                                 &rs.surface_configs.view_formats[0]);
+                let mut viewer = match viewer {
+                    Ok(viewer) => viewer,
+                    Err(e) => {
+                        eprintln!("Volume too large for this GPU, try --downsample: {:?}", e);
+                        event_loop.exit();
+                        return;
+                    }
+                };
+                // run the shaders on disk, they may have been edited since the build
+                #[cfg(feature = "hot-reload")]
+                if let Some(shader_watcher) = &rs.shader_watcher {
+                    if let Err(e) = shader_watcher.reload_all(&mut viewer, &rs.device) {
+                        eprintln!("Failed to load the shaders: {:?}", e);
+                    }
+                }
+                if let Some(mut camera) = self.restored_camera.take() {
+                    // the window may have been resized while the device was gone
+                    camera.aspect = viewer.camera().aspect;
+                    viewer.set_camera(camera);
+                }
+                rs.viewer = Some(viewer);
+            }
+        }
+        println!("Volume: {}", self.render_configs.volume_path.display());
+        rs.window.request_redraw();
+    }
+
     fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
         let window = self.render_state.as_ref().unwrap().window.clone();
         if window.id() != window_id {
             return;
         }
+        #[cfg(feature = "egui")]
+        {
+            let rs = self.render_state.as_mut().unwrap();
+            if rs.control_panel.on_window_event(&window, &event) {
+                return;
+            }
+        }
+        // the camera controller consumes cursor moves, picking needs the position too
+        if let WindowEvent::CursorMoved { position, .. } = &event {
+            self.cursor_position = *position;
+        }
+        if self.input(&event) {
+            window.request_redraw();
+            return;
+        }
         match &event {
             WindowEvent::Resized(physical_size) => self.resize(*physical_size),
             WindowEvent::ScaleFactorChanged { .. } => {
                 self.resize(window.inner_size());
             }
             WindowEvent::CloseRequested => event_loop.exit(),
-            WindowEvent::KeyboardInput { event, .. } => {
-                if self.input(event) {
-                    window.request_redraw();
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            // pick a point on the volume and measure the distance to the previous one
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Right, .. } => {
+                let rs = self.render_state.as_ref().unwrap();
+                let Some(viewer) = rs.viewer.as_ref() else {
                     return;
+                };
+                let (x, y) = (self.cursor_position.x as u32, self.cursor_position.y as u32);
+                match viewer.pick(x, y, &rs.device, &rs.queue) {
+                    None => println!("Picked nothing"),
+                    Some(position) => {
+                        match self.last_pick {
+                            Some(last_pick) => println!("Picked {:?}, {:.4} from the previous pick",
+                                                        position,
+                                                        position.distance(last_pick)),
+                            None => println!("Picked {:?}", position),
+                        }
+                        self.last_pick = Some(position);
+                    }
                 }
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
                 if event.state.is_pressed() {
+                    if event.physical_key == Code(KeyCode::Escape) {
+                        event_loop.exit();
+                        return;
+                    }
+                    // unlike the other keys, vsync and the render mode do not wait for the volume
+                    if event.physical_key == Code(KeyCode::KeyF) {
+                        self.toggle_vsync();
+                        return;
+                    }
+                    if event.physical_key == Code(KeyCode::KeyC) {
+                        self.continuous_rendering = !self.continuous_rendering;
+                        println!("Rendering: {}", if self.continuous_rendering { "continuous" } else { "on demand" });
+                        window.request_redraw();
+                        return;
+                    }
+                    let rs = self.render_state.as_mut().unwrap();
+                    // the other keys act on the volume, which may still be loading
+                    let Some(viewer) = rs.viewer.as_mut() else {
+                        return;
+                    };
                     match event.physical_key {
-                        Code(KeyCode::Escape) => {
-                            event_loop.exit();
+                        // Ctrl + F12 exports a turntable animation instead of a single frame
+                        Code(KeyCode::F12) if self.modifiers.control_key() => {
+                            let output_dir = Path::new("turntable");
+                            match viewer.render_turntable(TURNTABLE_FRAMES, output_dir, &rs.device, &rs.queue) {
+                                Ok(_) => println!("Saved {} turntable frames to {}", TURNTABLE_FRAMES, output_dir.display()),
+                                Err(e) => eprintln!("Failed to save the turntable: {:?}", e),
+                            }
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::F12) => {
+                            self.capture_next_frame = true;
+                            window.request_redraw();
+                        }
+                        // switch between a dark and a white background, e.g. for figures
+                        Code(KeyCode::KeyB) => {
+                            let canvas_pass = viewer.canvas_pass_mut();
+                            let background_color = if canvas_pass.background_color() == Color::WHITE {
+                                self.render_configs.background_color
+                            } else {
+                                Color::WHITE
+                            };
+                            canvas_pass.set_background_color(background_color);
+                            window.request_redraw();
+                        }
+                        // Ctrl + T fades to the next preset instead of switching at once
+                        Code(KeyCode::KeyT) => {
+                            let from = viewer.canvas_pass().transfer_function().to_vec();
+                            let name = viewer.cycle_transfer_function_preset(&rs.device, &rs.queue);
+                            println!("Transfer function: {}", name);
+                            if self.modifiers.control_key() {
+                                // the first step of the fade uploads `from` again before the next frame is rendered
+                                self.transfer_function_fade = Some(TransferFunctionFade {
+                                    from,
+                                    to: viewer.canvas_pass().transfer_function().to_vec(),
+                                    start_time: Instant::now(),
+                                    duration: TRANSFER_FUNCTION_FADE_DURATION,
+                                });
+                            }
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::KeyM) => {
+                            let mode = viewer.canvas_pass().render_mode().next();
+                            viewer.canvas_pass_mut().set_render_mode(mode, &rs.queue);
+                            window.request_redraw();
+                        }
+                        // debug the ray entry and exit positions: volume -> front faces -> back faces
+                        Code(KeyCode::KeyE) => {
+                            let view = viewer.canvas_pass().face_buffer_view().next();
+                            viewer.canvas_pass_mut().set_face_buffer_view(view, &rs.queue);
+                            println!("Face buffer view: {:?}", view);
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::KeyI) => {
+                            let interpolation = viewer.canvas_pass().interpolation().next();
+                            viewer.canvas_pass_mut().set_interpolation(interpolation, &rs.queue);
+                            println!("Interpolation: {:?}", interpolation);
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::KeyJ) => {
+                            let enabled = !viewer.canvas_pass().jitter_enabled();
+                            viewer.canvas_pass_mut().set_jitter_enabled(enabled, &rs.queue);
+                            window.request_redraw();
+                        }
+                        // compare single sampling with the configured multisampling, rebuilding the pipelines takes a moment
+                        Code(KeyCode::KeyN) => {
+                            let sample_count = if viewer.sample_count().get() > 1 {
+                                NonZeroU32::MIN
+                            } else {
+                                self.render_configs.sample_count
+                            };
+                            viewer.set_sample_count(sample_count, &rs.device);
+                            println!("Samples per pixel: {}", sample_count);
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::KeyQ) => {
+                            let enabled = viewer.accumulated_frames().is_none();
+                            viewer.set_progressive_refinement(enabled, &rs.device, &rs.queue);
+                            window.request_redraw();
+                        }
+                        Code(KeyCode::KeyV) => {
+                            viewer.set_stereo(viewer.stereo().is_none(), STEREO_EYE_SEPARATION);
+                            window.request_redraw();
+                        }
+                        // slice view through the center: hidden -> sagittal -> coronal -> axial
+                        Code(KeyCode::KeyL) => {
+                            let axis = match viewer.slice_view() {
+                                None => Some(SliceAxis::Sagittal),
+                                Some((SliceAxis::Sagittal, _)) => Some(SliceAxis::Coronal),
+                                Some((SliceAxis::Coronal, _)) => Some(SliceAxis::Axial),
+                                Some((SliceAxis::Axial, _)) => None,
+                            };
+                            viewer.set_slice_view(axis.map(|axis| (axis, 0.5)), &rs.device, &rs.queue);
+                            window.request_redraw();
+                        }
+                        // bounding box: hidden -> on top -> only the front edges
+                        Code(KeyCode::KeyX) => {
+                            viewer.set_wireframe_mode(viewer.wireframe_mode().next());
+                            window.request_redraw();
+                        }
+                        // previous or next volume in the directory of the current one
+                        Code(KeyCode::PageUp) | Code(KeyCode::PageDown) if !self.volume_loading => {
+                            let forward = event.physical_key == Code(KeyCode::PageDown);
+                            if let Some(path) = neighbouring_volume_file(viewer.volume_path(), forward) {
+                                self.render_configs.volume_path = path;
+                                self.load_volume_in_background();
+                                window.request_redraw();
+                            }
+                        }
+                        // peel away the outer layers or bring them back
+                        Code(KeyCode::KeyP) | Code(KeyCode::KeyO) => {
+                            let peel = event.physical_key == Code(KeyCode::KeyP);
+                            viewer.start_peel_animation(peel);
+                            window.request_redraw();
+                        }
+                        // Ctrl + 1..9 bookmarks the camera, 1..9 flies back to it
+                        Code(key) if bookmark_slot(key).is_some() => {
+                            let slot = bookmark_slot(key).unwrap();
+                            if self.modifiers.control_key() {
+                                viewer.bookmark_camera(slot);
+                            } else if viewer.recall_camera(slot) {
+                                window.request_redraw();
+                            }
                         }
                         _ => {}
                     }
                 }
             }
             WindowEvent::RedrawRequested => {
+                if self.render_state.as_ref().unwrap().device_lost.load(Ordering::Acquire) {
+                    self.recreate_render_state();
+                    return;
+                }
+                // a minimized window has nothing to present, the resize on restore requests the next frame
+                let size = window.inner_size();
+                if size.width == 0 || size.height == 0 {
+                    return;
+                }
+                let frame_start = Instant::now();
                 self.update();
                 match self.render() {
-                    Ok(_) => {}
-                    // Recreate the swap_chain if lost
-                    Err(wgpu::SurfaceError::Lost) => self.resize(self.render_state.as_ref().unwrap().size),
+                    Ok(_) => {
+                        self.frame_timer.push(frame_start.elapsed());
+                        self.update_title();
+                        // keep rendering until the animation settles, otherwise the event loop waits for the next input
+                        if self.continuous_rendering
+                            || self.transfer_function_fade.is_some()
+                            || self.render_state.as_ref().unwrap().viewer.as_ref().is_some_and(Viewer::is_animating) {
+                            window.request_redraw();
+                        }
+                    }
+                    // reconfigure the surface for the current window size, e.g. after the display went to sleep, and try again
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        let size = window.inner_size();
+                        self.resize(size);
+                        // minimized windows cannot present, they are redrawn once restored
+                        if size.width > 0 && size.height > 0 {
+                            window.request_redraw();
+                        }
+                    }
+                    // the compositor did not hand out a texture in time, e.g. while the window is hidden, skip the frame
+                    Err(wgpu::SurfaceError::Timeout) => log::warn!("Timed out acquiring the surface texture, skipping the frame"),
                     Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
-                    Err(e) => eprintln!("Some unhandled error {:?}", e),
                 }
             }
             _ => {}
@@ -358,15 +791,107 @@ impl ApplicationHandler for App {
 }
 
 
+/// Reads the command line options described in [`USAGE`] on top of the default render configs
+fn parse_args(mut args: impl Iterator<Item=String>) -> Result<(RenderConfigs, PhysicalSize<u32>, bool), String> {
+    let mut render_configs = RenderConfigs::default();
+    let mut size = PhysicalSize::new(1000, 1000);
+    let mut continuous_rendering = false;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if arg == "--gpu-debug" {
+            render_configs.gpu_debug = true;
+            continue;
+        }
+        if arg == "--continuous" {
+            continuous_rendering = true;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", arg))?;
+        let invalid = || format!("invalid value for {}: {}", arg, value);
+        match arg.as_str() {
+            "--volume" => render_configs.volume_path = PathBuf::from(&value),
+            "--dims" => {
+                let [x, y, z] = parse_extent(&value).ok_or_else(invalid)?;
+                render_configs.volume_dims = Some((x, y, z));
+            }
+            "--sample-type" => {
+                render_configs.volume_sample_type = match value.as_str() {
+                    "u8" => RawSampleType::U8,
+                    "u16" => RawSampleType::U16,
+                    "f32" => RawSampleType::F32,
+                    _ => return Err(invalid()),
+                };
+            }
+            "--axes" => {
+                let axes: Vec<usize> = value.chars().map(axis_index).collect::<Option<_>>().ok_or_else(invalid)?;
+                let mut sorted_axes = axes.clone();
+                sorted_axes.sort();
+                if sorted_axes != [0, 1, 2] {
+                    return Err(invalid());
+                }
+                render_configs.orientation.axes = [axes[0], axes[1], axes[2]];
+            }
+            "--flip" => {
+                for axis in value.chars() {
+                    render_configs.orientation.flip[axis_index(axis).ok_or_else(invalid)?] = true;
+                }
+            }
+            "--downsample" => {
+                let factor = value.parse().ok().filter(|factor| *factor > 0).ok_or_else(invalid)?;
+                render_configs.downsample_factor = Some(factor);
+            }
+            "--size" => {
+                let [width, height] = parse_extent(&value).ok_or_else(invalid)?;
+                size = PhysicalSize::new(width as u32, height as u32);
+            }
+            "--samples" => {
+                render_configs.sample_count = value.parse().map_err(|_| invalid())?;
+            }
+            "--present-mode" => {
+                render_configs.present_mode = match value.as_str() {
+                    "fifo" => PresentMode::Fifo,
+                    "mailbox" => PresentMode::Mailbox,
+                    "immediate" => PresentMode::Immediate,
+                    _ => return Err(invalid()),
+                };
+            }
+            "--export-alpha" => {
+                render_configs.export_alpha = match value.as_str() {
+                    "straight" => ExportAlpha::Straight,
+                    "premultiplied" => ExportAlpha::Premultiplied,
+                    _ => return Err(invalid()),
+                };
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    Ok((render_configs, size, continuous_rendering))
+}
+
+/// Parses `N` positive integers separated by `x`, e.g. `1280x720`
+fn parse_extent<const N: usize>(value: &str) -> Option<[usize; N]> {
+    let parts: Vec<usize> = value.split('x')
+        .map(|part| part.parse().ok().filter(|n| *n > 0))
+        .collect::<Option<_>>()?;
+    parts.try_into().ok()
+}
+
 fn main() {
     env_logger::init();
-    let event_loop = EventLoop::new().unwrap();
+    let (render_configs, size, continuous_rendering) = parse_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("error: {}\n\n{}", error, USAGE);
+        std::process::exit(2);
+    });
+    let event_loop = EventLoop::<anyhow::Result<VolumeData>>::with_user_event().build().unwrap();
+    // sleep until the next event, redraws are only requested when something changed, see App::continuous_rendering
     event_loop.set_control_flow(ControlFlow::Wait);
-    let render_configs = RenderConfigs {
-        sample_count: NonZeroU32::new(4).unwrap(),
-    };
     let mut app = App::new(render_configs,
-                           PhysicalSize::new(1000, 1000),
-                           "WebGPU-based DVR".to_string());
+                           size,
+                           "WebGPU-based DVR".to_string(),
+                           event_loop.create_proxy(),
+                           continuous_rendering);
     event_loop.run_app(&mut app).expect("Failed to run app");
 }
@@ -1,13 +1,22 @@
-use cgmath::{perspective, Deg, Matrix4, Point3, Vector3};
+use anyhow::{anyhow, Result};
+use cgmath::{perspective, Deg, InnerSpace, Matrix3, Matrix4, Point3, Vector3};
+#[cfg(not(target_arch = "wasm32"))]
+use futures::executor::block_on;
+use half::f16;
 use wgpu::util::DeviceExt;
 use wgpu::*;
 
-use crate::data::{CanvasShaderUniforms, Uniforms};
+use crate::data::{CanvasShaderUniforms, SliceUniforms, Uniforms};
 use crate::geometries::{Mesh3, Rectangle};
 use crate::shading::Tex;
-use crate::utils::{create_cube_fbo, load_example_transfer_function};
+use crate::timing::GpuTimer;
+use crate::utils::{
+    compute_preintegration_table, create_cube_fbo, load_example_transfer_function,
+    max_opacity_over_gradients, OccupancyGrid,
+};
 use crevice::std140::AsStd140;
 use std::num::NonZeroU32;
+use std::sync::Arc;
 
 // The coordinate system in Wgpu is based on DirectX, and Metal's coordinate systems.
 // That means that in normalized device coordinates the x axis and y axis are in the range of -1.0 to +1.0, and the z axis is 0.0 to +1.0.
@@ -40,6 +49,7 @@ pub trait RenderPass {
     );
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Camera {
     pub eye: Point3<f32>,
     pub center: Point3<f32>,
@@ -50,48 +60,229 @@ pub struct Camera {
     pub zfar: f32,
 }
 
+/// Looks at the origin from the front and a little above, with z up, where the unit cube of the volume fills most of the view
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            eye: (0.0, -2.5, 1.0).into(),
+            center: (0.0, 0.0, 0.0).into(),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
 impl Camera {
+    /// Range of [`Camera::set_fovy`] in degrees
+    pub const MIN_FOVY: f32 = 10.0;
+    pub const MAX_FOVY: f32 = 90.0;
+
+    /// Camera at `eye` looking at `center` with the aspect of a `resolution` of (width, height) pixels,
+    /// the field of view and the clip planes are the ones of [`Camera::default`]
+    pub fn looking_at(
+        eye: Point3<f32>,
+        center: Point3<f32>,
+        up: Vector3<f32>,
+        resolution: (u32, u32),
+    ) -> Self {
+        Self {
+            eye,
+            center,
+            up,
+            aspect: resolution.0.max(1) as f32 / resolution.1.max(1) as f32,
+            ..Default::default()
+        }
+    }
+
+    /// Zooms optically by changing the vertical field of view, clamped to [`Camera::MIN_FOVY`, `Camera::MAX_FOVY`].
+    /// Unlike moving the eye, the perspective distortion stays the same
+    pub fn set_fovy(&mut self, degrees: f32) {
+        self.fovy = degrees.clamp(Self::MIN_FOVY, Self::MAX_FOVY);
+    }
+
+    /// World to eye space, the camera looks down -z
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_rh(self.eye, self.center, self.up)
+    }
+
+    /// Eye to clip space, x and y of the normalized device coordinates are in [-1, 1] with y up
+    pub fn projection_matrix(&self) -> Matrix4<f32> {
+        perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar)
+    }
+
     pub fn build_view_projection_matrix(&self, model_transformation: Matrix4<f32>) -> Matrix4<f32> {
-        let view = Matrix4::look_at_rh(self.eye, self.center, self.up);
-        let proj = perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        return proj * view * model_transformation;
+        self.projection_matrix() * self.view_matrix() * model_transformation
+    }
+
+    /// Left and right eye cameras for side-by-side stereo, each with half the aspect.
+    /// The eyes are shifted `eye_separation` apart along the camera's right axis and look in parallel
+    pub fn stereo_eyes(&self, eye_separation: f32) -> (Camera, Camera) {
+        let right = (self.center - self.eye).cross(self.up).normalize() * (eye_separation / 2.0);
+        let eye = |offset: Vector3<f32>| Camera {
+            eye: self.eye + offset,
+            center: self.center + offset,
+            aspect: self.aspect / 2.0,
+            ..*self
+        };
+        (eye(-right), eye(right))
+    }
+
+    /// Rotates the eye around the center about the up axis by `degrees`, counterclockwise seen from above
+    pub fn orbit(&self, degrees: f32) -> Camera {
+        let rotation = Matrix3::from_axis_angle(self.up.normalize(), Deg(degrees));
+        Camera {
+            eye: self.center + rotation * (self.eye - self.center),
+            ..*self
+        }
+    }
+
+    /// Linearly interpolates pose and field of view towards `target`, `t` in [0, 1]
+    pub fn interpolate(&self, target: &Camera, t: f32) -> Camera {
+        use cgmath::InnerSpace;
+        Camera {
+            eye: self.eye + (target.eye - self.eye) * t,
+            center: self.center + (target.center - self.center) * t,
+            up: (self.up + (target.up - self.up) * t).normalize(),
+            aspect: self.aspect + (target.aspect - self.aspect) * t,
+            fovy: self.fovy + (target.fovy - self.fovy) * t,
+            znear: self.znear + (target.znear - self.znear) * t,
+            zfar: self.zfar + (target.zfar - self.zfar) * t,
+        }
+    }
+}
+
+/// Runs `create` in a validation error scope, so that an invalid shader is reported instead of being fatal
+#[cfg(not(target_arch = "wasm32"))]
+fn catch_validation_error<T>(device: &Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(ErrorFilter::Validation);
+    let created = create();
+    match block_on(device.pop_error_scope()) {
+        None => Ok(created),
+        Some(error) => Err(anyhow!("{}", error)),
+    }
+}
+
+/// Browsers resolve error scopes asynchronously, which `block_on` cannot wait for on their only thread.
+/// Errors are logged to the console as uncaptured errors instead
+#[cfg(target_arch = "wasm32")]
+fn catch_validation_error<T>(_device: &Device, create: impl FnOnce() -> T) -> Result<T> {
+    Ok(create())
+}
+
+/// Creates a uniform buffer initialized with `uniforms` and its bind group
+fn create_uniform_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    uniforms: &Uniforms,
+    label: &str,
+) -> (Buffer, BindGroup) {
+    let buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+        label: Some(label),
+        contents: uniforms.as_std140().as_bytes(),
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        label: Some(label),
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    (buffer, bind_group)
+}
+
+/// Calls `draw` once per eye with the eye's uniforms bound to group 0.
+/// With one bind group it draws into the whole target, with two the eyes are placed side by side
+fn draw_per_eye(
+    render_pass: &mut wgpu::RenderPass,
+    target_size: Extent3d,
+    eye_bind_groups: &[&BindGroup],
+    mut draw: impl FnMut(&mut wgpu::RenderPass),
+) {
+    let eye_width = target_size.width as f32 / eye_bind_groups.len() as f32;
+    for (eye, bind_group) in eye_bind_groups.iter().enumerate() {
+        render_pass.set_viewport(
+            eye as f32 * eye_width,
+            0.0,
+            eye_width,
+            target_size.height as f32,
+            0.0,
+            1.0,
+        );
+        render_pass.set_bind_group(0, bind_group, &[]);
+        draw(render_pass);
+    }
+}
+
+/// Which faces of the proxy geometry a [`D3Pass`] rasterizes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FaceSelection {
+    /// the ray entry instead of the exit
+    pub render_front_face: bool,
+    /// usually [`D3Pass::default_cull_mode`], proxy geometries that are not closed
+    /// or not wound consistently may need `None` or the other face
+    pub cull_mode: Option<Face>,
+}
+
+impl FaceSelection {
+    /// The ray entry (`render_front_face`) or exit of the closed proxy cube
+    pub fn new(render_front_face: bool) -> Self {
+        Self {
+            render_front_face,
+            cull_mode: D3Pass::default_cull_mode(render_front_face),
+        }
     }
 }
 
 pub struct D3Pass {
     depth_texture: Tex,
     uniform_bind_group: BindGroup,
+    right_eye_uniform_bind_group: BindGroup,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     uniforms: Uniforms,
     uniform_buffer: Buffer,
+    right_eye_uniform_buffer: Buffer,
+    stereo: bool, // left and right eye side by side
     num_depth_indices: u32,
+    render_pipeline_layout: PipelineLayout,
     render_pipeline: RenderPipeline,
+    shader_source: String, // source of render_pipeline, kept for rebuilding it
+    target_format: TextureFormat,
+    faces: FaceSelection,
     depth_clear_op: LoadOp<f32>,
     multisample_buffer: Option<Tex>,
-    pub clear_color: (f64, f64, f64, f64),
+    clear_color: (f64, f64, f64, f64),
     cube: Mesh3,
     sample_count: u32,
 }
 
 impl D3Pass {
+    ///
+    /// Renders the ray entry or exit of the proxy cube into a face buffer, as selected by `faces`
+    ///
     pub fn new(
         device: &Device,
         render_width: u32,
         render_height: u32,
         target_format: &TextureFormat,
-        render_front_face: bool,
+        faces: FaceSelection,
         camera: &Camera,
         sample_cnt: NonZeroU32,
         cube_transformation: Matrix4<f32>,
     ) -> Self {
+        let name = Self::pass_name(faces.render_front_face);
         let sample_count = sample_cnt.get();
         let enable_multisample = sample_count > 1;
         let multisample_buffer = if enable_multisample {
             Some(Tex::create_render_buffer(
                 (render_width, render_height),
                 device,
-                Some("Multisample Buffer"),
+                Some(&format!("{} Multisample Buffer", name)),
                 sample_cnt.clone(),
                 target_format,
             ))
@@ -99,11 +290,7 @@ impl D3Pass {
             None
         };
         // configuring back and front face rendering
-        let face_render_config = if render_front_face {
-            (Face::Back, CompareFunction::Less, LoadOp::Clear(1.0))
-        } else {
-            (Face::Front, CompareFunction::Greater, LoadOp::Clear(0.0))
-        };
+        let face_render_config = Self::face_render_config(faces.render_front_face);
         let depth_clear_op = face_render_config.2;
         // create geometry
         let cube = create_cube_fbo();
@@ -113,21 +300,16 @@ impl D3Pass {
             render_width,
             render_height,
             sample_cnt,
-            "depth_texture",
+            &format!("{} Depth Texture", name),
         );
         // create uniforms
         let mut uniforms = Uniforms::new();
         uniforms.update_model_view_proj(camera, cube_transformation);
-        let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: uniforms.as_std140().as_bytes(),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        });
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 entries: &[BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::VERTEX,
+                    visibility: ShaderStages::VERTEX_FRAGMENT, // the entry fragment shader reads the eye position
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -135,43 +317,93 @@ impl D3Pass {
                     },
                     count: None,
                 }],
-                label: Some("Uniform_bind_group_layout"),
+                label: Some(&format!("{} Uniform Bind Group Layout", name)),
             });
-        let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            label: Some("uniform_bind_group"),
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: uniform_buffer.as_entire_binding(),
-            }],
-        });
+        let (uniform_buffer, uniform_bind_group) = create_uniform_bind_group(
+            device,
+            &uniform_bind_group_layout,
+            &uniforms,
+            &format!("{} Uniform Buffer", name),
+        );
+        // only drawn in stereo mode, see update_stereo_model_view_proj_uniforms
+        let (right_eye_uniform_buffer, right_eye_uniform_bind_group) = create_uniform_bind_group(
+            device,
+            &uniform_bind_group_layout,
+            &uniforms,
+            &format!("{} Right Eye Uniform Buffer", name),
+        );
 
         // create vertex buffer
         let vertex_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
+            label: Some(&format!("{} Vertex Buffer", name)),
             contents: cube.get_vertex_raw(),
             usage: BufferUsages::VERTEX,
         });
         // create index buffer
         let index_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
+            label: Some(&format!("{} Index Buffer", name)),
             contents: cube.get_index_raw(),
             usage: BufferUsages::INDEX,
         });
 
-        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("3D Pass shaders"),
-            source: ShaderSource::Wgsl(include_str!("./shaders/shader3d.wgsl").into()),
-        });
-
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("3DPass Render Pipeline Layout"),
+            label: Some(&format!("{} Render Pipeline Layout", name)),
             bind_group_layouts: &[&uniform_bind_group_layout],
             push_constant_ranges: &[],
         });
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("3DPass Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+        let shader_source = include_str!("./shaders/shader3d.wgsl").to_string();
+        let render_pipeline = Self::create_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader_source,
+            &cube,
+            target_format,
+            faces,
+            sample_count,
+        );
+        Self {
+            depth_texture,
+            vertex_buffer,
+            index_buffer,
+            uniforms,
+            uniform_bind_group,
+            right_eye_uniform_bind_group,
+            uniform_buffer,
+            right_eye_uniform_buffer,
+            stereo: false,
+            depth_clear_op,
+            multisample_buffer,
+            clear_color: (0.0, 0.0, 0.0, 1.0),
+            num_depth_indices: cube.get_num_indices() as u32,
+            render_pipeline_layout,
+            render_pipeline,
+            shader_source,
+            target_format: *target_format,
+            faces,
+            cube,
+            sample_count,
+        }
+    }
+
+    /// Builds the pipeline from the WGSL `shader_source`, shader3d.wgsl or an edited version of it
+    fn create_render_pipeline(
+        device: &Device,
+        render_pipeline_layout: &PipelineLayout,
+        shader_source: &str,
+        cube: &Mesh3,
+        target_format: &TextureFormat,
+        faces: FaceSelection,
+        sample_count: u32,
+    ) -> RenderPipeline {
+        let name = Self::pass_name(faces.render_front_face);
+        let face_render_config = Self::face_render_config(faces.render_front_face);
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(&format!("{} Shaders", name)),
+            source: ShaderSource::Wgsl(shader_source.into()),
+        });
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(&format!("{} Render Pipeline", name)),
+            layout: Some(render_pipeline_layout),
             vertex: VertexState {
                 module: &shader_module,
                 entry_point: "vertex_shader",
@@ -180,7 +412,11 @@ impl D3Pass {
             },
             fragment: Some(FragmentState {
                 module: &shader_module,
-                entry_point: "fragment_shader",
+                entry_point: if faces.render_front_face {
+                    "entry_fragment_shader"
+                } else {
+                    "fragment_shader"
+                },
                 compilation_options: Default::default(),
                 targets: &[Some(ColorTargetState {
                     format: target_format.clone(),
@@ -192,7 +428,7 @@ impl D3Pass {
                 topology: PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: FrontFace::Ccw, // facing forward if the vertices are arranged in a counter clockwise direction
-                cull_mode: Some(face_render_config.0),
+                cull_mode: faces.cull_mode,
                 unclipped_depth: false,
                 polygon_mode: PolygonMode::Fill,
                 conservative: false,
@@ -214,21 +450,92 @@ impl D3Pass {
             }, // the config of this struct is the same as MultisampleState::default()
             multiview: None,
             cache: None,
+        })
+    }
+
+    /// Rebuilds the pipeline from the WGSL `shader_source`, e.g. while editing shader3d.wgsl.
+    /// The old pipeline is kept if the shader does not compile or does not match the pipeline layout
+    pub fn reload_shader(&mut self, device: &Device, shader_source: &str) -> Result<()> {
+        self.render_pipeline = catch_validation_error(device, || {
+            Self::create_render_pipeline(
+                device,
+                &self.render_pipeline_layout,
+                shader_source,
+                &self.cube,
+                &self.target_format,
+                self.faces,
+                self.sample_count,
+            )
+        })?;
+        self.shader_source = shader_source.to_string();
+        Ok(())
+    }
+
+    /// Recreates the multisample buffer, the depth texture and the pipeline for `sample_cnt` samples per pixel.
+    /// Expensive, the pipeline is compiled again
+    pub fn set_sample_count(&mut self, device: &Device, sample_cnt: NonZeroU32) {
+        let name = Self::pass_name(self.faces.render_front_face);
+        let size = self.depth_texture.texture.size();
+        self.sample_count = sample_cnt.get();
+        self.depth_texture = Tex::create_depth_texture(
+            device,
+            size.width,
+            size.height,
+            sample_cnt,
+            &format!("{} Depth Texture", name),
+        );
+        self.multisample_buffer = (self.sample_count > 1).then(|| {
+            Tex::create_render_buffer(
+                (size.width, size.height),
+                device,
+                Some(&format!("{} Multisample Buffer", name)),
+                sample_cnt,
+                &self.target_format,
+            )
         });
-        Self {
-            depth_texture,
-            vertex_buffer,
-            index_buffer,
-            uniforms,
-            uniform_bind_group,
-            uniform_buffer,
-            depth_clear_op,
-            multisample_buffer,
-            clear_color: (0.0, 0.0, 0.0, 1.0),
-            num_depth_indices: cube.get_num_indices() as u32,
-            render_pipeline,
-            cube,
-            sample_count,
+        self.render_pipeline = Self::create_render_pipeline(
+            device,
+            &self.render_pipeline_layout,
+            &self.shader_source,
+            &self.cube,
+            &self.target_format,
+            self.faces,
+            self.sample_count,
+        );
+    }
+
+    /// Name of the front-face or back-face pass, the labels of its GPU resources start with it
+    fn pass_name(render_front_face: bool) -> &'static str {
+        if render_front_face {
+            "Front Face Pass"
+        } else {
+            "Back Face Pass"
+        }
+    }
+
+    /// Cull mode of the front-face (`true`) or back-face pass for the closed proxy cube.
+    /// The back-face pass culls front faces, the front-face pass culls nothing to enter on the near plane from inside
+    pub fn default_cull_mode(render_front_face: bool) -> Option<Face> {
+        Self::face_render_config(render_front_face).0
+    }
+
+    ///
+    /// Cull mode, depth comparison and depth clear value of the front-face (`true`) or back-face pass
+    ///
+    /// The back-face pass keeps the farthest back face, the exit of the ray.
+    /// The front-face pass keeps the nearest face of any orientation: a front face if there is one,
+    /// otherwise the camera is inside the volume or the near plane clipped the front faces,
+    /// and the nearest back face writes the ray entry on the near plane instead.
+    ///
+    fn face_render_config(render_front_face: bool) -> (Option<Face>, CompareFunction, LoadOp<f32>) {
+        if render_front_face {
+            (None, CompareFunction::Less, LoadOp::Clear(1.0))
+        } else {
+            (
+                Some(Face::Front),
+                CompareFunction::Greater,
+                LoadOp::Clear(0.0),
+            )
         }
     }
 
@@ -245,25 +552,75 @@ impl D3Pass {
             0,
             self.uniforms.as_std140().as_bytes(),
         );
+        self.stereo = false;
+    }
+
+    /// Switches to stereo mode: the left eye is drawn into the left half of the target, the right eye into the right half.
+    /// The cameras should have half the aspect of the target, see [`Camera::stereo_eyes`]
+    pub fn update_stereo_model_view_proj_uniforms(
+        &mut self,
+        model_transformation: Matrix4<f32>,
+        left_eye: &Camera,
+        right_eye: &Camera,
+        queue: &Queue,
+    ) {
+        self.update_model_view_proj_uniform(model_transformation, left_eye, queue);
+        let mut right_eye_uniforms = Uniforms::new();
+        right_eye_uniforms.update_model_view_proj(right_eye, model_transformation);
+        queue.write_buffer(
+            &self.right_eye_uniform_buffer,
+            0,
+            right_eye_uniforms.as_std140().as_bytes(),
+        );
+        self.stereo = true;
+    }
+
+    /// The composed matrix the faces are drawn with, mapping the unit cube centered at the origin to clip space.
+    /// In stereo mode it is the one of the left eye
+    pub fn model_view_proj(&self) -> Matrix4<f32> {
+        self.uniforms.model_view_proj()
+    }
+
+    fn eye_bind_groups(&self) -> Vec<&BindGroup> {
+        if self.stereo {
+            vec![&self.uniform_bind_group, &self.right_eye_uniform_bind_group]
+        } else {
+            vec![&self.uniform_bind_group]
+        }
+    }
+
+    pub fn clear_color(&self) -> (f64, f64, f64, f64) {
+        self.clear_color
+    }
+
+    /// Sets the color of pixels the cube does not cover.
+    ///
+    /// Face passes render ray entry/exit positions as colors, so the clear color is read as a position
+    /// by the canvas pass. With the default black entry and exit coincide and such rays are skipped,
+    /// other colors make the canvas pass march rays that miss the volume.
+    /// Use [`CanvasPass::set_background_color`] to change what is seen behind the volume.
+    pub fn set_clear_color(&mut self, clear_color: (f64, f64, f64, f64)) {
+        self.clear_color = clear_color;
     }
 }
 
 impl RenderPass for D3Pass {
     fn resize(&mut self, device: &Device, render_width: u32, render_height: u32) {
+        let name = Self::pass_name(self.faces.render_front_face);
         let sample_cnt = NonZeroU32::new(self.sample_count).unwrap();
         self.depth_texture = Tex::create_depth_texture(
             device,
             render_width,
             render_height,
             sample_cnt.clone(),
-            "depth texture",
+            &format!("{} Depth Texture", name),
         );
         self.multisample_buffer = match self.multisample_buffer {
             None => None,
             Some(ref old_buffer) => Some(Tex::create_render_buffer(
                 (render_width, render_height),
                 device,
-                Some("Multisample Buffer"),
+                Some(&format!("{} Multisample Buffer", name)),
                 sample_cnt,
                 &old_buffer.format,
             )),
@@ -281,7 +638,7 @@ impl RenderPass for D3Pass {
             Some(ref multisample_buffer) => (&multisample_buffer.view, Some(render_into_view)),
         };
         let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render Pass"),
+            label: Some(Self::pass_name(self.faces.render_front_face)),
             // color_attachments describe where we are going to draw our color to
             color_attachments: &[Some(RenderPassColorAttachment {
                 //view informs wgpu what texture to save the colors to
@@ -319,33 +676,155 @@ impl RenderPass for D3Pass {
         // You can store as many objects in a buffer as your hardware allows, so slice allows us to specify which portion of the buffer to use.
         render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         render_pass.set_index_buffer(self.index_buffer.slice(..), self.cube.get_index_format());
-        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
-        render_pass.draw_indexed(0..self.num_depth_indices, 0, 0..1);
+        draw_per_eye(
+            &mut render_pass,
+            self.depth_texture.texture.size(),
+            &self.eye_bind_groups(),
+            |render_pass| render_pass.draw_indexed(0..self.num_depth_indices, 0, 0..1),
+        );
+    }
+}
+
+/// How the samples along a ray are combined into a pixel
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Front-to-back alpha compositing through the transfer function
+    Composite = 0,
+    /// Maximum scalar along the ray (MIP)
+    MaximumIntensity = 1,
+    /// Mean scalar along the ray
+    AverageIntensity = 2,
+}
+
+impl RenderMode {
+    /// The mode after this one, wrapping around
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Composite => RenderMode::MaximumIntensity,
+            RenderMode::MaximumIntensity => RenderMode::AverageIntensity,
+            RenderMode::AverageIntensity => RenderMode::Composite,
+        }
+    }
+}
+
+/// How the volume is reconstructed between voxels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// A single hardware-filtered sample
+    Trilinear = 0,
+    /// Cubic B-spline from 8 trilinear samples, smoother but 8 times the texture reads
+    Tricubic = 1,
+}
+
+impl Interpolation {
+    /// The interpolation after this one, wrapping around
+    pub fn next(self) -> Self {
+        match self {
+            Interpolation::Trilinear => Interpolation::Tricubic,
+            Interpolation::Tricubic => Interpolation::Trilinear,
+        }
+    }
+}
+
+/// What is seen behind the volume, colors are linear with straight alpha
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BackgroundStyle {
+    /// The canvas is cleared to the color
+    SolidColor(Color),
+    /// Blends from `top` at the top of the canvas to `bottom` at its bottom
+    VerticalGradient { top: Color, bottom: Color },
+}
+
+/// Operator compressing bright composited colors into the displayable range, see [`CanvasPass::set_tonemap`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneMapping {
+    /// Only the exposure is applied, colors above 1 are clipped
+    None = 0,
+    /// `c / (1 + c)`, never reaches white
+    Reinhard = 1,
+    /// Fit of the ACES filmic curve, keeps more contrast than Reinhard
+    Aces = 2,
+}
+
+/// Face buffer shown instead of the volume to debug the ray entry and exit positions, see [`CanvasPass::set_face_buffer_view`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaceBufferView {
+    /// The volume is rendered
+    Off = 0,
+    /// Ray entry positions of the front faces as colors
+    FrontFaces = 1,
+    /// Ray exit positions of the back faces as colors
+    BackFaces = 2,
+}
+
+impl FaceBufferView {
+    /// The view after this one, wrapping around
+    pub fn next(self) -> Self {
+        match self {
+            FaceBufferView::Off => FaceBufferView::FrontFaces,
+            FaceBufferView::FrontFaces => FaceBufferView::BackFaces,
+            FaceBufferView::BackFaces => FaceBufferView::Off,
+        }
     }
 }
 
+/// Colors and opacities of `width` scalars by `height` gradient magnitudes, stored row by row
+struct TransferFunction2d {
+    width: u32,
+    height: u32,
+    values: Vec<cgmath::Vector4<u8>>,
+}
+
+/// Label volume and palette bound for the overlay of [`CanvasPass::with_labels`], placeholders without one
+struct LabelTextures<'a> {
+    texture: &'a Tex,
+    palette: &'a Tex,
+}
+
 pub struct CanvasPass {
     face_texture_bind_group_layout: BindGroupLayout,
     face_texture_bind_group: BindGroup,
+    volume_bind_group_layout: BindGroupLayout,
     volume_bind_group: BindGroup,
+    volume_texture: Arc<Tex>, // shared with other passes sampling the same volume, see CanvasPass::volume_texture
+    default_mask_texture: Tex,
+    default_gradient_texture: Tex,
+    default_label_texture: Tex,
+    default_label_palette: Tex,
+    tf_bind_group_layout: BindGroupLayout,
     tf_bind_group: BindGroup,
+    transfer_function: Vec<cgmath::Vector4<u8>>,
+    transfer_function_2d: Option<TransferFunction2d>,
+    occupancy_grid: Option<OccupancyGrid>,
+    occupancy_texture: Tex, // rewritten when the scalars reach the transfer function differently, e.g. with another window
     uniforms: CanvasShaderUniforms,
     uniform_bind_group: BindGroup,
     uniform_buffer: Buffer,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     num_depth_indices: u32,
+    render_pipeline_layout: PipelineLayout,
     render_pipeline: RenderPipeline,
+    pick_pipeline: RenderPipeline,
+    shader_source: String, // source of the pipelines, kept for rebuilding them
+    target_format: TextureFormat,
     canvas: Rectangle,
     sample_count: u32,
     multisample_buffer: Option<Tex>,
+    background_color: Color,
+    gpu_timer: Option<Arc<GpuTimer>>,
+    revision: u64, // bumped by every change of the rendered image, see CanvasPass::revision
 }
 
 impl CanvasPass {
+    /// Format of the hit positions rendered by [`CanvasPass::pick`], precise to 1/2048 of the volume extent.
+    /// Rgba32Float would be more precise but is not renderable on downlevel devices
+    const PICK_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
     pub fn new(
         front_face_render_buffer: &Tex,
         back_face_render_buffer: &Tex,
-        volume_texture: &Tex,
+        volume_texture: Arc<Tex>,
         device: &Device,
         queue: &Queue,
         resolution: (u32, u32),
@@ -357,7 +836,7 @@ impl CanvasPass {
             Some(Tex::create_render_buffer(
                 resolution,
                 device,
-                Some("Render Pass multisample buffer"),
+                Some("Canvas Pass Multisample Buffer"),
                 sample_cnt,
                 tex_format,
             ))
@@ -369,7 +848,7 @@ impl CanvasPass {
         // We create a BindGroup using a BindGroupLayout.
         let face_texture_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("Texture binding group layout"),
+                label: Some("Canvas Pass Face Texture Bind Group Layout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
@@ -409,7 +888,7 @@ impl CanvasPass {
         // The reason why they're separate is it allows us to swap out BindGroups on the fly,
         // so long as they all share the same BindGroupLayout
         let face_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Backface and front face bind group"),
+            label: Some("Canvas Pass Face Texture Bind Group"),
             layout: &face_texture_bind_group_layout,
             entries: &[
                 BindGroupEntry {
@@ -430,9 +909,20 @@ impl CanvasPass {
                 },
             ],
         });
+        // the volume can be any format sampled as float, e.g. R8Unorm, R16Unorm or R16Float
+        let volume_filterable = match volume_texture
+            .format
+            .sample_type(None, Some(device.features()))
+        {
+            Some(TextureSampleType::Float { filterable }) => filterable,
+            _ => panic!(
+                "Volume texture format {:?} is not sampled as float",
+                volume_texture.format
+            ),
+        };
         let volume_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("volume bind group layout"),
+                label: Some("Canvas Pass Volume Bind Group Layout"),
                 entries: &[
                     BindGroupLayoutEntry {
                         binding: 0,
@@ -440,41 +930,130 @@ impl CanvasPass {
                         ty: BindingType::Texture {
                             multisampled: false,
                             view_dimension: TextureViewDimension::D3,
-                            sample_type: TextureSampleType::Float { filterable: true },
+                            sample_type: TextureSampleType::Float {
+                                filterable: volume_filterable,
+                            },
                         },
                         count: None,
                     },
                     BindGroupLayoutEntry {
                         binding: 1,
                         visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(if volume_filterable {
+                            SamplerBindingType::Filtering
+                        } else {
+                            SamplerBindingType::NonFiltering
+                        }),
+                        count: None,
+                    },
+                    // mask volume restricting where the volume is rendered
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D3,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // optional precomputed gradients
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D3,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Sampler(SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // optional segmentation labels and their colors, both read with textureLoad
+                    BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D3,
+                            sample_type: TextureSampleType::Uint,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: TextureViewDimension::D2,
+                            sample_type: TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
                 ],
             });
-        let volume_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("volume bind group"),
-            layout: &volume_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&volume_texture.view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&volume_texture.sampler),
-                },
-            ],
-        });
-        let transfer_function_values = load_example_transfer_function();
-        let transfer_function_texture = Tex::create_1d_texture_rgba8(
-            &transfer_function_values,
+        let single_voxel = Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        // a single voxel mask of 1.0 lets every sample pass when no mask is bound
+        let default_mask_texture = Tex::create_3d_texture_red_f16(
+            &single_voxel,
+            &vec![f16::ONE],
             device,
             queue,
-            "Transfer function",
+            "Canvas Pass Default Mask",
         );
+        // placeholder until precomputed gradients are bound, gradients are estimated in the shader
+        let default_gradient_texture = Tex::create_3d_texture_rgba16f(
+            &single_voxel,
+            &vec![f16::ZERO; 4],
+            device,
+            queue,
+            "Canvas Pass Default Gradients",
+        );
+        // every voxel is unlabeled until a label volume is bound
+        let default_label_texture = Tex::create_3d_texture_label_u8(
+            &single_voxel,
+            &vec![0],
+            device,
+            queue,
+            "Canvas Pass Default Labels",
+        );
+        let default_label_palette = Tex::create_2d_texture_rgba8(
+            1,
+            1,
+            &[cgmath::Vector4::new(0, 0, 0, 0)],
+            device,
+            queue,
+            "Canvas Pass Default Label Palette",
+        );
+        let volume_bind_group = Self::create_volume_bind_group(
+            device,
+            &volume_bind_group_layout,
+            &volume_texture,
+            &default_mask_texture,
+            &default_gradient_texture,
+            LabelTextures {
+                texture: &default_label_texture,
+                palette: &default_label_palette,
+            },
+        );
+        let transfer_function = load_example_transfer_function();
         let tf_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("tf bind group layout"),
+            label: Some("Canvas Pass Transfer Function Bind Group Layout"),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
@@ -492,32 +1071,69 @@ impl CanvasPass {
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
-            ],
-        });
-        let tf_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("volume bind group"),
-            layout: &tf_bind_group_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&transfer_function_texture.view),
+                // occupancy of the volume blocks under the transfer function, read with textureLoad
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D3,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
                 },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&transfer_function_texture.sampler),
+                // 2D transfer function indexed by scalar and gradient magnitude
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // pre-integrated transfer function indexed by front and back scalar, see set_preintegration_enabled
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
                 },
             ],
         });
+        let (tf_bind_group, occupancy_texture) = Self::create_tf_bind_group(
+            device,
+            queue,
+            &tf_bind_group_layout,
+            &transfer_function,
+            None,
+            None,
+            &CanvasShaderUniforms::default(),
+        );
         // create uniform bindings
-        let uniforms = CanvasShaderUniforms::default();
+        let uniforms = CanvasShaderUniforms {
+            rgba_volume: Self::is_rgba_volume(&volume_texture) as u32,
+            ..Default::default()
+        };
         let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Uniform Buffer"),
-            contents: uniforms.as_std140().as_bytes(),
+            label: Some("Canvas Pass Uniform Buffer"),
+            contents: &uniforms.as_std140_bytes(),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("Uniform Bind Group Layout"),
+                label: Some("Canvas Pass Uniform Bind Group Layout"),
                 entries: &[BindGroupLayoutEntry {
                     binding: 0,
                     visibility: ShaderStages::FRAGMENT,
@@ -530,7 +1146,7 @@ impl CanvasPass {
                 }],
             });
         let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Uniform Bind Group"),
+            label: Some("Canvas Pass Uniform Bind Group"),
             layout: &uniform_bind_group_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
@@ -539,22 +1155,17 @@ impl CanvasPass {
         });
         // create vertex buffer
         let vertex_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
+            label: Some("Canvas Pass Vertex Buffer"),
             contents: canvas.get_vertex_raw(),
             usage: BufferUsages::VERTEX,
         });
         // create index buffer
         let index_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
+            label: Some("Canvas Pass Index Buffer"),
             contents: canvas.get_index_raw(),
             usage: BufferUsages::INDEX,
         });
 
-        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some("Canvas Pass Shaders"),
-            source: ShaderSource::Wgsl(include_str!("./shaders/canvas_shader.wgsl").into()),
-        });
-
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Canvas Pass Render Pipeline Layout"),
             bind_group_layouts: &[
@@ -565,48 +1176,31 @@ impl CanvasPass {
             ],
             push_constant_ranges: &[],
         });
-
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Canvas Pass Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: VertexState {
-                module: &shader_module,
-                entry_point: "vertex_shader",
-                compilation_options: Default::default(),
-                buffers: &[canvas.vertex_desc()],
-            },
-            fragment: Some(FragmentState {
-                module: &shader_module,
-                entry_point: "fragment_shader",
-                compilation_options: Default::default(),
-                targets: &[Some(ColorTargetState {
-                    format: tex_format.clone(),
-                    blend: Some(BlendState::REPLACE), //specify that the blending should just replace old pixel data with new data
-                    write_mask: ColorWrites::ALL, //tell wgpu to write to all colors: red, blue, green, and alpha
-                })],
-            }),
-            primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: FrontFace::Ccw, // facing forward if the vertices are arranged in a counter clockwise direction
-                cull_mode: Some(Face::Back),
-                unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: sample_count,
-                ..Default::default()
-            },
-            multiview: None,
-            cache: None,
-        });
+        let shader_source = include_str!("./shaders/canvas_shader.wgsl").to_string();
+        let (render_pipeline, pick_pipeline) = Self::create_pipelines(
+            device,
+            &render_pipeline_layout,
+            &shader_source,
+            &canvas,
+            tex_format,
+            sample_count,
+        );
         Self {
             face_texture_bind_group_layout,
             face_texture_bind_group,
+            volume_bind_group_layout,
             volume_bind_group,
+            volume_texture,
+            default_mask_texture,
+            default_gradient_texture,
+            default_label_texture,
+            default_label_palette,
+            tf_bind_group_layout,
             tf_bind_group,
+            transfer_function,
+            transfer_function_2d: None,
+            occupancy_grid: None,
+            occupancy_texture,
             uniforms,
             uniform_bind_group,
             uniform_buffer,
@@ -614,20 +1208,211 @@ impl CanvasPass {
             index_buffer,
             num_depth_indices: canvas.get_num_indices() as u32,
             canvas,
+            render_pipeline_layout,
             render_pipeline,
+            pick_pipeline,
+            shader_source,
+            target_format: *tex_format,
             sample_count,
             multisample_buffer,
+            background_color: Color::TRANSPARENT,
+            gpu_timer: None,
+            revision: 0,
         }
     }
 
+    /// Builds the render and the pick pipeline from the WGSL `shader_source`, canvas_shader.wgsl or an edited version of it
+    fn create_pipelines(
+        device: &Device,
+        render_pipeline_layout: &PipelineLayout,
+        shader_source: &str,
+        canvas: &Rectangle,
+        tex_format: &TextureFormat,
+        sample_count: u32,
+    ) -> (RenderPipeline, RenderPipeline) {
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Canvas Pass Shaders"),
+            source: ShaderSource::Wgsl(shader_source.into()),
+        });
+        let create_pipeline =
+            |label: &str, entry_point: &str, target: ColorTargetState, sample_count: u32| {
+                device.create_render_pipeline(&RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(render_pipeline_layout),
+                    vertex: VertexState {
+                        module: &shader_module,
+                        entry_point: "vertex_shader",
+                        compilation_options: Default::default(),
+                        buffers: &[canvas.vertex_desc()],
+                    },
+                    fragment: Some(FragmentState {
+                        module: &shader_module,
+                        entry_point,
+                        compilation_options: Default::default(),
+                        targets: &[Some(target)],
+                    }),
+                    primitive: PrimitiveState {
+                        topology: PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face: FrontFace::Ccw, // facing forward if the vertices are arranged in a counter clockwise direction
+                        cull_mode: Some(Face::Back),
+                        unclipped_depth: false,
+                        polygon_mode: PolygonMode::Fill,
+                        conservative: false,
+                    },
+                    depth_stencil: None,
+                    multisample: MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    },
+                    multiview: None,
+                    cache: None,
+                })
+            };
+        let render_pipeline = create_pipeline(
+            "Canvas Pass Render Pipeline",
+            "fragment_shader",
+            ColorTargetState {
+                format: *tex_format,
+                blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING), // the shader outputs premultiplied color, composite it over the background
+                write_mask: ColorWrites::ALL, //tell wgpu to write to all colors: red, blue, green, and alpha
+            },
+            sample_count,
+        );
+        // hit positions are not blended and not multisampled, a single ray per pixel decides the hit
+        let pick_pipeline = create_pipeline(
+            "Canvas Pass Pick Pipeline",
+            "pick_fragment_shader",
+            ColorTargetState {
+                format: Self::PICK_FORMAT,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            },
+            1,
+        );
+        (render_pipeline, pick_pipeline)
+    }
+
+    /// Rebuilds the render and the pick pipeline from the WGSL `shader_source`, e.g. while editing canvas_shader.wgsl.
+    /// The old pipelines are kept if the shader does not compile or does not match the bind group layouts
+    pub fn reload_shader(&mut self, device: &Device, shader_source: &str) -> Result<()> {
+        (self.render_pipeline, self.pick_pipeline) = catch_validation_error(device, || {
+            Self::create_pipelines(
+                device,
+                &self.render_pipeline_layout,
+                shader_source,
+                &self.canvas,
+                &self.target_format,
+                self.sample_count,
+            )
+        })?;
+        self.shader_source = shader_source.to_string();
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Recreates the multisample buffer of the `resolution` sized target and the pipelines
+    /// for `sample_cnt` samples per pixel. Expensive, the pipelines are compiled again
+    pub fn set_sample_count(
+        &mut self,
+        device: &Device,
+        resolution: (u32, u32),
+        sample_cnt: NonZeroU32,
+    ) {
+        self.sample_count = sample_cnt.get();
+        self.multisample_buffer = (self.sample_count > 1).then(|| {
+            Tex::create_render_buffer(
+                resolution,
+                device,
+                Some("Canvas Pass Multisample Buffer"),
+                sample_cnt,
+                &self.target_format,
+            )
+        });
+        (self.render_pipeline, self.pick_pipeline) = Self::create_pipelines(
+            device,
+            &self.render_pipeline_layout,
+            &self.shader_source,
+            &self.canvas,
+            &self.target_format,
+            self.sample_count,
+        );
+        self.revision += 1;
+    }
+
+    /// The pass of [`CanvasPass::new`], but shading uses gradients precomputed by [`crate::utils::compute_gradients`]
+    pub fn with_gradients(
+        mut self,
+        gradient_texture: &Tex,
+        device: &Device,
+        queue: &Queue,
+    ) -> Self {
+        let volume_texture = self.volume_texture.clone();
+        self.change_bound_volume_textures(
+            device,
+            queue,
+            volume_texture,
+            None,
+            Some(gradient_texture),
+        );
+        self
+    }
+
+    ///
+    /// The pass of [`CanvasPass::new`] with a segmentation overlaid on the volume
+    ///
+    /// `label_texture` holds a label per voxel, see [`Tex::create_3d_texture_label_u8`], and need not have
+    /// the resolution of the volume. `palette[label]` is the sRGB color of a label, its alpha how much
+    /// the color replaces the one of the transfer function. Label 0 and labels beyond the palette are not overlaid.
+    /// Only the composite mode shows the labels, [`CanvasPass::change_bound_volume_textures`] removes them.
+    ///
+    pub fn with_labels(
+        self,
+        label_texture: &Tex,
+        palette: &[cgmath::Vector4<u8>],
+        device: &Device,
+        queue: &Queue,
+    ) -> Self {
+        assert!(
+            !palette.is_empty(),
+            "the label palette needs at least one color"
+        );
+        let mut canvas_pass = self;
+        // a single row, labels index it with textureLoad
+        let palette_texture = Tex::create_2d_texture_rgba8(
+            palette.len() as u32,
+            1,
+            palette,
+            device,
+            queue,
+            "Canvas Pass Label Palette",
+        );
+        canvas_pass.volume_bind_group = Self::create_volume_bind_group(
+            device,
+            &canvas_pass.volume_bind_group_layout,
+            &canvas_pass.volume_texture,
+            &canvas_pass.default_mask_texture,
+            &canvas_pass.default_gradient_texture,
+            LabelTextures {
+                texture: label_texture,
+                palette: &palette_texture,
+            },
+        );
+        let mut uniforms = canvas_pass.uniforms;
+        uniforms.label_overlay = 1;
+        canvas_pass.set_uniforms(&uniforms, queue);
+        canvas_pass
+    }
+
     pub fn change_bound_face_textures(
         &mut self,
         device: &Device,
         front_face_texture: &Tex,
         back_face_texture: &Tex,
     ) {
+        self.revision += 1;
         self.face_texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Backface and front face bind group"),
+            label: Some("Canvas Pass Face Texture Bind Group"),
             layout: &self.face_texture_bind_group_layout,
             entries: &[
                 BindGroupEntry {
@@ -650,76 +1435,2584 @@ impl CanvasPass {
         });
     }
 
-    pub fn set_uniforms(&mut self, uniforms: &CanvasShaderUniforms, queue: &Queue) {
-        self.uniforms = uniforms.clone();
-        queue.write_buffer(
-            &self.uniform_buffer,
-            0,
-            self.uniforms.as_std140().as_bytes(),
-        );
+    fn create_volume_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        volume_texture: &Tex,
+        mask_texture: &Tex,
+        gradient_texture: &Tex,
+        labels: LabelTextures,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Canvas Pass Volume Bind Group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&volume_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&volume_texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&mask_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Sampler(&mask_texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(&gradient_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::Sampler(&gradient_texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(&labels.texture.view),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: BindingResource::TextureView(&labels.palette.view),
+                },
+            ],
+        })
     }
-}
 
-impl RenderPass for CanvasPass {
-    fn resize(&mut self, device: &Device, width: u32, height: u32) {
-        self.multisample_buffer = match self.multisample_buffer {
-            None => None,
-            Some(ref old_buffer) => Some(Tex::create_render_buffer(
-                (width, height),
+    /// The occupancy texture is classified against the transfer function in use,
+    /// without a grid a single occupied block covers the whole volume. Returns the bind group and the occupancy texture
+    fn create_tf_bind_group(
+        device: &Device,
+        queue: &Queue,
+        layout: &BindGroupLayout,
+        transfer_function: &Vec<cgmath::Vector4<u8>>,
+        transfer_function_2d: Option<&TransferFunction2d>,
+        occupancy_grid: Option<&OccupancyGrid>,
+        uniforms: &CanvasShaderUniforms,
+    ) -> (BindGroup, Tex) {
+        let preintegrated = uniforms.preintegrated != 0;
+        let transfer_function_texture = Tex::create_1d_texture_rgba8(
+            transfer_function,
+            device,
+            queue,
+            "Canvas Pass Transfer Function",
+        );
+        let transfer_function_2d_texture = match transfer_function_2d {
+            Some(tf) => Tex::create_2d_texture_rgba8(
+                tf.width,
+                tf.height,
+                &tf.values,
                 device,
-                Some("Render Pass multisample buffer"),
-                NonZeroU32::new(self.sample_count).unwrap(),
-                &old_buffer.format,
-            )),
+                queue,
+                "Canvas Pass 2D Transfer Function",
+            ),
+            // placeholder while the 1D transfer function is in use
+            None => Tex::create_2d_texture_rgba8(
+                1,
+                1,
+                &[cgmath::Vector4::new(0, 0, 0, 0)],
+                device,
+                queue,
+                "Canvas Pass Default 2D Transfer Function",
+            ),
+        };
+        let preintegration_texture = if preintegrated {
+            let size = transfer_function.len() as u32;
+            Tex::create_2d_texture_rgba8(
+                size,
+                size,
+                &compute_preintegration_table(transfer_function),
+                device,
+                queue,
+                "Canvas Pass Pre-integrated Transfer Function",
+            )
+        } else {
+            // placeholder while the samples are classified on their own
+            Tex::create_2d_texture_rgba8(
+                1,
+                1,
+                &[cgmath::Vector4::new(0, 0, 0, 0)],
+                device,
+                queue,
+                "Canvas Pass Default Pre-integrated Transfer Function",
+            )
+        };
+        let occupancy_texture = match occupancy_grid {
+            Some(grid) => Tex::create_3d_texture_r8(
+                &grid.extent(),
+                &Self::classify_occupancy(grid, transfer_function, transfer_function_2d, uniforms),
+                device,
+                queue,
+                "Canvas Pass Occupancy Grid",
+            ),
+            None => Tex::create_3d_texture_r8(
+                &Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                &vec![255],
+                device,
+                queue,
+                "Canvas Pass Default Occupancy Grid",
+            ),
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Canvas Pass Transfer Function Bind Group"),
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&transfer_function_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&transfer_function_texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&occupancy_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(&transfer_function_2d_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::Sampler(&transfer_function_2d_texture.sampler),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: BindingResource::TextureView(&preintegration_texture.view),
+                },
+            ],
+        });
+        (bind_group, occupancy_texture)
+    }
+
+    /// Occupancy of the blocks of `grid` under the transfer function in use, after the scalars are windowed
+    /// and remapped like in the shader
+    fn classify_occupancy(
+        grid: &OccupancyGrid,
+        transfer_function: &[cgmath::Vector4<u8>],
+        transfer_function_2d: Option<&TransferFunction2d>,
+        uniforms: &CanvasShaderUniforms,
+    ) -> Vec<u8> {
+        let remap = |scalar| uniforms.transfer_function_scalar(scalar);
+        match transfer_function_2d {
+            Some(tf) => grid.classify(
+                &max_opacity_over_gradients(tf.width as usize, tf.height as usize, &tf.values),
+                remap,
+            ),
+            None => grid.classify(transfer_function, remap),
         }
     }
 
-    fn render(
-        &self,
-        render_into_view: &TextureView,
-        _depth_view: Option<&TextureView>,
-        encoder: &mut CommandEncoder,
-    ) {
-        let (view, resolve_target) = match self.multisample_buffer {
-            None => (render_into_view, None),
-            Some(ref multisample_buffer) => (&multisample_buffer.view, Some(render_into_view)),
+    /// Classifies the occupancy grid again into the same texture, e.g. after the window or the gamma changed
+    fn reclassify_occupancy(&self, queue: &Queue) {
+        let Some(grid) = self.occupancy_grid.as_ref() else {
+            return;
         };
-        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-            label: Some("Render Pass"),
-            // color_attachments describe where we are going to draw our color to
-            color_attachments: &[Some(RenderPassColorAttachment {
-                //view informs wgpu what texture to save the colors to
-                view,
-                // The resolve_target is the texture that will receive the resolved output.
-                // This will be the same as `view` unless multisampling is enabled
-                resolve_target,
-                ops: Operations {
-                    // The load field tells wgpu how to handle colors stored from the previous frame
-                    load: LoadOp::Clear(Color {
-                        r: 0.0,
-                        g: 0.0,
-                        b: 0.0,
-                        a: 0.0,
-                    }),
-                    store: StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-            occlusion_query_set: None,
-        });
-        render_pass.set_pipeline(&self.render_pipeline);
-        // set_vertex_buffer takes two parameters.
-        // The first is what buffer slot to use for this vertex buffer.
-        // You can have multiple vertex buffers set at a time
-        // The second parameter is the slice of the buffer to use.
-        // You can store as many objects in a buffer as your hardware allows, so slice allows us to specify which portion of the buffer to use.
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), self.canvas.get_index_format());
-        render_pass.set_bind_group(0, &self.face_texture_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.volume_bind_group, &[]);
-        render_pass.set_bind_group(2, &self.tf_bind_group, &[]);
-        render_pass.set_bind_group(3, &self.uniform_bind_group, &[]);
-        render_pass.draw_indexed(0..self.num_depth_indices, 0, 0..1);
+        let occupancy = Self::classify_occupancy(
+            grid,
+            &self.transfer_function,
+            self.transfer_function_2d.as_ref(),
+            &self.uniforms,
+        );
+        let extent = grid.extent();
+        queue.write_texture(
+            self.occupancy_texture.texture.as_image_copy(),
+            &occupancy,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(extent.width),
+                rows_per_image: Some(extent.height),
+            },
+            extent,
+        );
+    }
+
+    /// Entries of the 1D transfer function, from the lowest to the highest scalar
+    pub fn transfer_function(&self) -> &[cgmath::Vector4<u8>] {
+        &self.transfer_function
+    }
+
+    /// Uploads a new transfer function, the texture is recreated but the bind group layout is reused.
+    /// The occupancy grid, if any, is reclassified against the new transfer function
+    pub fn update_transfer_function(
+        &mut self,
+        values: &[cgmath::Vector4<u8>],
+        device: &Device,
+        queue: &Queue,
+    ) {
+        self.transfer_function = values.to_vec();
+        self.update_tf_bind_group(device, queue);
+    }
+
+    ///
+    /// Looks up the color and opacity by scalar and gradient magnitude instead of the scalar alone,
+    /// which isolates material boundaries from homogeneous regions of the same scalar
+    ///
+    /// `values` holds `width` scalars by `height` gradient magnitudes row by row, from a magnitude of 0 in the first row
+    /// to [`CanvasPass::set_max_gradient_magnitude`] in the last. Gradients are those of
+    /// [`crate::utils::compute_gradients`] if bound, estimated while ray marching otherwise.
+    ///
+    pub fn update_transfer_function_2d(
+        &mut self,
+        width: u32,
+        height: u32,
+        values: &[cgmath::Vector4<u8>],
+        device: &Device,
+        queue: &Queue,
+    ) {
+        assert_eq!(
+            values.len(),
+            (width * height) as usize,
+            "Transfer function size not match"
+        );
+        self.transfer_function_2d = Some(TransferFunction2d {
+            width,
+            height,
+            values: values.to_vec(),
+        });
+        self.update_tf_bind_group(device, queue);
+        let mut uniforms = self.uniforms;
+        uniforms.transfer_function_2d = 1;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Looks up the 1D transfer function again after [`CanvasPass::update_transfer_function_2d`]
+    pub fn disable_transfer_function_2d(&mut self, device: &Device, queue: &Queue) {
+        self.transfer_function_2d = None;
+        self.update_tf_bind_group(device, queue);
+        let mut uniforms = self.uniforms;
+        uniforms.transfer_function_2d = 0;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    ///
+    /// Classifies the segment between two consecutive samples instead of each sample on its own,
+    /// with [`crate::utils::compute_preintegration_table`] of the 1D transfer function
+    ///
+    /// A transfer function with narrow peaks no longer needs tiny steps to show them.
+    /// The table is computed again whenever the transfer function changes, a 2D transfer function takes precedence.
+    ///
+    pub fn set_preintegration_enabled(&mut self, enabled: bool, device: &Device, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.preintegrated = enabled as u32;
+        self.set_uniforms(&uniforms, queue);
+        self.update_tf_bind_group(device, queue);
+    }
+
+    pub fn preintegration_enabled(&self) -> bool {
+        self.uniforms.preintegrated != 0
+    }
+
+    /// Gradient magnitude per voxel of the normalized volume mapped to the last row of the 2D transfer function,
+    /// larger magnitudes are clamped to it
+    pub fn set_max_gradient_magnitude(&mut self, max_gradient_magnitude: f32, queue: &Queue) {
+        assert!(
+            max_gradient_magnitude > 0.0,
+            "The max gradient magnitude must be positive"
+        );
+        let mut uniforms = self.uniforms;
+        uniforms.max_gradient_magnitude = max_gradient_magnitude;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    fn update_tf_bind_group(&mut self, device: &Device, queue: &Queue) {
+        self.revision += 1;
+        (self.tf_bind_group, self.occupancy_texture) = Self::create_tf_bind_group(
+            device,
+            queue,
+            &self.tf_bind_group_layout,
+            &self.transfer_function,
+            self.transfer_function_2d.as_ref(),
+            self.occupancy_grid.as_ref(),
+            &self.uniforms,
+        );
+    }
+
+    /// Enables empty-space skipping with an occupancy grid built by [`crate::utils::build_occupancy_grid`],
+    /// `None` marches every step again
+    pub fn set_occupancy_grid(
+        &mut self,
+        occupancy_grid: Option<OccupancyGrid>,
+        device: &Device,
+        queue: &Queue,
+    ) {
+        self.uniforms.occupancy_block_size = occupancy_grid
+            .as_ref()
+            .map_or(0.0, |grid| grid.block_size as f32);
+        queue.write_buffer(&self.uniform_buffer, 0, &self.uniforms.as_std140_bytes());
+        self.occupancy_grid = occupancy_grid;
+        self.update_tf_bind_group(device, queue);
+    }
+
+    /// Binds a new volume, optionally with a single-channel mask volume and precomputed gradients.
+    /// Samples whose mask value is below `mask_threshold` are skipped, no mask renders the whole volume.
+    /// Without gradients they are estimated with central differences while ray marching.
+    /// A label overlay of [`CanvasPass::with_labels`] is removed.
+    /// The volume format may differ from the one the pass was created with, as long as both are filterable or not
+    pub fn change_bound_volume_textures(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        volume_texture: Arc<Tex>,
+        mask_texture: Option<&Tex>,
+        gradient_texture: Option<&Tex>,
+    ) {
+        self.revision += 1;
+        self.volume_bind_group = Self::create_volume_bind_group(
+            device,
+            &self.volume_bind_group_layout,
+            &volume_texture,
+            mask_texture.unwrap_or(&self.default_mask_texture),
+            gradient_texture.unwrap_or(&self.default_gradient_texture),
+            LabelTextures {
+                texture: &self.default_label_texture,
+                palette: &self.default_label_palette,
+            },
+        );
+        let mut uniforms = self.uniforms;
+        uniforms.precomputed_gradient = gradient_texture.is_some() as u32;
+        uniforms.label_overlay = 0;
+        uniforms.rgba_volume = Self::is_rgba_volume(&volume_texture) as u32;
+        self.volume_texture = volume_texture;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// The bound volume, clone it to sample the same upload in another pass without recreating the texture
+    pub fn volume_texture(&self) -> &Arc<Tex> {
+        &self.volume_texture
+    }
+
+    /// Four channel volumes, e.g. from [`crate::utils::combine_channels`], store emitted color and opacity
+    /// and are rendered without the transfer function
+    fn is_rgba_volume(volume_texture: &Tex) -> bool {
+        volume_texture.format.components() == 4
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        match self.uniforms.render_mode {
+            1 => RenderMode::MaximumIntensity,
+            2 => RenderMode::AverageIntensity,
+            _ => RenderMode::Composite,
+        }
+    }
+
+    /// Switches the ray-marching mode, only the uniform buffer is updated
+    pub fn set_render_mode(&mut self, mode: RenderMode, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.render_mode = mode as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn voxel_spacing(&self) -> Vector3<f32> {
+        self.uniforms.voxel_spacing
+    }
+
+    /// Physical size of a voxel along each axis, e.g. from the header of the volume file. Gradients are divided by it
+    /// so that normals, and the lighting, of anisotropic volumes are not skewed. Only the ratios matter, the default is 1
+    pub fn set_voxel_spacing(&mut self, spacing: Vector3<f32>, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.voxel_spacing = spacing.map(|s| s.max(f32::EPSILON));
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn opacity_scale(&self) -> f32 {
+        self.uniforms.opacity_scale
+    }
+
+    /// Scales the opacity of the transfer function, or of an RGBA volume, without touching its colors,
+    /// e.g. above 1 for thin and below 1 for dense data. The scaled opacity is clamped to 1 and corrected
+    /// for the step size like the original, so the result does not depend on the step size. The default is 1
+    pub fn set_opacity_scale(&mut self, scale: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.opacity_scale = scale.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn face_buffer_view(&self) -> FaceBufferView {
+        match self.uniforms.face_buffer_view {
+            1 => FaceBufferView::FrontFaces,
+            2 => FaceBufferView::BackFaces,
+            _ => FaceBufferView::Off,
+        }
+    }
+
+    /// Shows the volume texture coordinates in the front-face or back-face buffer as colors instead of the volume.
+    /// Wrong colors point at the cube geometry or the camera, right ones at the ray marching
+    pub fn set_face_buffer_view(&mut self, view: FaceBufferView, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.face_buffer_view = view as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        match self.uniforms.interpolation {
+            1 => Interpolation::Tricubic,
+            _ => Interpolation::Trilinear,
+        }
+    }
+
+    /// Switches the reconstruction of the volume between voxels, only the uniform buffer is updated.
+    /// Tricubic interpolation combines linearly filtered samples, so it needs the volume sampler to filter linearly
+    pub fn set_interpolation(&mut self, interpolation: Interpolation, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.interpolation = interpolation as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Cuts away the part of the volume behind a plane, `normal` and `distance` are in volume texture coordinates
+    /// ([0, 1]³) and samples with `dot(normal, position) < distance` are skipped
+    pub fn set_clip_plane(&mut self, normal: Vector3<f32>, distance: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.clip_plane_enabled = 1;
+        uniforms.clip_plane = normal.normalize().extend(distance);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Renders the whole volume again after [`CanvasPass::set_clip_plane`]
+    pub fn disable_clip_plane(&mut self, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.clip_plane_enabled = 0;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Skips samples closer to the camera than `near` or farther than `far`, both are view depths in world units,
+    /// i.e. distances along the viewing direction. Unlike the clip plane, the slab follows the camera
+    pub fn set_ray_range(&mut self, near: f32, far: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.ray_near = near.max(0.0);
+        uniforms.ray_far = far.max(uniforms.ray_near);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Marches the whole rays again after [`CanvasPass::set_ray_range`]
+    pub fn disable_ray_range(&mut self, queue: &Queue) {
+        self.set_ray_range(0.0, f32::MAX, queue);
+    }
+
+    /// Camera the view depths of [`CanvasPass::set_ray_range`] are measured from, the camera moving does not count
+    /// as a change of [`CanvasPass::revision`]
+    pub fn update_view_depth_plane(
+        &mut self,
+        model_transformation: Matrix4<f32>,
+        camera: &Camera,
+        queue: &Queue,
+    ) {
+        self.uniforms
+            .update_view_depth_plane(camera, model_transformation);
+        queue.write_buffer(&self.uniform_buffer, 0, &self.uniforms.as_std140_bytes());
+    }
+
+    /// Restricts rendering to an axis-aligned box given by its corners in volume texture coordinates,
+    /// both are clamped to [0, 1]³. The whole unit cube renders the whole volume
+    pub fn set_region_of_interest(&mut self, min: Vector3<f32>, max: Vector3<f32>, queue: &Queue) {
+        let clamp = |v: Vector3<f32>| v.map(|c| c.clamp(0.0, 1.0));
+        let mut uniforms = self.uniforms;
+        uniforms.roi_min = clamp(min);
+        uniforms.roi_max = clamp(max);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Window/level (contrast) of the scalar field: scalars in `[center - width / 2, center + width / 2]`
+    /// are stretched over the whole transfer function, the ones outside are clamped to its ends.
+    /// The default center 0.5 and width 1 leave the scalars unchanged
+    pub fn set_window_level(&mut self, center: f32, width: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.window_center = center;
+        uniforms.window_width = width.max(f32::EPSILON);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn scalar_gamma(&self) -> f32 {
+        self.uniforms.scalar_gamma
+    }
+
+    /// Raises the windowed scalars to `gamma` before the transfer function lookup,
+    /// below 1 emphasizes low intensities and above 1 high ones. The default 1 leaves them unchanged
+    pub fn set_scalar_gamma(&mut self, gamma: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.scalar_gamma = gamma.max(f32::EPSILON);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn scalar_log_scale(&self) -> bool {
+        self.uniforms.scalar_log_scale != 0
+    }
+
+    /// Remaps the windowed scalars logarithmically before the gamma, which spreads three decades
+    /// of a volume with a huge dynamic range over the transfer function
+    pub fn set_scalar_log_scale(&mut self, enabled: bool, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.scalar_log_scale = enabled as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Strength and radius of the ambient occlusion, see [`CanvasPass::set_ambient_occlusion`]
+    pub fn ambient_occlusion(&self) -> (f32, f32) {
+        (self.uniforms.ao_strength, self.uniforms.ao_radius)
+    }
+
+    /// Darkens the ambient and diffuse light of samples in cavities, which reads depth far better than Phong shading alone.
+    /// A few short rays per sample look for material within `radius` in volume texture coordinates on the open side of the gradient,
+    /// `strength` in [0, 1] blends the occlusion in and 0, the default, disables it
+    pub fn set_ambient_occlusion(&mut self, strength: f32, radius: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.ao_strength = strength.clamp(0.0, 1.0);
+        uniforms.ao_radius = radius.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Lights the volume from a fixed direction in volume texture coordinates, independent of the camera.
+    /// `direction` is the direction the light travels, the gradient serves as the surface normal
+    pub fn set_light_direction(&mut self, direction: Vector3<f32>, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.light_direction = direction.normalize();
+        uniforms.headlight = 0;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// With the headlight, which is the default, the light travels along the viewing rays and follows the camera
+    pub fn set_headlight(&mut self, enabled: bool, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.headlight = enabled as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Weight of the ambient term of the Phong shading, which lights every sample regardless of its normal
+    pub fn set_ambient(&mut self, ambient: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.ambient_intensity = ambient.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Weight of the diffuse term of the Phong shading
+    pub fn set_diffuse(&mut self, diffuse: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.diffuse_intensity = diffuse.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Weight of the specular term of the Phong shading
+    pub fn set_specular(&mut self, specular: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.specular_intensity = specular.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Exponent of the specular term, higher values give smaller and sharper highlights
+    pub fn set_shininess(&mut self, shininess: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.shininess = shininess.max(1.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.uniforms.exposure
+    }
+
+    /// Multiplies the composited color of the volume before tone mapping, the background is not affected
+    pub fn set_exposure(&mut self, exposure: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.exposure = exposure.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn tonemap(&self) -> ToneMapping {
+        match self.uniforms.tonemap_mode {
+            1 => ToneMapping::Reinhard,
+            2 => ToneMapping::Aces,
+            _ => ToneMapping::None,
+        }
+    }
+
+    /// Switches the operator applied to the composited color after the exposure, only the uniform buffer is updated
+    pub fn set_tonemap(&mut self, tonemap: ToneMapping, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.tonemap_mode = tonemap as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Caps the samples per ray, rays longer than `max_steps` steps end early instead of stalling the GPU
+    pub fn set_max_steps(&mut self, max_steps: u32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.max_steps = max_steps.max(1);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Offsets the start of every ray by a per-pixel fraction of the step size.
+    /// This trades the wood-grain banding of large steps for fine noise, which is far less visible
+    pub fn set_jitter_enabled(&mut self, enabled: bool, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.jitter = enabled as u32;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn jitter_enabled(&self) -> bool {
+        self.uniforms.jitter != 0
+    }
+
+    /// Samples coarser mip levels the deeper the ray marches into the volume, which smooths distant
+    /// detail and reads less memory. The mip level grows by `lod_per_distance` per unit of distance
+    /// in volume texture coordinates, 0 disables it.
+    /// Only has an effect with a mipmapped volume from [`Tex::create_3d_texture_red_f16_mipmapped`]
+    pub fn set_level_of_detail(&mut self, lod_per_distance: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.lod_per_distance = lod_per_distance.max(0.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    ///
+    /// Adapts the step length of the composite mode to the material along the ray
+    ///
+    /// Steps grow towards `max_step` where recent samples contributed little opacity and the scalar barely changes,
+    /// and shrink to `min_step` in visible material and at sharp transitions. Opacity is corrected for the actual
+    /// step length, so the composite and the early ray termination stay consistent with fixed steps.
+    /// Larger `max_step` saves samples in empty regions but may step over thin features in front of them,
+    /// a `min_step` around the fixed step size keeps the quality at the surfaces.
+    ///
+    pub fn set_adaptive_step(&mut self, min_step: f32, max_step: f32, queue: &Queue) {
+        assert!(
+            min_step > 0.0 && max_step >= min_step,
+            "Adaptive steps need 0 < min_step <= max_step"
+        );
+        let mut uniforms = self.uniforms;
+        uniforms.adaptive_min_step = min_step;
+        uniforms.adaptive_max_step = max_step;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Marches with the fixed step size again after [`CanvasPass::set_adaptive_step`]
+    pub fn disable_adaptive_step(&mut self, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.adaptive_max_step = 0.0;
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    pub fn uniforms(&self) -> &CanvasShaderUniforms {
+        &self.uniforms
+    }
+
+    ///
+    /// Marches the ray of pixel (`x`, `y`) of a `size` render target and returns where it hits the volume
+    /// in volume texture coordinates, `None` if it misses or passes through transparent material
+    ///
+    /// A composite ray hits where its accumulated opacity reaches [`CanvasPass::set_pick_opacity`],
+    /// an intensity projection where it finds its maximum scalar. The face buffers of the last render are used,
+    /// the ray is marched again into a separate target and read back, so this waits for the GPU.
+    ///
+    pub fn pick(
+        &self,
+        x: u32,
+        y: u32,
+        size: (u32, u32),
+        device: &Device,
+        queue: &Queue,
+    ) -> Option<Vector3<f32>> {
+        if x >= size.0 || y >= size.1 {
+            return None;
+        }
+        let target = device.create_texture(&TextureDescriptor {
+            label: Some("Canvas Pass Pick Target"),
+            size: Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: Self::PICK_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Canvas Pass Pick Readback Buffer"),
+            size: COPY_BYTES_PER_ROW_ALIGNMENT as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Canvas Pass Pick Encoder"),
+        });
+        {
+            let view = target.create_view(&TextureViewDescriptor::default());
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Canvas Pass Pick Render Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            // only the picked pixel is marched
+            render_pass.set_scissor_rect(x, y, 1, 1);
+            render_pass.set_pipeline(&self.pick_pipeline);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass
+                .set_index_buffer(self.index_buffer.slice(..), self.canvas.get_index_format());
+            render_pass.set_bind_group(0, &self.face_texture_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.volume_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.tf_bind_group, &[]);
+            render_pass.set_bind_group(3, &self.uniform_bind_group, &[]);
+            render_pass.draw_indexed(0..self.num_depth_indices, 0, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &target,
+                mip_level: 0,
+                origin: Origin3d { x, y, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        let slice = readback_buffer.slice(..);
+        slice.map_async(MapMode::Read, |_| {});
+        device.poll(Maintain::Wait);
+        let hit: [f16; 4] = {
+            let bytes = slice.get_mapped_range();
+            bytemuck::pod_read_unaligned(&bytes[..8])
+        };
+        readback_buffer.unmap();
+        let [x, y, z, hit] = hit.map(f16::to_f32);
+        (hit > 0.0).then(|| Vector3::new(x, y, z))
+    }
+
+    /// Composited opacity in (0, 1] at which [`CanvasPass::pick`] finds the hit of a composite ray
+    pub fn set_pick_opacity(&mut self, pick_opacity: f32, queue: &Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.pick_opacity = pick_opacity.clamp(f32::EPSILON, 1.0);
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Changes whenever the rendered image may change, e.g. with new uniforms or a new transfer function.
+    /// The frame index of [`CanvasPass::set_frame_index`] does not count
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Varies the jittered ray starts from frame to frame, so that the frames of [`AccumulationPass`] differ
+    pub fn set_frame_index(&mut self, frame_index: u32, queue: &Queue) {
+        self.uniforms.frame_index = frame_index;
+        queue.write_buffer(&self.uniform_buffer, 0, &self.uniforms.as_std140_bytes());
+    }
+
+    pub fn background_color(&self) -> Color {
+        self.background_color
+    }
+
+    /// Color behind the volume, transparent black by default.
+    /// The volume is composited over it, so it also shows through translucent material.
+    /// A gradient of [`CanvasPass::set_background`] covers it
+    pub fn set_background_color(&mut self, color: Color) {
+        self.background_color = color;
+        self.revision += 1;
+    }
+
+    /// Solid color or gradient behind the volume, the gradient is filled in by the shader
+    /// wherever the volume is not opaque
+    pub fn set_background(&mut self, style: BackgroundStyle, queue: &Queue) {
+        let premultiplied = |color: Color| {
+            let alpha = color.a as f32;
+            cgmath::Vector4::new(
+                color.r as f32 * alpha,
+                color.g as f32 * alpha,
+                color.b as f32 * alpha,
+                alpha,
+            )
+        };
+        let mut uniforms = self.uniforms;
+        match style {
+            BackgroundStyle::SolidColor(color) => {
+                self.background_color = color;
+                uniforms.background_mode = 0;
+            }
+            BackgroundStyle::VerticalGradient { top, bottom } => {
+                uniforms.background_mode = 1;
+                uniforms.background_top = premultiplied(top);
+                uniforms.background_bottom = premultiplied(bottom);
+            }
+        }
+        self.set_uniforms(&uniforms, queue);
+    }
+
+    /// Measures the duration of this pass on the GPU, `None` stops measuring
+    pub fn set_gpu_timer(&mut self, gpu_timer: Option<Arc<GpuTimer>>) {
+        self.gpu_timer = gpu_timer;
+    }
+
+    /// Uploads all uniforms, `opacity_threshold` is clamped to [0, 1]
+    pub fn set_uniforms(&mut self, uniforms: &CanvasShaderUniforms, queue: &Queue) {
+        self.revision += 1;
+        // the occupancy grid classifies the windowed and remapped scalars
+        let reclassify = (
+            uniforms.window_center,
+            uniforms.window_width,
+            uniforms.scalar_gamma,
+            uniforms.scalar_log_scale,
+        ) != (
+            self.uniforms.window_center,
+            self.uniforms.window_width,
+            self.uniforms.scalar_gamma,
+            self.uniforms.scalar_log_scale,
+        );
+        self.uniforms = uniforms.clone();
+        if !(0.0..=1.0).contains(&self.uniforms.opacity_threshold) {
+            log::warn!(
+                "opacity_threshold {} is outside of [0, 1], clamping it",
+                self.uniforms.opacity_threshold
+            );
+            self.uniforms.opacity_threshold = self.uniforms.opacity_threshold.clamp(0.0, 1.0);
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &self.uniforms.as_std140_bytes());
+        if reclassify {
+            self.reclassify_occupancy(queue);
+        }
+    }
+}
+
+impl RenderPass for CanvasPass {
+    fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.multisample_buffer = match self.multisample_buffer {
+            None => None,
+            Some(ref old_buffer) => Some(Tex::create_render_buffer(
+                (width, height),
+                device,
+                Some("Canvas Pass Multisample Buffer"),
+                NonZeroU32::new(self.sample_count).unwrap(),
+                &old_buffer.format,
+            )),
+        }
+    }
+
+    fn render(
+        &self,
+        render_into_view: &TextureView,
+        _depth_view: Option<&TextureView>,
+        encoder: &mut CommandEncoder,
+    ) {
+        let (view, resolve_target) = match self.multisample_buffer {
+            None => (render_into_view, None),
+            Some(ref multisample_buffer) => (&multisample_buffer.view, Some(render_into_view)),
+        };
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Canvas Pass"),
+            // color_attachments describe where we are going to draw our color to
+            color_attachments: &[Some(RenderPassColorAttachment {
+                //view informs wgpu what texture to save the colors to
+                view,
+                // The resolve_target is the texture that will receive the resolved output.
+                // This will be the same as `view` unless multisampling is enabled
+                resolve_target,
+                ops: Operations {
+                    // The load field tells wgpu how to handle colors stored from the previous frame
+                    // the volume is blended over the background
+                    load: LoadOp::Clear(self.background_color),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: self
+                .gpu_timer
+                .as_ref()
+                .map(|timer| timer.timestamp_writes()),
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+        // set_vertex_buffer takes two parameters.
+        // The first is what buffer slot to use for this vertex buffer.
+        // You can have multiple vertex buffers set at a time
+        // The second parameter is the slice of the buffer to use.
+        // You can store as many objects in a buffer as your hardware allows, so slice allows us to specify which portion of the buffer to use.
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), self.canvas.get_index_format());
+        render_pass.set_bind_group(0, &self.face_texture_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.volume_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.tf_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.uniform_bind_group, &[]);
+        render_pass.draw_indexed(0..self.num_depth_indices, 0, 0..1);
+    }
+}
+
+///
+/// Averages the frames of a still camera for progressive refinement
+///
+/// The canvas pass renders into [`AccumulationPass::frame_view`], this pass blends that frame into a running average
+/// and writes the average into the target. Combined with jittered ray starts the average converges to the image
+/// of a much smaller step size. Call [`AccumulationPass::advance`] once per frame before rendering
+/// and [`AccumulationPass::reset`] whenever the image changes.
+///
+pub struct AccumulationPass {
+    frame_texture: Tex,
+    // ping-pong pair, one holds the average so far while the other receives the new average
+    accumulation_textures: [Tex; 2],
+    bind_group_layout: BindGroupLayout,
+    // bind_groups[i] reads accumulation_textures[i] as history
+    bind_groups: [BindGroup; 2],
+    uniform_buffer: Buffer,
+    render_pipeline: RenderPipeline,
+    history: usize,   // index of the accumulation texture holding the average so far
+    frame_count: u32, // frames in the average after the next render
+}
+
+impl AccumulationPass {
+    const ACCUMULATION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+    pub fn new(
+        device: &Device,
+        render_width: u32,
+        render_height: u32,
+        target_format: &TextureFormat,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Accumulation Bind Group Layout"),
+            entries: &[
+                // the new frame and the average so far, read with textureLoad
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        // frame_weight padded to the 16 bytes of a uniform struct
+        let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Accumulation Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[1.0f32, 0.0, 0.0, 0.0]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let (frame_texture, accumulation_textures) =
+            Self::create_textures(device, (render_width, render_height), target_format);
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &frame_texture,
+            &accumulation_textures,
+            &uniform_buffer,
+        );
+
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Accumulation Pass Shaders"),
+            source: ShaderSource::Wgsl(include_str!("./shaders/accumulation.wgsl").into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Accumulation Render Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Accumulation Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "vertex_shader",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "fragment_shader",
+                compilation_options: Default::default(),
+                targets: &[
+                    Some(ColorTargetState {
+                        format: Self::ACCUMULATION_FORMAT,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                    Some(ColorTargetState {
+                        format: *target_format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }),
+                ],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        Self {
+            frame_texture,
+            accumulation_textures,
+            bind_group_layout,
+            bind_groups,
+            uniform_buffer,
+            render_pipeline,
+            history: 0,
+            frame_count: 0,
+        }
+    }
+
+    fn create_textures(
+        device: &Device,
+        size: (u32, u32),
+        target_format: &TextureFormat,
+    ) -> (Tex, [Tex; 2]) {
+        let single_sample = NonZeroU32::new(1).unwrap();
+        let frame_texture = Tex::create_render_buffer(
+            size,
+            device,
+            Some("Accumulation Frame Buffer"),
+            single_sample,
+            target_format,
+        );
+        let accumulation_textures = [0, 1].map(|_| {
+            Tex::create_render_buffer(
+                size,
+                device,
+                Some("Accumulation Buffer"),
+                single_sample,
+                &Self::ACCUMULATION_FORMAT,
+            )
+        });
+        (frame_texture, accumulation_textures)
+    }
+
+    fn create_bind_groups(
+        device: &Device,
+        layout: &BindGroupLayout,
+        frame_texture: &Tex,
+        accumulation_textures: &[Tex; 2],
+        uniform_buffer: &Buffer,
+    ) -> [BindGroup; 2] {
+        accumulation_textures.each_ref().map(|history| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Accumulation Bind Group"),
+                layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&frame_texture.view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&history.view),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+
+    /// The canvas pass renders the new frame into this view
+    pub fn frame_view(&self) -> &TextureView {
+        &self.frame_texture.view
+    }
+
+    /// Frames in the average, including the one of the next render
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Starts a new average, the next frame replaces the previous ones
+    pub fn reset(&mut self) {
+        self.frame_count = 0;
+    }
+
+    /// Adds the next frame to the average, call once per frame before [`RenderPass::render`]
+    pub fn advance(&mut self, queue: &Queue) {
+        if self.frame_count > 0 {
+            // the average written by the last frame becomes the history
+            self.history = 1 - self.history;
+        }
+        self.frame_count += 1;
+        let frame_weight = 1.0 / self.frame_count as f32;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[frame_weight]),
+        );
+    }
+}
+
+impl RenderPass for AccumulationPass {
+    fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        let target_format = self.frame_texture.format;
+        (self.frame_texture, self.accumulation_textures) =
+            Self::create_textures(device, (width, height), &target_format);
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.frame_texture,
+            &self.accumulation_textures,
+            &self.uniform_buffer,
+        );
+        self.reset();
+    }
+
+    fn render(
+        &self,
+        render_into_view: &TextureView,
+        _depth_view: Option<&TextureView>,
+        encoder: &mut CommandEncoder,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Accumulation Render Pass"),
+            color_attachments: &[
+                Some(RenderPassColorAttachment {
+                    view: &self.accumulation_textures[1 - self.history].view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: StoreOp::Store,
+                    },
+                }),
+                Some(RenderPassColorAttachment {
+                    view: render_into_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: StoreOp::Store,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_groups[self.history], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Whether the bounding box wireframe is drawn and how it is depth tested
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WireframeMode {
+    Hidden,
+    /// All edges are drawn over the volume
+    OnTop,
+    /// Only the edges in front, those behind the front faces of the bounding box are hidden.
+    /// The depth test is against the box, so the volume itself hides no edges
+    FrontEdges,
+}
+
+impl WireframeMode {
+    /// Cycles hidden -> on top -> front edges -> hidden
+    pub fn next(self) -> Self {
+        match self {
+            WireframeMode::Hidden => WireframeMode::OnTop,
+            WireframeMode::OnTop => WireframeMode::FrontEdges,
+            WireframeMode::FrontEdges => WireframeMode::Hidden,
+        }
+    }
+}
+
+///
+/// Draws the 12 edges of the volume bounding box over an already rendered image
+///
+/// Uses the cube of the face passes with line-list topology and the same model view projection,
+/// so the edges line up with the ray entry and exit positions.
+/// Renders without multisampling into the resolved target, keeping its content.
+/// A depth view passed to [`RenderPass::render`] is depth tested against and kept, otherwise an own one is cleared.
+///
+pub struct WireframePass {
+    depth_texture: Tex,
+    uniform_bind_group: BindGroup,
+    right_eye_uniform_bind_group: BindGroup,
+    vertex_buffer: Buffer,
+    face_index_buffer: Buffer,
+    edge_index_buffer: Buffer,
+    uniforms: Uniforms,
+    uniform_buffer: Buffer,
+    right_eye_uniform_buffer: Buffer,
+    stereo: bool, // left and right eye side by side
+    num_face_indices: u32,
+    num_edge_indices: u32,
+    depth_pipeline: RenderPipeline,
+    line_pipeline: RenderPipeline,
+    cube: Mesh3,
+    hide_back_edges: bool,
+}
+
+impl WireframePass {
+    // pairs of create_cube_fbo vertices: the 4 edges on z = 0.5, the 4 edges on z = -0.5 and the 4 edges between them
+    #[rustfmt::skip]
+    const EDGE_INDICES: [u16; 24] = [
+        0, 1, 1, 2, 2, 3, 3, 0,
+        4, 5, 5, 6, 6, 7, 7, 4,
+        0, 4, 1, 5, 2, 6, 3, 7,
+    ];
+
+    pub fn new(
+        device: &Device,
+        render_width: u32,
+        render_height: u32,
+        target_format: &TextureFormat,
+        camera: &Camera,
+        cube_transformation: Matrix4<f32>,
+    ) -> Self {
+        let cube = create_cube_fbo();
+        let depth_texture = Tex::create_depth_texture(
+            device,
+            render_width,
+            render_height,
+            NonZeroU32::new(1).unwrap(),
+            "Wireframe Depth Texture",
+        );
+        let mut uniforms = Uniforms::new();
+        uniforms.update_model_view_proj(camera, cube_transformation);
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Wireframe Uniform Bind Group Layout"),
+            });
+        let (uniform_buffer, uniform_bind_group) = create_uniform_bind_group(
+            device,
+            &uniform_bind_group_layout,
+            &uniforms,
+            "Wireframe Uniform Buffer",
+        );
+        let (right_eye_uniform_buffer, right_eye_uniform_bind_group) = create_uniform_bind_group(
+            device,
+            &uniform_bind_group_layout,
+            &uniforms,
+            "Wireframe Right Eye Uniform Buffer",
+        );
+
+        let vertex_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Wireframe Vertex Buffer"),
+            contents: cube.get_vertex_raw(),
+            usage: BufferUsages::VERTEX,
+        });
+        let face_index_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Wireframe Face Index Buffer"),
+            contents: cube.get_index_raw(),
+            usage: BufferUsages::INDEX,
+        });
+        let edge_index_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Wireframe Edge Index Buffer"),
+            contents: bytemuck::cast_slice(&Self::EDGE_INDICES),
+            usage: BufferUsages::INDEX,
+        });
+
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Wireframe Pass Shaders"),
+            source: ShaderSource::Wgsl(include_str!("./shaders/wireframe.wgsl").into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Wireframe Render Pipeline Layout"),
+            bind_group_layouts: &[&uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label: &str,
+                               topology: PrimitiveTopology,
+                               write_mask: ColorWrites,
+                               depth_write_enabled: bool,
+                               bias: DepthBiasState| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&render_pipeline_layout),
+                vertex: VertexState {
+                    module: &shader_module,
+                    entry_point: "vertex_shader",
+                    compilation_options: Default::default(),
+                    buffers: &[cube.vertex_desc()],
+                },
+                fragment: Some(FragmentState {
+                    module: &shader_module,
+                    entry_point: "fragment_shader",
+                    compilation_options: Default::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: *target_format,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology,
+                    strip_index_format: None,
+                    front_face: FrontFace::Ccw,
+                    // only the front faces occlude, lines are never culled
+                    cull_mode: Some(Face::Back),
+                    unclipped_depth: false,
+                    polygon_mode: PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(DepthStencilState {
+                    format: Tex::DEPTH_FORMAT,
+                    depth_write_enabled,
+                    depth_compare: CompareFunction::LessEqual,
+                    stencil: StencilState::default(),
+                    bias,
+                }),
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        // writes only the depth of the front faces, pushed back so the edges lying on them stay visible
+        let depth_pipeline = create_pipeline(
+            "Wireframe Depth Pipeline",
+            PrimitiveTopology::TriangleList,
+            ColorWrites::empty(),
+            true,
+            DepthBiasState {
+                constant: 2,
+                slope_scale: 2.0,
+                clamp: 0.0,
+            },
+        );
+        let line_pipeline = create_pipeline(
+            "Wireframe Line Pipeline",
+            PrimitiveTopology::LineList,
+            ColorWrites::ALL,
+            false,
+            DepthBiasState::default(),
+        );
+        Self {
+            depth_texture,
+            uniform_bind_group,
+            right_eye_uniform_bind_group,
+            vertex_buffer,
+            face_index_buffer,
+            edge_index_buffer,
+            uniforms,
+            uniform_buffer,
+            right_eye_uniform_buffer,
+            stereo: false,
+            num_face_indices: cube.get_num_indices() as u32,
+            num_edge_indices: Self::EDGE_INDICES.len() as u32,
+            depth_pipeline,
+            line_pipeline,
+            cube,
+            hide_back_edges: false,
+        }
+    }
+
+    pub fn update_model_view_proj_uniform(
+        &mut self,
+        model_transformation: Matrix4<f32>,
+        camera: &Camera,
+        queue: &Queue,
+    ) {
+        self.uniforms
+            .update_model_view_proj(camera, model_transformation);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            self.uniforms.as_std140().as_bytes(),
+        );
+        self.stereo = false;
+    }
+
+    /// Switches to stereo mode: the left eye is drawn into the left half of the target, the right eye into the right half.
+    /// The cameras should have half the aspect of the target, see [`Camera::stereo_eyes`]
+    pub fn update_stereo_model_view_proj_uniforms(
+        &mut self,
+        model_transformation: Matrix4<f32>,
+        left_eye: &Camera,
+        right_eye: &Camera,
+        queue: &Queue,
+    ) {
+        self.update_model_view_proj_uniform(model_transformation, left_eye, queue);
+        let mut right_eye_uniforms = Uniforms::new();
+        right_eye_uniforms.update_model_view_proj(right_eye, model_transformation);
+        queue.write_buffer(
+            &self.right_eye_uniform_buffer,
+            0,
+            right_eye_uniforms.as_std140().as_bytes(),
+        );
+        self.stereo = true;
+    }
+
+    fn eye_bind_groups(&self) -> Vec<&BindGroup> {
+        if self.stereo {
+            vec![&self.uniform_bind_group, &self.right_eye_uniform_bind_group]
+        } else {
+            vec![&self.uniform_bind_group]
+        }
+    }
+
+    /// Hides the edges behind the front faces of the bounding box (`true`) or draws all edges on top (`false`)
+    pub fn set_back_edges_hidden(&mut self, hidden: bool) {
+        self.hide_back_edges = hidden;
+    }
+}
+
+impl RenderPass for WireframePass {
+    fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.depth_texture = Tex::create_depth_texture(
+            device,
+            width,
+            height,
+            NonZeroU32::new(1).unwrap(),
+            "Wireframe Depth Texture",
+        );
+    }
+
+    fn render(
+        &self,
+        render_into_view: &TextureView,
+        external_depth_view: Option<&TextureView>,
+        encoder: &mut CommandEncoder,
+    ) {
+        let (depth_view, depth_ops) = match external_depth_view {
+            // the depth of what the caller rendered before hides the edges behind it and is kept for later passes
+            Some(view) => (
+                view,
+                Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            ),
+            None => (
+                &self.depth_texture.view,
+                Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: StoreOp::Discard,
+                },
+            ),
+        };
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Wireframe Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: render_into_view,
+                resolve_target: None,
+                ops: Operations {
+                    // the edges are drawn over the rendered volume
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(depth_ops),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        draw_per_eye(
+            &mut render_pass,
+            self.depth_texture.texture.size(),
+            &self.eye_bind_groups(),
+            |render_pass| {
+                if self.hide_back_edges {
+                    render_pass.set_pipeline(&self.depth_pipeline);
+                    render_pass.set_index_buffer(
+                        self.face_index_buffer.slice(..),
+                        self.cube.get_index_format(),
+                    );
+                    render_pass.draw_indexed(0..self.num_face_indices, 0, 0..1);
+                }
+                render_pass.set_pipeline(&self.line_pipeline);
+                render_pass.set_index_buffer(self.edge_index_buffer.slice(..), IndexFormat::Uint16);
+                render_pass.draw_indexed(0..self.num_edge_indices, 0, 0..1);
+            },
+        );
+    }
+}
+
+/// Volume axis a slice of [`SlicePass`] is orthogonal to, named after the usual patient orientation of the axes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SliceAxis {
+    /// Orthogonal to x, shows y to the right and z upwards
+    Sagittal = 0,
+    /// Orthogonal to y, shows x to the right and z upwards
+    Coronal = 1,
+    /// Orthogonal to z, shows x to the right and y upwards
+    Axial = 2,
+}
+
+///
+/// Draws an axis-aligned slice of the volume (multiplanar reconstruction) as grayscale into a viewport of the target
+///
+/// Samples the first channel of the volume bound to a [`CanvasPass`], reusing its volume bind group,
+/// so the slice follows volume changes of the canvas pass. Renders without multisampling, keeping the rest of the target.
+///
+pub struct SlicePass {
+    uniforms: SliceUniforms,
+    uniform_buffer: Buffer,
+    uniform_bind_group: BindGroup,
+    render_pipeline: RenderPipeline,
+}
+
+impl SlicePass {
+    /// Axial slice through the center of the volume of `canvas_pass`
+    pub fn new(device: &Device, canvas_pass: &CanvasPass, target_format: &TextureFormat) -> Self {
+        let uniforms = SliceUniforms {
+            axis: SliceAxis::Axial as u32,
+            position: 0.5,
+        };
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("Slice Uniform Bind Group Layout"),
+            });
+        let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+            label: Some("Slice Uniform Buffer"),
+            contents: uniforms.as_std140().as_bytes(),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            label: Some("Slice Uniform Bind Group"),
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Slice Pass Shaders"),
+            source: ShaderSource::Wgsl(include_str!("./shaders/slice.wgsl").into()),
+        });
+        let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Slice Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &canvas_pass.volume_bind_group_layout,
+                &uniform_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Slice Render Pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "vertex_shader",
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "fragment_shader",
+                compilation_options: Default::default(),
+                targets: &[Some(ColorTargetState {
+                    format: *target_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        Self {
+            uniforms,
+            uniform_buffer,
+            uniform_bind_group,
+            render_pipeline,
+        }
+    }
+
+    pub fn axis(&self) -> SliceAxis {
+        match self.uniforms.axis {
+            0 => SliceAxis::Sagittal,
+            1 => SliceAxis::Coronal,
+            _ => SliceAxis::Axial,
+        }
+    }
+
+    /// Position of the slice along its axis in volume texture coordinates
+    pub fn position(&self) -> f32 {
+        self.uniforms.position
+    }
+
+    /// Shows the slice orthogonal to `axis` at `position` in [0, 1] along it
+    pub fn set_slice(&mut self, axis: SliceAxis, position: f32, queue: &Queue) {
+        self.uniforms = SliceUniforms {
+            axis: axis as u32,
+            position: position.clamp(0.0, 1.0),
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            self.uniforms.as_std140().as_bytes(),
+        );
+    }
+
+    /// Draws the slice of the volume bound to `canvas_pass` into the `viewport` (x, y, width, height) in pixels
+    pub fn render(
+        &self,
+        canvas_pass: &CanvasPass,
+        render_into_view: &TextureView,
+        viewport: (f32, f32, f32, f32),
+        encoder: &mut CommandEncoder,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Slice Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: render_into_view,
+                resolve_target: None,
+                ops: Operations {
+                    // only the viewport is drawn over
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let (x, y, width, height) = viewport;
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &canvas_pass.volume_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+///
+/// Computes the gradients of a volume texture on the GPU, where the volume already is
+///
+/// Writes the same central differences as [`crate::utils::compute_gradients`] into an Rgba16Float texture
+/// for [`CanvasPass::with_gradients`], saving large volumes the slow CPU pass on load.
+/// Reads any float volume format with `textureLoad`, only the first mip level.
+/// Needs compute shaders, which WebGL 2 lacks. The OpenGL backend of wgpu writes only the first slice
+/// of 3D storage textures, compute the gradients on the CPU there.
+///
+pub struct GradientComputePass {
+    bind_group_layout: BindGroupLayout,
+    compute_pipeline: ComputePipeline,
+}
+
+impl GradientComputePass {
+    /// Edge length of the workgroup cubes, the same as in gradients.wgsl
+    const WORKGROUP_SIZE: u32 = 4;
+
+    pub fn new(device: &Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Gradient Compute Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D3,
+                        // R32Float volumes are not filterable everywhere, the shader does not filter anyway
+                        sample_type: TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::StorageTexture {
+                        access: StorageTextureAccess::WriteOnly,
+                        format: TextureFormat::Rgba16Float,
+                        view_dimension: TextureViewDimension::D3,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Gradient Compute Shader"),
+            source: ShaderSource::Wgsl(include_str!("./shaders/gradients.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Gradient Compute Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Gradient Compute Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: "compute_shader",
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Self {
+            bind_group_layout,
+            compute_pipeline,
+        }
+    }
+
+    /// Dispatches the shader over every voxel of `volume_texture` and submits it right away,
+    /// returns the gradient texture of the same size
+    pub fn compute(&self, volume_texture: &Tex, device: &Device, queue: &Queue) -> Tex {
+        let size = volume_texture.texture.size();
+        let gradient_texture = Tex::create_3d_storage_texture_rgba16f(&size, device, "Gradients");
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Gradient Compute Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&volume_texture.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&gradient_texture.view),
+                },
+            ],
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Gradient Compute Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Gradient Compute Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.compute_pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(Self::WORKGROUP_SIZE),
+                size.height.div_ceil(Self::WORKGROUP_SIZE),
+                size.depth_or_array_layers.div_ceil(Self::WORKGROUP_SIZE),
+            );
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        gradient_texture
+    }
+}
+
+#[cfg(test)]
+mod rendering_tests {
+    use super::*;
+    use crate::shading::VolumeSampling;
+    use crate::test_utils::{front_camera, request_device, CanvasFixture};
+    use cgmath::SquareMatrix;
+
+    fn extent_of(tex: &Tex) -> (u32, u32) {
+        let size = tex.texture.size();
+        (size.width, size.height)
+    }
+
+    #[test]
+    fn test_face_render_config() {
+        // the ray exit is the farthest back face
+        assert_eq!(
+            D3Pass::face_render_config(false),
+            (
+                Some(Face::Front),
+                CompareFunction::Greater,
+                LoadOp::Clear(0.0)
+            )
+        );
+        // the ray entry is the nearest face, back faces stand in for front faces clipped by the near plane
+        assert_eq!(
+            D3Pass::face_render_config(true),
+            (None, CompareFunction::Less, LoadOp::Clear(1.0))
+        );
+        assert_eq!(D3Pass::default_cull_mode(false), Some(Face::Front));
+        assert_eq!(D3Pass::default_cull_mode(true), None);
+    }
+
+    #[test]
+    fn test_entry_inside_volume() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        // entry positions are in [0, 1]³, so an 8-bit target is precise enough to read them back
+        let format = TextureFormat::Rgba8Unorm;
+        let size = (8, 8);
+        // the eye is inside the cube, whose front faces are all behind it
+        let camera = Camera {
+            eye: Point3::new(0.0, 0.0, 0.3),
+            center: Point3::new(0.0, 0.0, -1.0),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let front_face_pass = D3Pass::new(
+            &device,
+            size.0,
+            size.1,
+            &format,
+            FaceSelection::new(true),
+            &camera,
+            NonZeroU32::new(1).unwrap(),
+            Matrix4::identity(),
+        );
+        let target = Tex::create_readable_render_target(size, &device, None, &format);
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        front_face_pass.render(&target.view, None, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+        let image = target.read_rgba8_image(&device, &queue).unwrap();
+        // rays enter on the near plane in front of the eye at z = 0.3 - 0.1, i.e. 0.7 in volume coordinates
+        let [x, y, z, a] = image.get_pixel(4, 4).0;
+        assert_eq!(a, 255);
+        assert!(
+            (x as i32 - 128).abs() <= 8 && (y as i32 - 128).abs() <= 8,
+            "{:?}",
+            (x, y)
+        );
+        assert!((z as i32 - 179).abs() <= 2, "{}", z);
+    }
+
+    #[test]
+    fn test_reload_shader() {
+        let Some((_, device, _queue)) = request_device() else {
+            return;
+        };
+        let camera = Camera {
+            eye: Point3::new(0.0, -2.0, 0.0),
+            center: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let mut face_pass = D3Pass::new(
+            &device,
+            4,
+            4,
+            &TextureFormat::Rgba16Float,
+            FaceSelection::new(true),
+            &camera,
+            NonZeroU32::new(1).unwrap(),
+            Matrix4::identity(),
+        );
+        let shader_source = include_str!("./shaders/shader3d.wgsl");
+        assert!(face_pass.reload_shader(&device, shader_source).is_ok());
+        // a typo is reported and the pass keeps its pipeline
+        let broken_source = shader_source.replacen("fn vertex_shader", "fn vertex_shadr", 1);
+        assert!(face_pass.reload_shader(&device, &broken_source).is_err());
+    }
+
+    #[test]
+    fn test_stereo_eyes() {
+        let camera = Camera {
+            eye: Point3::new(0.0, -2.0, 0.0),
+            center: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_z(),
+            aspect: 2.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let (left, right) = camera.stereo_eyes(0.1);
+        assert!(((right.eye - left.eye).magnitude() - 0.1).abs() < 1e-6);
+        // looking along +y with z up, the right eye is at +x
+        assert!(right.eye.x > left.eye.x);
+        assert_eq!(left.center - left.eye, camera.center - camera.eye);
+        assert_eq!(right.center - right.eye, camera.center - camera.eye);
+        assert_eq!((left.aspect, right.aspect), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_orbit() {
+        let camera = Camera {
+            eye: Point3::new(0.0, -2.0, 1.0),
+            center: Point3::new(0.0, 0.0, 1.0),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        // a quarter turn counterclockwise around z moves the eye from -y to +x, keeping its height
+        let orbited = camera.orbit(90.0);
+        assert!((orbited.eye - Point3::new(2.0, 0.0, 1.0)).magnitude() < 1e-6);
+        assert_eq!(orbited.center, camera.center);
+        assert!((camera.orbit(360.0).eye - camera.eye).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn test_camera_looking_at() {
+        let camera = Camera::looking_at(
+            Point3::new(0.0, 0.0, 3.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_y(),
+            (1280, 720),
+        );
+        assert_eq!(camera.aspect, 1280.0 / 720.0);
+        assert_eq!(camera.fovy, Camera::default().fovy);
+        // a collapsed window keeps a finite aspect
+        let camera = Camera::looking_at(camera.eye, camera.center, camera.up, (0, 0));
+        assert_eq!(camera.aspect, 1.0);
+    }
+
+    #[test]
+    fn test_camera_matrices() {
+        let camera = Camera {
+            eye: Point3::new(0.0, -2.0, 1.0),
+            center: Point3::new(0.0, 0.0, 1.0),
+            up: Vector3::unit_z(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        assert_eq!(
+            camera.projection_matrix() * camera.view_matrix(),
+            camera.build_view_projection_matrix(Matrix4::identity())
+        );
+        // the center is 2 in front of the eye
+        let center = camera.view_matrix() * camera.center.to_homogeneous();
+        assert!((center - cgmath::Vector4::new(0.0, 0.0, -2.0, 1.0)).magnitude() < 1e-6);
+        let project = |point: Point3<f32>| {
+            let clip = camera.projection_matrix() * camera.view_matrix() * point.to_homogeneous();
+            clip.truncate() / clip.w
+        };
+        let ndc = project(camera.center);
+        assert!(ndc.x.abs() < 1e-6 && ndc.y.abs() < 1e-6);
+        // up is up in the image, the top of the field of view is at the top edge
+        let top = Point3::new(0.0, 0.0, 1.0 + 2.0 * 22.5f32.to_radians().tan());
+        assert!((project(top).y - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_non_square_render_targets() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        let format = TextureFormat::Rgba16Float;
+        let sample_count = NonZeroU32::new(4).unwrap();
+        let face_sample_count = NonZeroU32::new(1).unwrap();
+        let (width, height) = (1920, 400);
+        let camera = Camera {
+            eye: Point3::new(0.0, 0.0, 2.0),
+            center: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_y(),
+            aspect: width as f32 / height as f32,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let front_face_buffer =
+            Tex::create_render_buffer((width, height), &device, None, face_sample_count, &format);
+        let back_face_buffer =
+            Tex::create_render_buffer((width, height), &device, None, face_sample_count, &format);
+        let volume_extent = Extent3d {
+            width: 2,
+            height: 2,
+            depth_or_array_layers: 2,
+        };
+        let volume_texture = Tex::create_3d_texture_red_f16(
+            &volume_extent,
+            &vec![f16::ZERO; 8],
+            &device,
+            &queue,
+            "Volume",
+        );
+        let mut face_pass = D3Pass::new(
+            &device,
+            width,
+            height,
+            &format,
+            FaceSelection::new(true),
+            &camera,
+            sample_count,
+            Matrix4::identity(),
+        );
+        let mut canvas_pass = CanvasPass::new(
+            &front_face_buffer,
+            &back_face_buffer,
+            Arc::new(volume_texture),
+            &device,
+            &queue,
+            (width, height),
+            &format,
+            sample_count,
+        );
+        let mut wireframe_pass = WireframePass::new(
+            &device,
+            width,
+            height,
+            &format,
+            &camera,
+            Matrix4::identity(),
+        );
+        assert_eq!(extent_of(&face_pass.depth_texture), (width, height));
+        assert_eq!(extent_of(&wireframe_pass.depth_texture), (width, height));
+        assert_eq!(
+            extent_of(face_pass.multisample_buffer.as_ref().unwrap()),
+            (width, height)
+        );
+        assert_eq!(
+            extent_of(canvas_pass.multisample_buffer.as_ref().unwrap()),
+            (width, height)
+        );
+        // resizing to the transposed aspect recreates every buffer
+        face_pass.resize(&device, height, width);
+        canvas_pass.resize(&device, height, width);
+        wireframe_pass.resize(&device, height, width);
+        assert_eq!(extent_of(&face_pass.depth_texture), (height, width));
+        assert_eq!(extent_of(&wireframe_pass.depth_texture), (height, width));
+        assert_eq!(
+            extent_of(face_pass.multisample_buffer.as_ref().unwrap()),
+            (height, width)
+        );
+        assert_eq!(
+            extent_of(canvas_pass.multisample_buffer.as_ref().unwrap()),
+            (height, width)
+        );
+        // switching the sample count keeps the resized extent
+        let single_sample = NonZeroU32::new(1).unwrap();
+        face_pass.set_sample_count(&device, single_sample);
+        canvas_pass.set_sample_count(&device, (height, width), single_sample);
+        assert!(face_pass.multisample_buffer.is_none());
+        assert!(canvas_pass.multisample_buffer.is_none());
+        assert_eq!(extent_of(&face_pass.depth_texture), (height, width));
+        face_pass.set_sample_count(&device, sample_count);
+        assert_eq!(
+            extent_of(face_pass.multisample_buffer.as_ref().unwrap()),
+            (height, width)
+        );
+    }
+
+    #[test]
+    fn test_accumulation_average() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        let format = TextureFormat::Rgba8Unorm;
+        let size = (4, 4);
+        let target = Tex::create_readable_render_target(size, &device, None, &format);
+        let mut accumulation_pass = AccumulationPass::new(&device, size.0, size.1, &format);
+        // stands in for the canvas pass, every frame is a single color
+        let render_frame = |accumulation_pass: &AccumulationPass, color: Color| {
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: accumulation_pass.frame_view(),
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(color),
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            accumulation_pass.render(&target.view, None, &mut encoder);
+            queue.submit(std::iter::once(encoder.finish()));
+            target
+                .read_rgba8_image(&device, &queue)
+                .unwrap()
+                .get_pixel(1, 2)
+                .0
+        };
+        let red = Color {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        };
+        let blue = Color {
+            r: 0.0,
+            g: 0.0,
+            b: 1.0,
+            a: 1.0,
+        };
+        accumulation_pass.advance(&queue);
+        assert_eq!(render_frame(&accumulation_pass, red), [255, 0, 0, 255]);
+        accumulation_pass.advance(&queue);
+        let [r, g, b, a] = render_frame(&accumulation_pass, blue);
+        assert!(r.abs_diff(128) <= 1 && g == 0 && b.abs_diff(128) <= 1 && a == 255);
+        // a reset drops the previous frames
+        accumulation_pass.reset();
+        accumulation_pass.advance(&queue);
+        assert_eq!(accumulation_pass.frame_count(), 1);
+        assert_eq!(render_frame(&accumulation_pass, blue), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_pick() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        let format = TextureFormat::Rgba16Float;
+        let single_sample = NonZeroU32::new(1).unwrap();
+        let size = (64, 64);
+        // looking down the z axis onto the z = 0.5 face of the cube
+        let camera = Camera {
+            eye: Point3::new(0.0, 0.0, 2.0),
+            center: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        };
+        let front_face_buffer =
+            Tex::create_render_buffer(size, &device, None, single_sample, &format);
+        let back_face_buffer =
+            Tex::create_render_buffer(size, &device, None, single_sample, &format);
+        let face_pass = |render_front_face| {
+            D3Pass::new(
+                &device,
+                size.0,
+                size.1,
+                &format,
+                FaceSelection::new(render_front_face),
+                &camera,
+                single_sample,
+                Matrix4::identity(),
+            )
+        };
+        // a translucent RGBA volume, its opacity does not depend on a transfer function
+        let volume_texture = Tex::create_3d_texture_rgba16f(
+            &Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 2,
+            },
+            &[1.0, 1.0, 1.0, 0.3]
+                .repeat(8)
+                .into_iter()
+                .map(f16::from_f32)
+                .collect(),
+            &device,
+            &queue,
+            "Volume",
+        );
+        let mut canvas_pass = CanvasPass::new(
+            &front_face_buffer,
+            &back_face_buffer,
+            Arc::new(volume_texture),
+            &device,
+            &queue,
+            size,
+            &format,
+            single_sample,
+        );
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        face_pass(true).render(&front_face_buffer.view, None, &mut encoder);
+        face_pass(false).render(&back_face_buffer.view, None, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let center = (size.0 / 2, size.1 / 2);
+        canvas_pass.set_pick_opacity(0.1, &queue);
+        let near_hit = canvas_pass
+            .pick(center.0, center.1, size, &device, &queue)
+            .unwrap();
+        // the ray enters at the center of the z = 1 face
+        assert!((near_hit.x - 0.5).abs() < 0.05 && (near_hit.y - 0.5).abs() < 0.05);
+        assert!(near_hit.z > 0.99 && near_hit.z <= 1.0);
+        // a higher opacity is reached deeper in the volume
+        canvas_pass.set_pick_opacity(0.9, &queue);
+        let far_hit = canvas_pass
+            .pick(center.0, center.1, size, &device, &queue)
+            .unwrap();
+        assert!(far_hit.z < near_hit.z);
+        // the corners of the target miss the cube
+        assert_eq!(canvas_pass.pick(0, 0, size, &device, &queue), None);
+        // the ray terminates early before it becomes fully opaque
+        canvas_pass.set_pick_opacity(1.0, &queue);
+        assert_eq!(
+            canvas_pass.pick(center.0, center.1, size, &device, &queue),
+            None
+        );
+    }
+
+    #[test]
+    fn test_label_overlay() {
+        // varying along x, so that the normals are defined where the rays enter
+        let Some(fixture) = CanvasFixture::new(
+            [0.5, 1.0, 0.5, 1.0, 0.5, 1.0, 0.5, 1.0],
+            (16, 16),
+            Some(&front_camera()),
+        ) else {
+            return;
+        };
+        let (device, queue) = (&fixture.device, &fixture.queue);
+        // only the upper half of the volume is labeled
+        let label_texture = Tex::create_3d_texture_label_u8(
+            &fixture.volume_texture.texture.size(),
+            &vec![0, 0, 1, 1, 0, 0, 1, 1],
+            device,
+            queue,
+            "Labels",
+        );
+        let palette = vec![
+            cgmath::Vector4::new(0, 0, 0, 0),
+            cgmath::Vector4::new(0, 255, 0, 255),
+        ];
+        let mut canvas_pass =
+            fixture
+                .canvas_pass()
+                .with_labels(&label_texture, &palette, device, queue);
+        // white and opaque without shading, so the label color shows as it is
+        canvas_pass.update_transfer_function_2d(
+            1,
+            1,
+            &[cgmath::Vector4::new(255, 255, 255, 255)],
+            device,
+            queue,
+        );
+        canvas_pass.set_ambient(1.0, queue);
+        canvas_pass.set_diffuse(0.0, queue);
+        canvas_pass.set_specular(0.0, queue);
+        let render = |canvas_pass: &CanvasPass| {
+            fixture.render(|view, encoder| canvas_pass.render(view, None, encoder))
+        };
+        // y points up in the volume and down in the image
+        let image = render(&canvas_pass);
+        let [r, g, _, _] = image.get_pixel(8, 4).0;
+        assert!(r < 16 && g > 240, "{:?}", (r, g));
+        let [r, g, _, _] = image.get_pixel(8, 12).0;
+        assert!(r > 240 && g > 240, "{:?}", (r, g));
+        // binding another volume removes the overlay
+        canvas_pass.change_bound_volume_textures(
+            device,
+            queue,
+            fixture.volume_texture.clone(),
+            None,
+            None,
+        );
+        let [r, g, _, _] = render(&canvas_pass).get_pixel(8, 4).0;
+        assert!(r > 240 && g > 240, "{:?}", (r, g));
+    }
+
+    #[test]
+    fn test_opacity_correction() {
+        let Some(fixture) = CanvasFixture::new(
+            [0.5, 1.0, 0.5, 1.0, 0.5, 1.0, 0.5, 1.0],
+            (16, 16),
+            Some(&front_camera()),
+        ) else {
+            return;
+        };
+        let mut canvas_pass = fixture.canvas_pass();
+        // faint enough that the ray through the volume stays far from opaque
+        canvas_pass.update_transfer_function_2d(
+            1,
+            1,
+            &[cgmath::Vector4::new(255, 255, 255, 2)],
+            &fixture.device,
+            &fixture.queue,
+        );
+        let mut alpha_at_step = |step_size: f32| {
+            let mut uniforms = *canvas_pass.uniforms();
+            uniforms.step_size = step_size;
+            uniforms.base_distance = 0.01;
+            canvas_pass.set_uniforms(&uniforms, &fixture.queue);
+            fixture
+                .render(|view, encoder| canvas_pass.render(view, None, encoder))
+                .get_pixel(8, 8)
+                .0[3] as i32
+        };
+        // without the correction, half the step would composite twice the samples, about 200 instead of 140
+        let coarse = alpha_at_step(0.01);
+        let fine = alpha_at_step(0.005);
+        assert!(coarse > 100 && coarse < 180, "{}", coarse);
+        assert!((coarse - fine).abs() <= 6, "{:?}", (coarse, fine));
+    }
+
+    #[test]
+    fn test_background_gradient() {
+        // without rendered faces every ray is empty and only the background remains
+        let Some(fixture) = CanvasFixture::new([0.0; 8], (4, 16), None) else {
+            return;
+        };
+        let mut canvas_pass = fixture.canvas_pass();
+        let render = |canvas_pass: &CanvasPass| {
+            fixture.render(|view, encoder| canvas_pass.render(view, None, encoder))
+        };
+        canvas_pass.set_background(
+            BackgroundStyle::VerticalGradient {
+                top: Color::RED,
+                bottom: Color::BLUE,
+            },
+            &fixture.queue,
+        );
+        let image = render(&canvas_pass);
+        let [r, g, b, a] = image.get_pixel(1, 0).0;
+        assert!(r > 240 && g == 0 && b < 16 && a == 255);
+        let [r, g, b, a] = image.get_pixel(1, fixture.size.1 - 1).0;
+        assert!(r < 16 && g == 0 && b > 240 && a == 255);
+        // a solid color is the clear color again
+        canvas_pass.set_background(BackgroundStyle::SolidColor(Color::GREEN), &fixture.queue);
+        assert_eq!(render(&canvas_pass).get_pixel(1, 0).0, [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_slice_pass() {
+        // 0 at x = 0 and 1 at x = 1, constant along y and z
+        let Some(fixture) =
+            CanvasFixture::new([0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0], (16, 16), None)
+        else {
+            return;
+        };
+        let canvas_pass = fixture.canvas_pass();
+        let mut slice_pass = SlicePass::new(&fixture.device, &canvas_pass, &fixture.format);
+        let render = |slice_pass: &SlicePass| {
+            // the right half of the target
+            fixture.render(|view, encoder| {
+                slice_pass.render(&canvas_pass, view, (8.0, 0.0, 8.0, 16.0), encoder)
+            })
+        };
+        // the axial slice shows x to the right
+        let image = render(&slice_pass);
+        assert_eq!(image.get_pixel(4, 8).0, [0, 0, 0, 0]);
+        let left = image.get_pixel(8, 8).0;
+        let right = image.get_pixel(15, 8).0;
+        assert!(left[0] < 16 && right[0] > 240 && right[3] == 255);
+        // the sagittal slice at x = 1 is constant
+        slice_pass.set_slice(SliceAxis::Sagittal, 1.0, &fixture.queue);
+        assert_eq!(slice_pass.axis(), SliceAxis::Sagittal);
+        let image = render(&slice_pass);
+        assert!(image.get_pixel(8, 8).0[0] > 240 && image.get_pixel(15, 15).0[0] > 240);
+    }
+
+    #[test]
+    fn test_volume_texture_too_large() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        // one voxel longer than the device supports fails instead of panicking
+        let width = device.limits().max_texture_dimension_3d + 1;
+        let created = Tex::create_volume_texture(
+            &Extent3d {
+                width,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            &vec![0.0; width as usize],
+            TextureFormat::R16Float,
+            VolumeSampling::default(),
+            &device,
+            &queue,
+            "Volume",
+        );
+        assert!(created.is_err());
+        let created = Tex::create_volume_texture(
+            &Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 2,
+            },
+            &[0.0; 8],
+            TextureFormat::R16Float,
+            VolumeSampling::default(),
+            &device,
+            &queue,
+            "Volume",
+        );
+        assert!(created.is_ok());
+    }
+
+    #[test]
+    fn test_wireframe_external_depth() {
+        let Some(fixture) = CanvasFixture::new([0.0; 8], (16, 16), None) else {
+            return;
+        };
+        let (width, height) = fixture.size;
+        let wireframe_pass = WireframePass::new(
+            &fixture.device,
+            width,
+            height,
+            &fixture.format,
+            &front_camera(),
+            Matrix4::identity(),
+        );
+        // everything in front of the box was rendered already, so no edge passes the depth test
+        let depth_texture = Tex::create_depth_texture(
+            &fixture.device,
+            width,
+            height,
+            NonZeroU32::MIN,
+            "External Depth Texture",
+        );
+        let occluded = fixture.render(|view, encoder| {
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Depth Clear Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &depth_texture.view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(0.0),
+                        store: StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            wireframe_pass.render(view, Some(&depth_texture.view), encoder);
+        });
+        assert!(occluded.pixels().all(|pixel| pixel.0 == [0, 0, 0, 0]));
+        // the own depth texture is cleared every frame
+        let drawn = fixture.render(|view, encoder| wireframe_pass.render(view, None, encoder));
+        assert!(drawn.pixels().any(|pixel| pixel.0[3] > 0));
+    }
+
+    #[test]
+    fn test_r32_float_volume_without_filtering() {
+        let voxels = [0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0];
+        let Some(mut fixture) = CanvasFixture::new(voxels, (16, 16), Some(&front_camera())) else {
+            return;
+        };
+        // the fixture requests no features, so R32Float is not filterable
+        assert!(!fixture
+            .device
+            .features()
+            .contains(Features::FLOAT32_FILTERABLE));
+        fixture.volume_texture = Arc::new(
+            Tex::create_volume_texture(
+                &Extent3d {
+                    width: 2,
+                    height: 2,
+                    depth_or_array_layers: 2,
+                },
+                &voxels,
+                TextureFormat::R32Float,
+                VolumeSampling::default(),
+                &fixture.device,
+                &fixture.queue,
+                "Volume",
+            )
+            .unwrap(),
+        );
+        // a linear sampler in the non-filtering slot of the bind group fails validation
+        fixture.device.push_error_scope(ErrorFilter::Validation);
+        let canvas_pass = fixture.canvas_pass();
+        let image = fixture.render(|view, encoder| canvas_pass.render(view, None, encoder));
+        assert!(block_on(fixture.device.pop_error_scope()).is_none());
+        assert!(image.get_pixel(8, 8).0[3] > 0);
+    }
+
+    #[test]
+    fn test_gradient_compute_pass() {
+        let Some((adapter, device, queue)) = request_device() else {
+            return;
+        };
+        // 32 texels of 8 bytes fill the 256 bytes a row of a texture to buffer copy is aligned to
+        let dims = (32, 2, 2);
+        let size = Extent3d {
+            width: 32,
+            height: 2,
+            depth_or_array_layers: 2,
+        };
+        // multiples of 1/64 are exact in R16Float
+        let data: Vec<f32> = (0..128)
+            .map(|i| ((i * 37 + i / 64 * 11) % 64) as f32 / 64.0)
+            .collect();
+        let volume_texture = Tex::create_volume_texture(
+            &size,
+            &data,
+            TextureFormat::R16Float,
+            VolumeSampling::default(),
+            &device,
+            &queue,
+            "Volume",
+        )
+        .unwrap();
+        let gradient_texture =
+            GradientComputePass::new(&device).compute(&volume_texture, &device, &queue);
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Gradient Readback Buffer"),
+            size: 128 * 8,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            gradient_texture.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(256),
+                    rows_per_image: Some(2),
+                },
+            },
+            size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+        let slice = readback_buffer.slice(..);
+        slice.map_async(MapMode::Read, |_| {});
+        device.poll(Maintain::Wait);
+        let gradients: Vec<f16> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        // the same central differences as on the CPU, up to the rounding of the half floats
+        let expected = crate::utils::compute_gradients(&data, dims);
+        assert_eq!(gradients.len(), expected.len());
+        // the OpenGL backend of wgpu binds only the first slice of a 3D storage texture
+        let checked_voxels = if adapter.get_info().backend == Backend::Gl {
+            32 * 2
+        } else {
+            128
+        };
+        assert!(expected[..checked_voxels * 4]
+            .iter()
+            .any(|v| v.to_f32() != 0.0));
+        for (gpu, cpu) in gradients.iter().zip(&expected).take(checked_voxels * 4) {
+            assert!(
+                (gpu.to_f32() - cpu.to_f32()).abs() < 1e-2,
+                "{} != {}",
+                gpu,
+                cpu
+            );
+        }
     }
 }
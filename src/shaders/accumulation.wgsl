@@ -0,0 +1,32 @@
+struct AccumulationUniforms{
+    // weight of the new frame, 1 / number of frames in the average
+    frame_weight: f32,
+}
+
+@group(0) @binding(0) var frame_tex: texture_2d<f32>;
+@group(0) @binding(1) var history_tex: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> uniforms: AccumulationUniforms;
+
+struct FragmentOutput{
+    // the average so far, read as history by the next frame
+    @location(0) accumulated: vec4<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+// a single triangle covering the whole target
+@vertex
+fn vertex_shader(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32>{
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fragment_shader(@builtin(position) position: vec4<f32>) -> FragmentOutput{
+    let pixel = vec2<i32>(position.xy);
+    let frame = textureLoad(frame_tex, pixel, 0);
+    let history = textureLoad(history_tex, pixel, 0);
+    var out: FragmentOutput;
+    out.accumulated = mix(history, frame, uniforms.frame_weight);
+    out.color = out.accumulated;
+    return out;
+}
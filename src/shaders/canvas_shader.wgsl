@@ -25,8 +25,103 @@ struct FragmentUniforms{
     diffuse: f32,
     specular: f32,
     shininess: f32,
+    min_opacity: f32,
+    silhouette_enhance: f32,
+    mask_threshold: f32,
+    render_mode: u32,
+    precomputed_gradient: u32,
+    occupancy_block_size: f32,
+    clip_plane_enabled: u32,
+    // xyz: normal, w: distance, in volume texture coordinates
+    clip_plane: vec4<f32>,
+    // region of interest in volume texture coordinates
+    roi_min: vec3<f32>,
+    roi_max: vec3<f32>,
+    // window/level applied to scalars before the transfer function lookup
+    window_center: f32,
+    window_width: f32,
+    // direction the light travels in volume texture coordinates, ignored with the headlight
+    light_direction: vec3<f32>,
+    headlight: u32,
+    // the volume stores emitted color and opacity instead of a scalar
+    rgba_volume: u32,
+    // mip level increase per unit of distance marched into the volume, 0 samples the full resolution
+    lod_per_distance: f32,
+    // bounds of the adaptive step length in the composite mode, a max of 0 marches with step_size
+    adaptive_min_step: f32,
+    adaptive_max_step: f32,
+    // look up tf_2d_tex by scalar and gradient magnitude instead of tf_tex by scalar
+    transfer_function_2d: u32,
+    // gradient magnitude per voxel mapped to the last row of tf_2d_tex
+    max_gradient_magnitude: f32,
+    interpolation: u32,
+    // offset the ray starts by a per-pixel fraction of the step size
+    jitter: u32,
+    // varies the jitter between the frames of the accumulation pass
+    frame_index: u32,
+    // composited opacity at which the ray hit is recorded for picking
+    pick_opacity: f32,
+    // xyz, w: view depth in world units of a position p in volume texture coordinates is dot(xyz, p) + w
+    view_depth_plane: vec4<f32>,
+    // rays are clamped to the view depths in [ray_near, ray_far]
+    ray_near: f32,
+    ray_far: f32,
+    // 1: the volume is composited over a vertical gradient from background_top to background_bottom
+    background_mode: u32,
+    // premultiplied colors
+    background_top: vec4<f32>,
+    background_bottom: vec4<f32>,
+    // hard bound of the ray marching loop whatever the step size
+    max_steps: u32,
+    // the composited color is scaled by exposure, then tone mapped according to tonemap_mode
+    exposure: f32,
+    tonemap_mode: u32,
+    // 1 if the colors of label_palette are blended in where label_data is non-zero
+    label_overlay: u32,
+    // 1 to look up preintegration_tex by the previous and the current sample instead of the 1D transfer function
+    preintegrated: u32,
+    // remap of the windowed scalars, optionally logarithmic, then raised to scalar_gamma
+    scalar_gamma: f32,
+    scalar_log_scale: u32,
+    // local ambient occlusion, see ambient_occlusion
+    ao_strength: f32,
+    ao_radius: f32,
+    // shows a face buffer instead of the volume for debugging, see FACE_BUFFER_VIEW_FRONT
+    face_buffer_view: u32,
+    // multiplies the classified opacity, the opacity correction applies to the scaled one
+    opacity_scale: f32,
+    // physical size of a voxel along each axis, for shading anisotropic volumes
+    voxel_spacing: vec3<f32>,
 }
 
+// scalar change per minimum step that counts as a sharp transition for the adaptive step
+const ADAPTIVE_EDGE_SCALE: f32 = 16.0;
+
+// steps along each of the ambient occlusion directions, which are spread over ao_radius
+const AO_STEPS: i32 = 4;
+
+// ratio of the largest to the smallest non-zero scalar the logarithmic remap spreads over [0, 1]
+const LOG_SCALE_RANGE: f32 = 1000.0;
+
+// values of FragmentUniforms.render_mode
+const RENDER_MODE_COMPOSITE: u32 = 0u;
+const RENDER_MODE_MAXIMUM_INTENSITY: u32 = 1u;
+const RENDER_MODE_AVERAGE_INTENSITY: u32 = 2u;
+
+// FragmentUniforms.interpolation, 0 is trilinear
+const INTERPOLATION_TRICUBIC: u32 = 1u;
+
+// FragmentUniforms.background_mode, 0 leaves the background to the clear color
+const BACKGROUND_VERTICAL_GRADIENT: u32 = 1u;
+
+// values of FragmentUniforms.tonemap_mode, 0 only applies the exposure
+const TONEMAP_REINHARD: u32 = 1u;
+const TONEMAP_ACES: u32 = 2u;
+
+// values of FragmentUniforms.face_buffer_view, 0 renders the volume
+const FACE_BUFFER_VIEW_FRONT: u32 = 1u;
+const FACE_BUFFER_VIEW_BACK: u32 = 2u;
+
 // textrues storing ray in and out positions
 @group(0) @binding(0) var front_face_tex: texture_2d<f32>;
 @group(0) @binding(1) var front_face_sampler: sampler;
@@ -36,64 +131,417 @@ struct FragmentUniforms{
 // volume data
 @group(1) @binding(0) var volume_data: texture_3d<f32>;
 @group(1) @binding(1) var volume_sampler: sampler;
+// mask volume restricting the rendered region
+@group(1) @binding(2) var mask_data: texture_3d<f32>;
+@group(1) @binding(3) var mask_sampler: sampler;
+// optional precomputed gradients, xyz is the normalized gradient and w its magnitude
+@group(1) @binding(4) var gradient_data: texture_3d<f32>;
+@group(1) @binding(5) var gradient_sampler: sampler;
+// optional segmentation, the single row of label_palette holds the sRGB decoded color of each label and alpha its weight
+@group(1) @binding(6) var label_data: texture_3d<u32>;
+@group(1) @binding(7) var label_palette: texture_2d<f32>;
 
 // tf: transfer function
 @group(2) @binding(0) var tf_tex: texture_1d<f32>;
 @group(2) @binding(1) var tf_sampler: sampler;
+// occupancy grid, a block is 0 if the transfer function maps all its scalars to zero opacity
+@group(2) @binding(2) var occupancy_data: texture_3d<f32>;
+// 2D transfer function, scalar along u and gradient magnitude along v
+@group(2) @binding(3) var tf_2d_tex: texture_2d<f32>;
+@group(2) @binding(4) var tf_2d_sampler: sampler;
+// pre-integrated 1D transfer function, front scalar along u and back scalar along v, sampled with tf_2d_sampler
+@group(2) @binding(5) var preintegration_tex: texture_2d<f32>;
 
 @group(3) @binding(0) var<uniform> uniforms: FragmentUniforms;
 
-fn sample_volume(position: vec3<f32>) -> f32{
-    return textureSample(volume_data, volume_sampler, position).r;
+// cubic B-spline interpolation from 8 trilinear samples instead of 64 voxel reads,
+// see Sigg and Hadwiger, "Fast Third-Order Texture Filtering", GPU Gems 2
+fn sample_volume_tricubic(position: vec3<f32>, lod: f32) -> vec4<f32>{
+    let level = min(u32(max(lod, 0.0)), textureNumLevels(volume_data) - 1u);
+    let size = vec3<f32>(textureDimensions(volume_data, level));
+    let coord = position * size - 0.5;
+    let index = floor(coord);
+    let f = coord - index;
+    // B-spline weights of the 4 voxels around the position along every axis
+    let w0 = (1.0 - f) * (1.0 - f) * (1.0 - f) / 6.0;
+    let w1 = 2.0 / 3.0 - 0.5 * f * f * (2.0 - f);
+    let w2 = 2.0 / 3.0 - 0.5 * (1.0 - f) * (1.0 - f) * (1.0 + f);
+    let w3 = f * f * f / 6.0;
+    // every pair of voxels becomes one linear sample in between them, weighted by their sum
+    let g0 = w0 + w1;
+    let g1 = w2 + w3;
+    let h0 = (index - 0.5 + w1 / g0) / size;
+    let h1 = (index + 1.5 + w3 / g1) / size;
+    let s000 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h0.x, h0.y, h0.z), lod);
+    let s100 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h1.x, h0.y, h0.z), lod);
+    let s010 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h0.x, h1.y, h0.z), lod);
+    let s110 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h1.x, h1.y, h0.z), lod);
+    let s001 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h0.x, h0.y, h1.z), lod);
+    let s101 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h1.x, h0.y, h1.z), lod);
+    let s011 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h0.x, h1.y, h1.z), lod);
+    let s111 = textureSampleLevel(volume_data, volume_sampler, vec3<f32>(h1.x, h1.y, h1.z), lod);
+    let s0 = g0.y * (g0.x * s000 + g1.x * s100) + g1.y * (g0.x * s010 + g1.x * s110);
+    let s1 = g0.y * (g0.x * s001 + g1.x * s101) + g1.y * (g0.x * s011 + g1.x * s111);
+    return g0.z * s0 + g1.z * s1;
+}
+
+fn fetch_volume(position: vec3<f32>, lod: f32) -> vec4<f32>{
+    if (uniforms.interpolation == INTERPOLATION_TRICUBIC){
+        return sample_volume_tricubic(position, lod);
+    }
+    return textureSampleLevel(volume_data, volume_sampler, position, lod);
+}
+
+// the scalar of RGBA volumes is their opacity
+fn sample_volume(position: vec3<f32>, lod: f32) -> f32{
+    let value = fetch_volume(position, lod);
+    return select(value.r, value.a, uniforms.rgba_volume != 0u);
+}
+
+fn sample_mask(position: vec3<f32>) -> f32{
+    return textureSample(mask_data, mask_sampler, position).r;
+}
+
+// samples behind the clip plane or outside of the region of interest are cut away
+fn is_clipped(position: vec3<f32>) -> bool{
+    let outside_roi = any(position < uniforms.roi_min) || any(position > uniforms.roi_max);
+    return outside_roi || (uniforms.clip_plane_enabled != 0u && dot(uniforms.clip_plane.xyz, position) < uniforms.clip_plane.w);
+}
+
+// maps [center - width / 2, center + width / 2] to [0, 1]
+fn apply_window(scalar: f32) -> f32{
+    let window_min = uniforms.window_center - uniforms.window_width / 2.0;
+    return clamp((scalar - window_min) / uniforms.window_width, 0.0, 1.0);
+}
+
+// emphasizes low scalars with a gamma below 1 or the logarithmic remap, high scalars with a gamma above 1
+fn remap_scalar(scalar: f32) -> f32{
+    var remapped = scalar;
+    if (uniforms.scalar_log_scale != 0u){
+        remapped = log(1.0 + remapped * LOG_SCALE_RANGE) / log(1.0 + LOG_SCALE_RANGE);
+    }
+    // pow is undefined at 0 on some backends
+    return select(pow(remapped, uniforms.scalar_gamma), 0.0, remapped <= 0.0);
 }
 
 fn sample_tf(scalar: f32) -> vec4<f32>{
     return textureSample(tf_tex, tf_sampler, scalar);
 }
 
+fn sample_tf_2d(scalar: f32, gradient_magnitude: f32) -> vec4<f32>{
+    let coord = vec2<f32>(scalar, gradient_magnitude / uniforms.max_gradient_magnitude);
+    return textureSampleLevel(tf_2d_tex, tf_2d_sampler, coord, 0.0);
+}
+
+// average color and opacity of the transfer function over the scalars of a ray segment
+fn sample_preintegrated(front_scalar: f32, back_scalar: f32) -> vec4<f32>{
+    return textureSampleLevel(preintegration_tex, tf_2d_sampler, vec2<f32>(front_scalar, back_scalar), 0.0);
+}
+
+// interleaved gradient noise in [0, 1), see Jimenez, "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+fn pixel_noise(pixel: vec2<f32>) -> f32{
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+// number of steps until the ray leaves the empty block containing position, 0 if the block is occupied
+fn empty_block_steps(position: vec3<f32>, ray_dir: vec3<f32>) -> i32{
+    let block_extent = uniforms.occupancy_block_size / vec3<f32>(textureDimensions(volume_data));
+    let grid_dims = vec3<i32>(textureDimensions(occupancy_data));
+    let block = clamp(vec3<i32>(floor(position / block_extent)), vec3<i32>(0), grid_dims - 1);
+    if (textureLoad(occupancy_data, block, 0).r > 0.5){
+        return 0;
+    }
+    // distance to the exit face of the block along every axis
+    let block_min = vec3<f32>(block) * block_extent;
+    let exit_face = select(block_min, block_min + block_extent, ray_dir > vec3<f32>(0.0));
+    let safe_dir = select(ray_dir, vec3<f32>(1e-6), abs(ray_dir) < vec3<f32>(1e-6));
+    let t = select((exit_face - position) / safe_dir, vec3<f32>(1e6), abs(ray_dir) < vec3<f32>(1e-6));
+    let t_exit = min(t.x, min(t.y, t.z));
+    return max(i32(ceil(t_exit / uniforms.step_size)), 1);
+}
+
+// palette color of the label of the voxel at position, transparent for label 0 and labels beyond the palette
+fn label_color(position: vec3<f32>) -> vec4<f32>{
+    let dims = vec3<i32>(textureDimensions(label_data));
+    let voxel = clamp(vec3<i32>(floor(position * vec3<f32>(dims))), vec3<i32>(0), dims - 1);
+    let label = textureLoad(label_data, voxel, 0).r;
+    if (label == 0u || label >= textureDimensions(label_palette).x){
+        return vec4<f32>(0.0);
+    }
+    return textureLoad(label_palette, vec2<i32>(i32(label), 0), 0);
+}
+
+// opacity of the material at position under the transfer function in use, for shadowing rather than shading
+fn sample_opacity(position: vec3<f32>, lod: f32) -> f32{
+    let scalar = remap_scalar(apply_window(sample_volume(position, lod)));
+    var opacity: f32;
+    if (uniforms.rgba_volume != 0u){
+        opacity = scalar;
+    } else if (uniforms.transfer_function_2d != 0u){
+        // homogeneous material, the row of the lowest gradient magnitude
+        opacity = sample_tf_2d(scalar, 0.0).a;
+    } else {
+        opacity = sample_tf(scalar).a;
+    }
+    return clamp(opacity * uniforms.opacity_scale, 0.0, 1.0);
+}
+
+// fraction of the ambient light reaching position, 1 if nothing is in the way
+// marches a few short rays into the hemisphere the gradient points away from and accumulates the opacity they meet
+fn ambient_occlusion(position: vec3<f32>, normal: vec3<f32>, lod: f32) -> f32{
+    // the gradient points into the material, the open side is the opposite one
+    let up = -normal;
+    let helper = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), abs(up.x) > 0.9);
+    let tangent = normalize(cross(up, helper));
+    let bitangent = cross(up, tangent);
+    var directions = array<vec3<f32>, 5>(
+        up,
+        normalize(up + tangent),
+        normalize(up - tangent),
+        normalize(up + bitangent),
+        normalize(up - bitangent),
+    );
+    let step = uniforms.ao_radius / f32(AO_STEPS);
+    var visibility = 0.0;
+    for (var d: i32 = 0; d < 5; d = d + 1){
+        var transmittance = 1.0;
+        for (var s: i32 = 1; s <= AO_STEPS; s = s + 1){
+            let opacity = sample_opacity(position + directions[d] * step * f32(s), lod);
+            transmittance = transmittance * pow(1.0 - opacity, step / uniforms.base_distance);
+        }
+        visibility = visibility + transmittance;
+    }
+    return mix(1.0, visibility / 5.0, clamp(uniforms.ao_strength, 0.0, 1.0));
+}
+
+struct RayResult{
+    color: vec4<f32>,
+    // volume texture coordinates of the first hit in xyz, w is 1 if the ray hit anything
+    hit_position: vec4<f32>,
+}
+
 // direct volume rendering shader
 @fragment
 fn fragment_shader(in : VertexOutput) -> @location(0) vec4<f32>{
+    // the raw ray entry or exit positions, transparent where the cube does not cover the pixel
+    if (uniforms.face_buffer_view == FACE_BUFFER_VIEW_FRONT){
+        return textureSample(front_face_tex, front_face_sampler, in.tex_coord);
+    } else if (uniforms.face_buffer_view == FACE_BUFFER_VIEW_BACK){
+        return textureSample(back_face_tex, back_face_sampler, in.tex_coord);
+    }
+    let color = tone_map(march_ray(in).color);
+    if (uniforms.background_mode == BACKGROUND_VERTICAL_GRADIENT){
+        // tex_coord.y is 0 at the top of the canvas
+        let background = mix(uniforms.background_top, uniforms.background_bottom, in.tex_coord.y);
+        return color + (1.0 - color.a) * background;
+    }
+    return color;
+}
+
+// applies the exposure and the tone mapping operator to the straight color, the background is left as it is
+fn tone_map(color: vec4<f32>) -> vec4<f32>{
+    if (color.a <= 0.0){
+        return color;
+    }
+    let x = color.rgb / color.a * uniforms.exposure;
+    var mapped: vec3<f32>;
+    switch uniforms.tonemap_mode {
+        case TONEMAP_REINHARD: {
+            mapped = x / (1.0 + x);
+        }
+        case TONEMAP_ACES: {
+            // Narkowicz's fit of the ACES filmic curve
+            mapped = clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        default: {
+            mapped = x;
+        }
+    }
+    return vec4<f32>(mapped * color.a, color.a);
+}
+
+// part [t_near, t_far] of the ray entry + t * ray_dir, t in [0, ray_length], between the view depths ray_near and ray_far
+fn view_depth_ray_range(entry: vec3<f32>, ray_dir: vec3<f32>, ray_length: f32) -> vec2<f32>{
+    let depth_per_length = dot(uniforms.view_depth_plane.xyz, ray_dir);
+    // without a view depth plane, or for empty rays whose direction is NaN, the ray stays as it is
+    if (!(depth_per_length > 0.0)){
+        return vec2<f32>(0.0, ray_length);
+    }
+    let entry_depth = dot(uniforms.view_depth_plane.xyz, entry) + uniforms.view_depth_plane.w;
+    let t_near = clamp((uniforms.ray_near - entry_depth) / depth_per_length, 0.0, ray_length);
+    let t_far = clamp((uniforms.ray_far - entry_depth) / depth_per_length, t_near, ray_length);
+    return vec2<f32>(t_near, t_far);
+}
+
+// renders the first hit positions instead of colors for CanvasPass::pick
+@fragment
+fn pick_fragment_shader(in : VertexOutput) -> @location(0) vec4<f32>{
+    return march_ray(in).hit_position;
+}
+
+// composite rays hit where their opacity reaches pick_opacity, intensity projections at their maximum scalar
+fn march_ray(in : VertexOutput) -> RayResult{
     let I_ambient = vec3<f32>(uniforms.ambient);
     let I_diffuse = vec3<f32>(uniforms.diffuse);
     let I_specular = vec3<f32>(uniforms.specular);
     let delta = uniforms.step_size / 2.0;
-    let start_volume_coord = textureSample(front_face_tex, front_face_sampler, in.tex_coord).rgb;
-    let end_volume_coord = textureSample(back_face_tex, back_face_sampler, in.tex_coord).rgb;
-    let ray_dir = normalize(end_volume_coord - start_volume_coord);
+    let entry_volume_coord = textureSample(front_face_tex, front_face_sampler, in.tex_coord).rgb;
+    let exit_volume_coord = textureSample(back_face_tex, back_face_sampler, in.tex_coord).rgb;
+    let ray_dir = normalize(exit_volume_coord - entry_volume_coord);
+    let ray_range = view_depth_ray_range(entry_volume_coord, ray_dir, length(exit_volume_coord - entry_volume_coord));
+    let start_volume_coord = entry_volume_coord + ray_dir * ray_range.x;
+    let end_volume_coord = entry_volume_coord + ray_dir * ray_range.y;
+    // physical size of the volume, normals and directions in volume texture coordinates are shaded in physical space
+    let physical_extent = vec3<f32>(textureDimensions(volume_data)) * uniforms.voxel_spacing;
+    let shading_ray_dir = normalize(ray_dir * physical_extent);
+    // the headlight travels along the viewing ray
+    let light_dir = select(normalize(uniforms.light_direction * physical_extent), shading_ray_dir, uniforms.headlight != 0u);
     var position:vec3<f32> = start_volume_coord;
+    if (uniforms.jitter != 0u){
+        // neighbouring pixels sample at different depths, which breaks up the banding of fixed steps
+        // the golden ratio spreads the offsets of consecutive frames evenly
+        let offset = fract(pixel_noise(in.clip_position.xy) + f32(uniforms.frame_index) * 0.618034);
+        position = position + ray_dir * uniforms.step_size * offset;
+    }
     var composite_color:vec4<f32> = vec4<f32>(0.0);
-    let max_marching_step = i32(length(end_volume_coord - start_volume_coord)/uniforms.step_size);
+    let ray_length = length(end_volume_coord - start_volume_coord);
+    let adaptive = uniforms.adaptive_max_step > 0.0 && uniforms.render_mode == RENDER_MODE_COMPOSITE;
+    // adaptive steps are never shorter than the minimum, the ray ends once it left the volume
+    // capped, as a tiny step size on a large volume would run long enough for the driver to reset the device
+    let max_marching_step = min(i32(ray_length / select(uniforms.step_size, uniforms.adaptive_min_step, adaptive)),
+                                i32(min(uniforms.max_steps, 0x7fffffffu)));
+    var step_length = uniforms.step_size;
+    var previous_scalar: f32 = -1.0;
+    // scalar of the previous composited sample for the pre-integrated segment, negative if there is none
+    var front_scalar: f32 = -1.0;
     let x_delta = vec3<f32>(delta, 0.0, 0.0);
     let y_delta = vec3<f32>(0.0, delta, 0.0);
     let z_delta = vec3<f32>(0.0, 0.0, delta);
+    var max_scalar: f32 = 0.0;
+    var hit_position = vec4<f32>(0.0);
+    var scalar_sum: f32 = 0.0;
+    var scalar_count: f32 = 0.0;
     for(var i:i32 = 0; i<max_marching_step; i = i+1){
-        let scalar = sample_volume(position);
-        let src = sample_tf(scalar);
-        let opacity = 1.0 - pow(1.0 - src.a, uniforms.step_size / uniforms.base_distance); // opacity correction
-        let new_src = vec4<f32>(src.rgb*opacity, opacity);
+        // farther samples cover more of the volume per pixel, so they read coarser mip levels
+        let lod = uniforms.lod_per_distance * distance(position, start_volume_coord);
+        let scalar = remap_scalar(apply_window(sample_volume(position, lod)));
+        if (adaptive && distance(position, start_volume_coord) > ray_length){
+            break;
+        }
+        let inside_mask = sample_mask(position) >= uniforms.mask_threshold && !is_clipped(position);
+        if (uniforms.render_mode != RENDER_MODE_COMPOSITE){
+            // intensity projections only track the scalar along the ray
+            if (inside_mask){
+                if (scalar > max_scalar){
+                    hit_position = vec4<f32>(position, 1.0);
+                }
+                max_scalar = max(max_scalar, scalar);
+                scalar_sum = scalar_sum + scalar;
+                scalar_count = scalar_count + 1.0;
+            }
+            position = position + ray_dir * uniforms.step_size;
+            continue;
+        }
+        if (uniforms.occupancy_block_size > 0.0){
+            // empty-space skipping
+            let skipped_steps = empty_block_steps(position, ray_dir);
+            if (skipped_steps > 0){
+                i = i + skipped_steps - 1;
+                position = position + ray_dir * uniforms.step_size * f32(skipped_steps);
+                front_scalar = -1.0;
+                continue;
+            }
+        }
         var normal : vec3<f32>;
-        normal.x = sample_volume(position + x_delta) - sample_volume(position - x_delta);
-        normal.y = sample_volume(position + y_delta) - sample_volume(position - y_delta);
-        normal.z = sample_volume(position + z_delta) - sample_volume(position - z_delta);
-        normal = normalize(normal);
-        let dir_dot_norm = dot(ray_dir, normal);
+        var gradient_magnitude: f32;
+        if (uniforms.precomputed_gradient != 0u){
+            let gradient = textureSample(gradient_data, gradient_sampler, position);
+            gradient_magnitude = gradient.w;
+            // the precomputed gradients are per voxel
+            normal = normalize(gradient.xyz / uniforms.voxel_spacing);
+        } else {
+            normal.x = sample_volume(position + x_delta, lod) - sample_volume(position - x_delta, lod);
+            normal.y = sample_volume(position + y_delta, lod) - sample_volume(position - y_delta, lod);
+            normal.z = sample_volume(position + z_delta, lod) - sample_volume(position - z_delta, lod);
+            // per voxel like the precomputed gradients, the samples are 2 * delta apart in texture coordinates
+            gradient_magnitude = length(normal / (2.0 * delta * vec3<f32>(textureDimensions(volume_data))));
+            // the differences are taken over the same texture coordinate distance, which spans unequal physical distances
+            normal = normalize(normal / physical_extent);
+        }
+        var src: vec4<f32>;
+        if (uniforms.rgba_volume != 0u){
+            // color and opacity come from the volume itself
+            src = fetch_volume(position, lod);
+            src.a = scalar;
+        } else if (uniforms.transfer_function_2d != 0u){
+            src = sample_tf_2d(scalar, gradient_magnitude);
+        } else if (uniforms.preintegrated != 0u && front_scalar >= 0.0){
+            // a thin feature of the transfer function between the samples still shows
+            src = sample_preintegrated(front_scalar, scalar);
+        } else {
+            src = sample_tf(scalar);
+        }
+        front_scalar = scalar;
+        if (uniforms.label_overlay != 0u){
+            // segmented voxels are tinted with their label color, keeping the opacity of the transfer function
+            let overlay = label_color(position);
+            src = vec4<f32>(mix(src.rgb, overlay.rgb, overlay.a), src.a);
+        }
+        // per base_distance like the transfer function, the opacity correction below adapts it to the step length
+        src.a = clamp(src.a * uniforms.opacity_scale, 0.0, 1.0);
+        src.a = select(src.a, 0.0, src.a < uniforms.min_opacity); // peel away low-opacity material
+        src.a = select(src.a, 0.0, !inside_mask); // outside of mask or clipped
+        if (adaptive){
+            // long steps through transparent and homogeneous regions, the minimum step near material and sharp transitions
+            let scalar_change = select(abs(scalar - previous_scalar) * uniforms.adaptive_min_step / step_length, 0.0, previous_scalar < 0.0);
+            let activity = clamp(max(src.a, scalar_change * ADAPTIVE_EDGE_SCALE), 0.0, 1.0);
+            step_length = mix(uniforms.adaptive_max_step, uniforms.adaptive_min_step, sqrt(activity));
+            previous_scalar = scalar;
+        }
+        // opacity correction for the length of the segment this sample stands for
+        var opacity = 1.0 - pow(1.0 - src.a, step_length / uniforms.base_distance);
+        if (uniforms.silhouette_enhance > 0.0 && gradient_magnitude > 0.0){
+            // emphasize contours where the surface is seen edge-on
+            opacity = opacity * pow(1.0 - abs(dot(normal, shading_ray_dir)), uniforms.silhouette_enhance);
+        }
+        let new_src = vec4<f32>(src.rgb*opacity, opacity);
+        let dir_dot_norm = dot(light_dir, normal);
         var specular_color : vec3<f32> = vec3<f32>(0.0);
         var diffuse_color : vec3<f32> = vec3<f32>(0.0);
         if(dir_dot_norm > 0.0){
             diffuse_color = dir_dot_norm * I_diffuse;
             let v = normalize(-position);
-            let r = reflect(-ray_dir, normal);
+            let r = reflect(-light_dir, normal);
             let r_dot_v = max(dot(r, v), 0.0);
             let pf = pow(r_dot_v, uniforms.shininess);
             specular_color = I_specular * pf;
         }
-        let final_color = vec4<f32>(I_ambient + diffuse_color + specular_color, 1.0)* new_src;
+        var ambient_visibility = 1.0;
+        if (uniforms.ao_strength > 0.0 && opacity > 0.0 && gradient_magnitude > 0.0){
+            // cavities get less of the ambient and diffuse light
+            ambient_visibility = ambient_occlusion(position, normal, lod);
+        }
+        let final_color = vec4<f32>((I_ambient + diffuse_color) * ambient_visibility + specular_color, 1.0)* new_src;
         composite_color = (1.0 - composite_color.a) * final_color + composite_color; // front-to-back compositing
+        if (hit_position.w == 0.0 && composite_color.a >= uniforms.pick_opacity){
+            hit_position = vec4<f32>(position, 1.0);
+        }
+        // early ray termination, samples behind an almost opaque composite barely contribute
         if (composite_color.a > uniforms.opacity_threshold){
-            break; // early ray termination
+            break;
         }
-        position = position + ray_dir * uniforms.step_size;
+        position = position + ray_dir * step_length;
+    }
+    // rays that miss the volume stay transparent
+    let hit = select(0.0, 1.0, max_marching_step > 0);
+    var result: RayResult;
+    result.hit_position = hit_position;
+    if (uniforms.render_mode == RENDER_MODE_MAXIMUM_INTENSITY){
+        result.color = vec4<f32>(vec3<f32>(max_scalar), hit);
+    } else if (uniforms.render_mode == RENDER_MODE_AVERAGE_INTENSITY){
+        result.color = vec4<f32>(vec3<f32>(scalar_sum / max(scalar_count, 1.0)), hit);
+    } else {
+        result.color = composite_color;
     }
-    return composite_color;
+    return result;
 }
\ No newline at end of file
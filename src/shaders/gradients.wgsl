@@ -0,0 +1,31 @@
+// edge length of the workgroup cubes the volume is dispatched in, WORKGROUP_SIZE of GradientComputePass
+const WORKGROUP_SIZE: u32 = 4u;
+
+@group(0) @binding(0) var volume_tex: texture_3d<f32>;
+@group(0) @binding(1) var gradient_tex: texture_storage_3d<rgba16float, write>;
+
+// central differences in voxel units, one-sided at the borders like compute_gradients of utils.rs
+@compute @workgroup_size(WORKGROUP_SIZE, WORKGROUP_SIZE, WORKGROUP_SIZE)
+fn compute_shader(@builtin(global_invocation_id) id: vec3<u32>){
+    let dims = vec3<i32>(textureDimensions(volume_tex));
+    let voxel = vec3<i32>(id);
+    // the last workgroups reach past volumes that are not a multiple of the workgroup size
+    if (any(voxel >= dims)){
+        return;
+    }
+    let lower = max(voxel - vec3<i32>(1), vec3<i32>(0));
+    let upper = min(voxel + vec3<i32>(1), dims - vec3<i32>(1));
+    // the actual distance of the two samples, a single voxel along an axis has no gradient
+    let distance = vec3<f32>(max(upper - lower, vec3<i32>(1)));
+    let gradient = vec3<f32>(
+        textureLoad(volume_tex, vec3<i32>(upper.x, voxel.y, voxel.z), 0).r - textureLoad(volume_tex, vec3<i32>(lower.x, voxel.y, voxel.z), 0).r,
+        textureLoad(volume_tex, vec3<i32>(voxel.x, upper.y, voxel.z), 0).r - textureLoad(volume_tex, vec3<i32>(voxel.x, lower.y, voxel.z), 0).r,
+        textureLoad(volume_tex, vec3<i32>(voxel.x, voxel.y, upper.z), 0).r - textureLoad(volume_tex, vec3<i32>(voxel.x, voxel.y, lower.z), 0).r,
+    ) / distance;
+    let magnitude = length(gradient);
+    var normal = gradient;
+    if (magnitude > 0.0){
+        normal = gradient / magnitude;
+    }
+    textureStore(gradient_tex, voxel, vec4<f32>(normal, magnitude));
+}
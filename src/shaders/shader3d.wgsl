@@ -1,5 +1,7 @@
 struct Uniforms{
     view_proj_mat: mat4x4<f32>,
+    eye_volume_coord: vec3<f32>,
+    znear: f32,
 }
 
 @group(0) @binding(0)
@@ -13,6 +15,7 @@ struct VertexInput{
 struct VertexOutput{
     @builtin(position) clip_position: vec4<f32>,
     @location(0) v_coord: vec3<f32>,
+    @location(1) view_depth: f32,
 };
 
 // simple vertex shader for drawing a box
@@ -21,6 +24,8 @@ fn vertex_shader(vertex: VertexInput) -> VertexOutput{
     var out: VertexOutput;
     out.v_coord = vertex.v_coord;
     out.clip_position = uniforms.view_proj_mat * vec4<f32>(vertex.v_pos, 1.0);
+    // w of a perspective projection is the distance along the view direction
+    out.view_depth = out.clip_position.w;
     return out;
 }
 
@@ -28,4 +33,16 @@ fn vertex_shader(vertex: VertexInput) -> VertexOutput{
 @fragment
 fn fragment_shader(in: VertexOutput) -> @location(0) vec4<f32>{
     return vec4<f32>(in.v_coord, 1.0);
-}
\ No newline at end of file
+}
+
+// ray entry position, drawn for front and back faces where the nearest one wins.
+// Without a front face in front of it, the ray enters the volume on the near plane:
+// the point on the line from the eye to the back face where the view depth equals znear
+@fragment
+fn entry_fragment_shader(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32>{
+    if (front_facing){
+        return vec4<f32>(in.v_coord, 1.0);
+    }
+    let near_coord = mix(uniforms.eye_volume_coord, in.v_coord, uniforms.znear / in.view_depth);
+    return vec4<f32>(near_coord, 1.0);
+}
@@ -0,0 +1,47 @@
+struct SliceUniforms{
+    // volume axis the slice is orthogonal to, 0: x, 1: y, 2: z
+    axis: u32,
+    // position of the slice along the axis in volume texture coordinates
+    position: f32,
+}
+
+struct VertexOutput{
+    @builtin(position) clip_position: vec4<f32>,
+    // in [0, 1] over the viewport, increasing to the right and upwards
+    @location(0) plane_coord: vec2<f32>,
+}
+
+// volume data, the same bind group as in canvas_shader.wgsl
+@group(0) @binding(0) var volume_data: texture_3d<f32>;
+@group(0) @binding(1) var volume_sampler: sampler;
+
+@group(1) @binding(0) var<uniform> uniforms: SliceUniforms;
+
+// a single triangle covering the whole viewport
+@vertex
+fn vertex_shader(@builtin(vertex_index) index: u32) -> VertexOutput{
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.plane_coord = uv;
+    return out;
+}
+
+@fragment
+fn fragment_shader(in: VertexOutput) -> @location(0) vec4<f32>{
+    let p = in.plane_coord;
+    var coord: vec3<f32>;
+    switch uniforms.axis {
+        case 0u: {
+            coord = vec3<f32>(uniforms.position, p.x, p.y);
+        }
+        case 1u: {
+            coord = vec3<f32>(p.x, uniforms.position, p.y);
+        }
+        default: {
+            coord = vec3<f32>(p.x, p.y, uniforms.position);
+        }
+    }
+    let value = textureSampleLevel(volume_data, volume_sampler, coord, 0.0).r;
+    return vec4<f32>(vec3<f32>(value), 1.0);
+}
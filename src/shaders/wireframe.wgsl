@@ -0,0 +1,24 @@
+struct Uniforms{
+    view_proj_mat: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+struct VertexInput{
+    @location(0) v_pos: vec3<f32>,
+    @location(1) v_coord: vec3<f32>,
+}
+
+const LINE_COLOR: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+
+// same transformation as the face passes, so the edges match the ray entry and exit positions
+@vertex
+fn vertex_shader(vertex: VertexInput) -> @builtin(position) vec4<f32>{
+    return uniforms.view_proj_mat * vec4<f32>(vertex.v_pos, 1.0);
+}
+
+@fragment
+fn fragment_shader() -> @location(0) vec4<f32>{
+    return LINE_COLOR;
+}
@@ -1,9 +1,35 @@
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
+use futures::executor::block_on;
 use half::f16;
 use image::GenericImageView;
+use rayon::prelude::*;
 use std::num::NonZeroU32;
 use wgpu::*;
 
+use crate::utils::downsample_volume;
+
+/// How a volume texture of [`Tex::create_volume_texture`] is sampled
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VolumeSampling {
+    /// builds a full mip chain like [`Tex::create_3d_texture_red_f16_mipmapped`]
+    pub mipmapped: bool,
+    /// what samples outside of [0, 1]³ return, see [`Tex::volume_sampler_with_address_mode`]
+    pub address_mode: AddressMode,
+    /// only used by [`AddressMode::ClampToBorder`]
+    pub border_color: Option<SamplerBorderColor>,
+}
+
+impl Default for VolumeSampling {
+    /// No mipmaps and the outermost voxels repeated, which every device supports
+    fn default() -> Self {
+        Self {
+            mipmapped: false,
+            address_mode: AddressMode::ClampToEdge,
+            border_color: None,
+        }
+    }
+}
+
 pub struct Tex {
     pub texture: Texture,
     pub view: TextureView,
@@ -13,6 +39,10 @@ pub struct Tex {
 
 impl Tex {
     pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float; // need when creating render pipeline depth stage and create texture
+    /// Transfer function colors are sRGB encoded like the colormaps and color pickers they come from.
+    /// Sampling decodes them to linear color, which the canvas pass composites and the sRGB render target encodes again.
+    /// Opacity is stored linearly either way
+    pub const TRANSFER_FUNCTION_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
 
     pub fn from_bytes(device: &Device, queue: &Queue, bytes: &[u8], label: &str) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
@@ -25,7 +55,7 @@ impl Tex {
         queue: &Queue,
         label: &str,
     ) -> Self {
-        let format = TextureFormat::Rgba8UnormSrgb;
+        let format = Self::TRANSFER_FUNCTION_FORMAT;
         let length = data.len() as u32;
         let flatten_data = data
             .iter()
@@ -80,25 +110,35 @@ impl Tex {
         }
     }
 
-    pub fn create_3d_texture_red_f16(
-        size: &Extent3d,
-        data: &Vec<f16>,
+    /// `width` x `height` texture stored row by row, e.g. a 2D transfer function
+    pub fn create_2d_texture_rgba8(
+        width: u32,
+        height: u32,
+        data: &[cgmath::Vector4<u8>],
         device: &Device,
         queue: &Queue,
         label: &str,
     ) -> Self {
-        let format = TextureFormat::R16Float;
-        let desc = TextureDescriptor {
+        let format = Self::TRANSFER_FUNCTION_FORMAT;
+        let flatten_data = data
+            .iter()
+            .flat_map(|v| [v.x, v.y, v.z, v.w])
+            .collect::<Vec<u8>>();
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
             label: Some(label),
-            size: size.clone(),
+            size,
             mip_level_count: 1,
             sample_count: 1,
-            dimension: TextureDimension::D3,
+            dimension: TextureDimension::D2,
             format,
             usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
             view_formats: &[format],
-        };
-        let texture = device.create_texture(&desc);
+        });
         queue.write_texture(
             ImageCopyTexture {
                 texture: &texture,
@@ -106,24 +146,435 @@ impl Tex {
                 origin: Origin3d::ZERO,
                 aspect: Default::default(),
             },
-            bytemuck::cast_slice(data.as_slice()),
+            flatten_data.as_slice(),
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(2 * size.width),
-                rows_per_image: Some(size.height),
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
             },
-            size.clone(),
+            size,
         );
         let view = texture.create_view(&TextureViewDescriptor::default());
         let sampler = device.create_sampler(&SamplerDescriptor {
-            address_mode_v: AddressMode::ClampToEdge,
             address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
             address_mode_w: AddressMode::ClampToEdge,
             mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Linear,
             mipmap_filter: FilterMode::Nearest,
             ..Default::default()
         });
+        Tex {
+            texture,
+            view,
+            sampler,
+            format,
+        }
+    }
+
+    /// 3D texture with a single half float channel
+    pub fn create_3d_texture_red_f16(
+        size: &Extent3d,
+        data: &Vec<f16>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[bytemuck::cast_slice(data.as_slice())],
+            TextureFormat::R16Float,
+            FilterMode::Linear,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// 3D texture with a single half float channel and a full mip chain for level-of-detail sampling,
+    /// see [`crate::rendering::CanvasPass::set_level_of_detail`]. Every level averages 2³ voxels of the previous one.
+    /// Fails like [`Tex::create_volume_texture`]
+    pub fn create_3d_texture_red_f16_mipmapped(
+        size: &Extent3d,
+        data: &[f32],
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Result<Self> {
+        Self::create_volume_texture(
+            size,
+            data,
+            TextureFormat::R16Float,
+            VolumeSampling {
+                mipmapped: true,
+                ..Default::default()
+            },
+            device,
+            queue,
+            label,
+        )
+    }
+
+    ///
+    /// Single channel volume texture storing `data`, normalized to [0, 1], in `format`
+    ///
+    /// * [`TextureFormat::R8Unorm`] for 8-bit data, a quarter of the memory of floats
+    /// * [`TextureFormat::R16Unorm`] for 16-bit data, needs [`Features::TEXTURE_FORMAT_16BIT_NORM`]
+    /// * [`TextureFormat::R16Float`] works everywhere, but keeps only 11 bits of precision
+    /// * [`TextureFormat::R32Float`] for full precision, sampled with nearest filtering unless the device has
+    ///   [`Features::FLOAT32_FILTERABLE`]
+    ///
+    /// `sampling` decides about the mip chain and what samples outside of [0, 1]³ return. Panics for other formats.
+    ///
+    /// Fails instead of panicking if the volume exceeds the 3D texture size limit of `device`
+    /// or does not fit into GPU memory, so that the caller can downsample it.
+    ///
+    pub fn create_volume_texture(
+        size: &Extent3d,
+        data: &[f32],
+        format: TextureFormat,
+        sampling: VolumeSampling,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Result<Self> {
+        let max_dimension = device.limits().max_texture_dimension_3d;
+        ensure!(
+            size.width.max(size.height).max(size.depth_or_array_layers) <= max_dimension,
+            "volume of {}x{}x{} voxels exceeds the {} voxels per side this GPU supports",
+            size.width,
+            size.height,
+            size.depth_or_array_layers,
+            max_dimension
+        );
+        let mut levels = vec![Self::encode_volume_texels(data, format)];
+        if sampling.mipmapped {
+            let mut dims = (
+                size.width as usize,
+                size.height as usize,
+                size.depth_or_array_layers as usize,
+            );
+            let mut level_data = data.to_vec();
+            while dims.0.max(dims.1).max(dims.2) > 1 {
+                (level_data, dims) = downsample_volume(&level_data, dims, 2);
+                levels.push(Self::encode_volume_texels(&level_data, format));
+            }
+        }
+        let level_bytes: Vec<&[u8]> = levels.iter().map(Vec::as_slice).collect();
+        // a non-filterable format, e.g. R32Float without FLOAT32_FILTERABLE, is bound to a non-filtering sampler slot
+        let filter = match format.sample_type(None, Some(device.features())) {
+            Some(TextureSampleType::Float { filterable: true }) => FilterMode::Linear,
+            _ => FilterMode::Nearest,
+        };
+        let sampler = Self::volume_sampler_with_address_mode(
+            device,
+            filter,
+            sampling.address_mode,
+            sampling.border_color,
+        );
+        catch_allocation_error(device, || {
+            Self::create_3d_texture_with_sampler(
+                size,
+                &level_bytes,
+                format,
+                sampler,
+                device,
+                queue,
+                label,
+            )
+        })
+        .map_err(|e| {
+            anyhow!(
+                "volume of {}x{}x{} voxels in {:?} does not fit into GPU memory: {}",
+                size.width,
+                size.height,
+                size.depth_or_array_layers,
+                format,
+                e
+            )
+        })
+    }
+
+    /// Texel bytes of normalized `data` in one of the formats of [`Tex::create_volume_texture`]
+    fn encode_volume_texels(data: &[f32], format: TextureFormat) -> Vec<u8> {
+        match format {
+            TextureFormat::R8Unorm => data
+                .par_iter()
+                .map(|v| (v.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8)
+                .collect(),
+            TextureFormat::R16Unorm => {
+                let texels: Vec<u16> = data
+                    .par_iter()
+                    .map(|v| (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16)
+                    .collect();
+                bytemuck::cast_slice(&texels).to_vec()
+            }
+            TextureFormat::R16Float => {
+                let texels: Vec<f16> = data.par_iter().map(|v| f16::from_f32(*v)).collect();
+                bytemuck::cast_slice(&texels).to_vec()
+            }
+            TextureFormat::R32Float => bytemuck::cast_slice(data).to_vec(),
+            _ => panic!("{:?} is not a supported volume texture format", format),
+        }
+    }
+
+    ///
+    /// 3D texture with a single 32-bit float channel, e.g. for volumes of [`crate::utils::load_volume_data_f32`]
+    ///
+    /// R32Float is only filterable with [`Features::FLOAT32_FILTERABLE`]. Without it the texture is sampled with
+    /// nearest filtering, which shows the voxels as blocks; enable the feature on the device for trilinear sampling
+    /// or convert to [`TextureFormat::R16Float`] with [`Tex::create_volume_texture`].
+    ///
+    pub fn create_3d_texture_red_f32(
+        size: &Extent3d,
+        data: &Vec<f32>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        let filter = if device.features().contains(Features::FLOAT32_FILTERABLE) {
+            FilterMode::Linear
+        } else {
+            FilterMode::Nearest
+        };
+        Self::create_3d_texture(
+            size,
+            &[bytemuck::cast_slice(data.as_slice())],
+            TextureFormat::R32Float,
+            filter,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// 3D texture with a single normalized byte channel, sampled as [0, 1] floats
+    pub fn create_3d_texture_red_u8(
+        size: &Extent3d,
+        data: &Vec<u8>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[data.as_slice()],
+            TextureFormat::R8Unorm,
+            FilterMode::Linear,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// 3D texture with a single normalized 16-bit channel, sampled as [0, 1] floats.
+    /// The device needs [`Features::TEXTURE_FORMAT_16BIT_NORM`]
+    pub fn create_3d_texture_red_u16(
+        size: &Extent3d,
+        data: &Vec<u16>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[bytemuck::cast_slice(data.as_slice())],
+            TextureFormat::R16Unorm,
+            FilterMode::Linear,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// 3D texture with four half float channels, e.g. for precomputed gradients.
+    /// `data` holds 4 values per voxel
+    pub fn create_3d_texture_rgba16f(
+        size: &Extent3d,
+        data: &Vec<f16>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[bytemuck::cast_slice(data.as_slice())],
+            TextureFormat::Rgba16Float,
+            FilterMode::Linear,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// Empty 3D texture with four half float channels that compute shaders write into, e.g. for gradients
+    /// computed by [`crate::rendering::GradientComputePass`]. It can be sampled and copied back as well
+    pub fn create_3d_storage_texture_rgba16f(
+        size: &Extent3d,
+        device: &Device,
+        label: &str,
+    ) -> Self {
+        let format = TextureFormat::Rgba16Float;
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: *size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC,
+            view_formats: &[format],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Tex {
+            texture,
+            view,
+            sampler: Self::volume_sampler(device, FilterMode::Linear),
+            format,
+        }
+    }
+
+    /// 3D texture of unnormalized byte labels, e.g. a segmentation. Integer textures cannot be filtered,
+    /// so the shaders read it with `textureLoad` and the sampler is only a placeholder
+    pub fn create_3d_texture_label_u8(
+        size: &Extent3d,
+        data: &Vec<u8>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[data.as_slice()],
+            TextureFormat::R8Uint,
+            FilterMode::Nearest,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    /// 3D texture with a single normalized byte channel, e.g. for occupancy grids.
+    /// Filtering is nearest since every texel describes a whole block
+    pub fn create_3d_texture_r8(
+        size: &Extent3d,
+        data: &Vec<u8>,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        Self::create_3d_texture(
+            size,
+            &[data.as_slice()],
+            TextureFormat::R8Unorm,
+            FilterMode::Nearest,
+            device,
+            queue,
+            label,
+        )
+    }
+
+    ///
+    /// Clamped sampler for 3D textures using `filter` for both magnification and minification
+    ///
+    /// Linear filtering interpolates trilinearly between voxels, so the volume stays smooth whether it is
+    /// magnified or minified. Nearest filtering keeps the voxels blocky and, when the volume is minified
+    /// (zoomed out), makes samples jump between voxels which shimmers while rotating.
+    /// Mipmapped textures also blend between their levels with `filter`.
+    /// It is only needed for textures that hold one value per block, like occupancy grids,
+    /// or for formats that are not filterable.
+    ///
+    pub fn volume_sampler(device: &Device, filter: FilterMode) -> Sampler {
+        Self::volume_sampler_with_address_mode(device, filter, AddressMode::ClampToEdge, None)
+    }
+
+    ///
+    /// Sampler for 3D textures like [`Tex::volume_sampler`] with `address_mode` on all axes
+    ///
+    /// [`AddressMode::ClampToEdge`] repeats the outermost voxels beyond the volume, which smears data touching
+    /// the boundary outwards. [`AddressMode::ClampToBorder`] returns `border_color` instead, so with
+    /// [`SamplerBorderColor::Zero`] nothing is sampled outside of the volume. It needs
+    /// [`Features::ADDRESS_MODE_CLAMP_TO_ZERO`] for a zero border or [`Features::ADDRESS_MODE_CLAMP_TO_BORDER`]
+    /// for the other colors. `border_color` is ignored by the other address modes.
+    ///
+    pub fn volume_sampler_with_address_mode(
+        device: &Device,
+        filter: FilterMode,
+        address_mode: AddressMode,
+        border_color: Option<SamplerBorderColor>,
+    ) -> Sampler {
+        device.create_sampler(&SamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            border_color: border_color.filter(|_| address_mode == AddressMode::ClampToBorder),
+            ..Default::default()
+        })
+    }
+
+    /// Uploads tightly packed texels of an uncompressed format into a new 3D texture,
+    /// one slice per mip level starting with the full resolution. See [`Tex::volume_sampler`] for the filtering
+    fn create_3d_texture(
+        size: &Extent3d,
+        mip_levels: &[&[u8]],
+        format: TextureFormat,
+        filter: FilterMode,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        let sampler = Self::volume_sampler(device, filter);
+        Self::create_3d_texture_with_sampler(
+            size, mip_levels, format, sampler, device, queue, label,
+        )
+    }
+
+    /// [`Tex::create_3d_texture`] sampled with `sampler`
+    fn create_3d_texture_with_sampler(
+        size: &Extent3d,
+        mip_levels: &[&[u8]],
+        format: TextureFormat,
+        sampler: Sampler,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+    ) -> Self {
+        let desc = TextureDescriptor {
+            label: Some(label),
+            size: *size,
+            mip_level_count: mip_levels.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[format],
+        };
+        let texture = device.create_texture(&desc);
+        let bytes_per_texel = format.block_copy_size(None).unwrap();
+        for (mip_level, data) in mip_levels.iter().enumerate() {
+            let level_size = size.mip_level_size(mip_level as u32, TextureDimension::D3);
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: Origin3d::ZERO,
+                    aspect: Default::default(),
+                },
+                data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_texel * level_size.width),
+                    rows_per_image: Some(level_size.height),
+                },
+                level_size,
+            );
+        }
+        let view = texture.create_view(&TextureViewDescriptor::default());
 
         Tex {
             texture,
@@ -222,6 +673,113 @@ impl Tex {
         }
     }
 
+    /// Single-sampled render target that can be copied back to the CPU with [`Tex::read_rgba8_image`]
+    pub fn create_readable_render_target(
+        dimensions: (u32, u32),
+        device: &Device,
+        label: Option<&str>,
+        format: &TextureFormat,
+    ) -> Self {
+        let size = Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: *format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::COPY_SRC,
+            view_formats: std::slice::from_ref(format),
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            format: *format,
+            sampler,
+        }
+    }
+
+    /// Copies a 2D Rgba8 or Bgra8 texture with `COPY_SRC` usage back into an RGBA image,
+    /// blocking until the copy is done
+    pub fn read_rgba8_image(&self, device: &Device, queue: &Queue) -> Result<image::RgbaImage> {
+        let bgra = matches!(
+            self.format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        );
+        ensure!(
+            bgra || matches!(
+                self.format,
+                TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+            ),
+            "Only Rgba8 and Bgra8 textures can be read back, got {:?}",
+            self.format
+        );
+        let (width, height) = (self.texture.width(), self.texture.height());
+        let unpadded_bytes_per_row = 4 * width;
+        // bytes_per_row of texture to buffer copies must be a multiple of 256
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Readback buffer"),
+            size: (padded_bytes_per_row * height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Readback encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            self.texture.size(),
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(Maintain::Wait);
+        block_on(receiver)??;
+        let mut pixels: Vec<u8> = slice
+            .get_mapped_range()
+            .chunks_exact(padded_bytes_per_row as usize)
+            .flat_map(|row| row[..unpadded_bytes_per_row as usize].iter().cloned())
+            .collect();
+        buffer.unmap();
+        if bgra {
+            pixels
+                .chunks_exact_mut(4)
+                .for_each(|pixel| pixel.swap(0, 2));
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("Read back pixels do not match the texture size"))
+    }
+
     pub fn from_image(
         device: &Device,
         queue: &Queue,
@@ -283,3 +841,24 @@ impl Tex {
         })
     }
 }
+
+/// Runs `create` in out-of-memory and validation error scopes, so that a resource the GPU cannot hold
+/// is reported instead of aborting the app
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn catch_allocation_error<T>(device: &Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(ErrorFilter::OutOfMemory);
+    device.push_error_scope(ErrorFilter::Validation);
+    let created = create();
+    let validation_error = block_on(device.pop_error_scope());
+    let out_of_memory_error = block_on(device.pop_error_scope());
+    match out_of_memory_error.or(validation_error) {
+        None => Ok(created),
+        Some(error) => Err(anyhow!("{}", error)),
+    }
+}
+
+/// Like `catch_validation_error` of the passes, the browser cannot be waited for and only the texture limits are checked
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn catch_allocation_error<T>(_device: &Device, create: impl FnOnce() -> T) -> Result<T> {
+    Ok(create())
+}
@@ -0,0 +1,137 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3};
+use futures::executor::block_on;
+use half::f16;
+use wgpu::*;
+
+use crate::rendering::{Camera, CanvasPass, D3Pass, FaceSelection, RenderPass};
+use crate::shading::Tex;
+
+/// Device and queue of the default adapter, `None` if there is none, e.g. on a CI machine without a GPU
+pub fn request_device() -> Option<(Adapter, Device, Queue)> {
+    let instance = Instance::default();
+    let Some(adapter) = block_on(instance.request_adapter(&RequestAdapterOptions::default()))
+    else {
+        eprintln!("no GPU adapter available, skipping");
+        return None;
+    };
+    let (device, queue) =
+        block_on(adapter.request_device(&DeviceDescriptor::default(), None)).unwrap();
+    Some((adapter, device, queue))
+}
+
+/// Looks down the z axis onto the z = 1 face of the volume
+pub fn front_camera() -> Camera {
+    Camera {
+        eye: Point3::new(0.0, 0.0, 2.0),
+        center: Point3::new(0.0, 0.0, 0.0),
+        up: Vector3::unit_y(),
+        aspect: 1.0,
+        fovy: 45.0,
+        znear: 0.1,
+        zfar: 100.0,
+    }
+}
+
+///
+/// A 2x2x2 volume with its face buffers and a readable target for rendering the passes without a window
+///
+/// The face buffers are single sampled and the target is [`TextureFormat::Rgba8Unorm`], so it can be read back.
+///
+pub struct CanvasFixture {
+    pub device: Device,
+    pub queue: Queue,
+    pub size: (u32, u32),
+    pub format: TextureFormat,
+    pub front_face_buffer: Tex,
+    pub back_face_buffer: Tex,
+    pub volume_texture: Arc<Tex>,
+    pub target: Tex,
+}
+
+impl CanvasFixture {
+    /// `voxels` of the R16Float volume with x varying fastest. The faces of the volume are rendered as seen by
+    /// `camera`, without one they stay empty and every ray misses the volume. `None` if there is no GPU
+    pub fn new(voxels: [f32; 8], size: (u32, u32), camera: Option<&Camera>) -> Option<Self> {
+        let (_, device, queue) = request_device()?;
+        let face_format = TextureFormat::Rgba16Float;
+        let format = TextureFormat::Rgba8Unorm;
+        let single_sample = NonZeroU32::new(1).unwrap();
+        let front_face_buffer =
+            Tex::create_render_buffer(size, &device, None, single_sample, &face_format);
+        let back_face_buffer =
+            Tex::create_render_buffer(size, &device, None, single_sample, &face_format);
+        if let Some(camera) = camera {
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+            for (render_front_face, buffer) in
+                [(true, &front_face_buffer), (false, &back_face_buffer)]
+            {
+                D3Pass::new(
+                    &device,
+                    size.0,
+                    size.1,
+                    &face_format,
+                    FaceSelection::new(render_front_face),
+                    camera,
+                    single_sample,
+                    Matrix4::identity(),
+                )
+                .render(&buffer.view, None, &mut encoder);
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+        let volume_texture = Arc::new(Tex::create_3d_texture_red_f16(
+            &Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 2,
+            },
+            &voxels.into_iter().map(f16::from_f32).collect(),
+            &device,
+            &queue,
+            "Volume",
+        ));
+        let target = Tex::create_readable_render_target(size, &device, None, &format);
+        Some(Self {
+            device,
+            queue,
+            size,
+            format,
+            front_face_buffer,
+            back_face_buffer,
+            volume_texture,
+            target,
+        })
+    }
+
+    /// Canvas pass ray marching the volume between the face buffers into the target
+    pub fn canvas_pass(&self) -> CanvasPass {
+        CanvasPass::new(
+            &self.front_face_buffer,
+            &self.back_face_buffer,
+            self.volume_texture.clone(),
+            &self.device,
+            &self.queue,
+            self.size,
+            &self.format,
+            NonZeroU32::new(1).unwrap(),
+        )
+    }
+
+    /// Records `render` into the target and reads it back
+    pub fn render(
+        &self,
+        render: impl FnOnce(&TextureView, &mut CommandEncoder),
+    ) -> image::RgbaImage {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
+        render(&self.target.view, &mut encoder);
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.target
+            .read_rgba8_image(&self.device, &self.queue)
+            .unwrap()
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use wgpu::*;
+
+/// Rolling average over the durations of the last frames
+pub struct FrameTimer {
+    frame_times: VecDeque<Duration>,
+    window: usize,
+}
+
+impl FrameTimer {
+    pub fn new(window: usize) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(window),
+            window: window.max(1),
+        }
+    }
+
+    pub fn push(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == self.window {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    /// Average frame time, zero before the first frame
+    pub fn average(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            Duration::ZERO
+        } else {
+            self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+        }
+    }
+}
+
+// states of the GpuTimer readback buffer
+const READBACK_IDLE: u8 = 0;
+const READBACK_MAPPING: u8 = 1;
+const READBACK_MAPPED: u8 = 2;
+
+///
+/// Measures the GPU duration of a render pass with timestamp queries
+///
+/// Every frame: pass [`GpuTimer::timestamp_writes`] to the pass, call [`GpuTimer::resolve`] before finishing the encoder
+/// and [`GpuTimer::map`] after submitting it. The result of a frame becomes available in a later frame,
+/// frames are skipped while the previous result is still being read back.
+///
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    readback_state: Arc<AtomicU8>,
+    resolved: AtomicBool, // the submitted frame copied timestamps into the readback buffer
+    timestamp_period: f32, // nanoseconds per tick
+}
+
+impl GpuTimer {
+    /// Returns `None` if the device was not created with [`Features::TIMESTAMP_QUERY`]
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("GPU timer query set"),
+            ty: QueryType::Timestamp,
+            count: 2,
+        });
+        let size = 2 * std::mem::size_of::<u64>() as BufferAddress;
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("GPU timer resolve buffer"),
+            size,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("GPU timer readback buffer"),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            readback_state: Arc::new(AtomicU8::new(READBACK_IDLE)),
+            resolved: AtomicBool::new(false),
+            timestamp_period: queue.get_timestamp_period(),
+        })
+    }
+
+    /// Writes a timestamp at the beginning and at the end of the pass
+    pub fn timestamp_writes(&self) -> RenderPassTimestampWrites<'_> {
+        RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Copies the timestamps into the readback buffer unless it is still in use
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        if self.readback_state.load(Ordering::Acquire) != READBACK_IDLE {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.resolve_buffer.size(),
+        );
+        self.resolved.store(true, Ordering::Release);
+    }
+
+    /// Starts reading back the timestamps resolved in the submitted frame
+    pub fn map(&self) {
+        if !self.resolved.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        self.readback_state
+            .store(READBACK_MAPPING, Ordering::Release);
+        let readback_state = self.readback_state.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                let state = if result.is_ok() {
+                    READBACK_MAPPED
+                } else {
+                    READBACK_IDLE
+                };
+                readback_state.store(state, Ordering::Release);
+            });
+    }
+
+    /// Duration of the last pass whose timestamps arrived, polls the device without blocking
+    pub fn read(&self, device: &Device) -> Option<Duration> {
+        device.poll(Maintain::Poll);
+        if self.readback_state.load(Ordering::Acquire) != READBACK_MAPPED {
+            return None;
+        }
+        let timestamps: Vec<u64> = {
+            let view = self.readback_buffer.slice(..).get_mapped_range();
+            bytemuck::cast_slice(&view).to_vec()
+        };
+        self.readback_buffer.unmap();
+        self.readback_state.store(READBACK_IDLE, Ordering::Release);
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        Some(Duration::from_nanos(
+            (ticks as f64 * self.timestamp_period as f64) as u64,
+        ))
+    }
+}
@@ -1,12 +1,16 @@
-use std::iter::FromIterator;
+use std::io::Read;
 use std::path::Path;
 
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use cgmath::{InnerSpace, Matrix3, One, Quaternion, Rotation};
+use flate2::read::GzDecoder;
+use half::f16;
 use rayon::prelude::*;
-use winit::event::KeyEvent;
+use winit::event::{KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
 use winit::keyboard::KeyCode;
 use winit::keyboard::PhysicalKey::Code;
 
-use crate::geometries::{Mesh3, V3};
+use crate::geometries::{Mat4, Mesh3, V3};
 use crate::rendering::Camera;
 
 pub struct CameraController {
@@ -17,6 +21,18 @@ pub struct CameraController {
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    // mouse arcball state
+    viewport_size: (f32, f32),
+    is_rotating: bool,
+    last_cursor: Option<(f32, f32)>,
+    pending_rotation: Quaternion<f32>, // in camera space, applied in update_camera
+    pending_dolly: f32,                // scroll lines, positive moves towards center
+    pending_zoom: f32,                 // Ctrl+scroll lines, positive narrows the field of view
+    is_control_pressed: bool,
+    // middle mouse panning state
+    is_panning: bool,
+    pending_pan: (f32, f32), // cursor delta in pixels, applied in update_camera
+    pending_recenter: bool,
 }
 
 impl CameraController {
@@ -29,10 +45,86 @@ impl CameraController {
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            viewport_size: (1.0, 1.0),
+            is_rotating: false,
+            last_cursor: None,
+            pending_rotation: Quaternion::one(),
+            pending_dolly: 0.0,
+            pending_zoom: 0.0,
+            is_control_pressed: false,
+            is_panning: false,
+            pending_pan: (0.0, 0.0),
+            pending_recenter: false,
         }
     }
 
-    pub fn process_events(&mut self, event: &KeyEvent) -> bool {
+    /// The arcball maps cursor positions onto a virtual sphere filling the viewport
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.viewport_size = (width.max(1) as f32, height.max(1) as f32);
+    }
+
+    /// Handles keyboard, mouse button, cursor and wheel events, returns true if the event changed the camera.
+    /// Scrolling dollies the eye, scrolling with Ctrl held zooms by changing the field of view
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            // the modifiers are tracked, never consumed
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.is_control_pressed = modifiers.state().control_key();
+                false
+            }
+            WindowEvent::KeyboardInput { event, .. } => self.process_key_event(event),
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.is_rotating = state.is_pressed();
+                true
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Middle,
+                ..
+            } => {
+                self.is_panning = state.is_pressed();
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = (position.x as f32, position.y as f32);
+                let last_cursor = self.last_cursor.replace(cursor);
+                match last_cursor {
+                    Some(last_cursor) if self.is_rotating => {
+                        let from = self.project_to_arcball(last_cursor);
+                        let to = self.project_to_arcball(cursor);
+                        self.pending_rotation =
+                            Quaternion::from_arc(from, to, None) * self.pending_rotation;
+                        true
+                    }
+                    Some(last_cursor) if self.is_panning => {
+                        self.pending_pan.0 += cursor.0 - last_cursor.0;
+                        self.pending_pan.1 += cursor.1 - last_cursor.1;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0, // roughly pixels per line
+                };
+                if self.is_control_pressed {
+                    self.pending_zoom += lines;
+                } else {
+                    self.pending_dolly += lines;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn process_key_event(&mut self, event: &KeyEvent) -> bool {
         let is_pressed = event.state.is_pressed(); // when the key is released, *state will be Release and thus reset the corresponding state
         if let Code(keycode) = event.physical_key {
             match keycode {
@@ -60,6 +152,10 @@ impl CameraController {
                     self.is_right_pressed = is_pressed;
                     true
                 }
+                KeyCode::KeyR => {
+                    self.pending_recenter |= is_pressed;
+                    true
+                }
                 _ => false
             }
         } else {
@@ -67,7 +163,23 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    /// Projects a cursor position onto the unit sphere in camera space (x right, y up, z towards the viewer)
+    fn project_to_arcball(&self, cursor: (f32, f32)) -> V3 {
+        let (width, height) = self.viewport_size;
+        let radius = width.min(height) / 2.0;
+        let x = (cursor.0 - width / 2.0) / radius;
+        let y = (height / 2.0 - cursor.1) / radius;
+        let length_squared = x * x + y * y;
+        if length_squared <= 1.0 {
+            V3::new(x, y, (1.0 - length_squared).sqrt())
+        } else {
+            // outside of the sphere, rotate around the view direction
+            V3::new(x, y, 0.0).normalize()
+        }
+    }
+
+    /// Applies the pending input to `camera`, recentering moves its center to `volume_center` in world space
+    pub fn update_camera(&mut self, camera: &mut Camera, volume_center: cgmath::Point3<f32>) {
         use cgmath::InnerSpace;
         let forward = camera.center - camera.eye;
         let forward_norm = forward.normalize();
@@ -97,6 +209,57 @@ impl CameraController {
         if self.is_left_pressed {
             camera.eye = camera.center - (forward - right * self.speed).normalize() * forward_mag;
         }
+
+        // mouse arcball: the object follows the cursor, so the eye orbits the opposite way
+        if self.pending_rotation != Quaternion::one() {
+            let forward_norm = (camera.center - camera.eye).normalize();
+            let right = forward_norm.cross(camera.up).normalize();
+            let up = right.cross(forward_norm);
+            let camera_to_world = Matrix3::from_cols(right, up, -forward_norm);
+            let rotation = Quaternion::from_sv(
+                self.pending_rotation.s,
+                camera_to_world * self.pending_rotation.v,
+            )
+            .invert();
+            camera.eye = camera.center + rotation * (camera.eye - camera.center);
+            camera.up = rotation * up;
+            self.pending_rotation = Quaternion::one();
+        }
+        // mouse wheel dolly, keep a minimal distance to the center
+        if self.pending_dolly != 0.0 {
+            let offset = camera.eye - camera.center;
+            let distance = (offset.magnitude() * 0.9f32.powf(self.pending_dolly)).max(self.speed);
+            camera.eye = camera.center + offset.normalize() * distance;
+            self.pending_dolly = 0.0;
+        }
+        // Ctrl + mouse wheel zoom, the eye stays where it is
+        if self.pending_zoom != 0.0 {
+            camera.set_fovy(camera.fovy * 0.9f32.powf(self.pending_zoom));
+            self.pending_zoom = 0.0;
+        }
+        // middle mouse pan, the point under the cursor at the center's depth follows the cursor
+        if self.pending_pan != (0.0, 0.0) {
+            let forward = camera.center - camera.eye;
+            let forward_norm = forward.normalize();
+            let right = forward_norm.cross(camera.up).normalize();
+            let up = right.cross(forward_norm);
+            // height of the view frustum at the center's depth divided by the viewport height
+            let world_per_pixel =
+                2.0 * forward.magnitude() * (camera.fovy.to_radians() / 2.0).tan()
+                    / self.viewport_size.1;
+            let translation =
+                (-right * self.pending_pan.0 + up * self.pending_pan.1) * world_per_pixel;
+            camera.eye += translation;
+            camera.center += translation;
+            self.pending_pan = (0.0, 0.0);
+        }
+        // move the center back to the volume center, keeping the view direction and distance
+        if self.pending_recenter {
+            let offset = camera.eye - camera.center;
+            camera.center = volume_center;
+            camera.eye = camera.center + offset;
+            self.pending_recenter = false;
+        }
     }
 }
 
@@ -139,45 +302,1448 @@ pub fn create_cube_fbo() -> Mesh3 {
     Mesh3::new(&vertices, &indices, &attribs_3d, None)
 }
 
+/// Scales the unit cube so that the volume keeps its physical shape, normalized by the middle extent.
+/// Without voxel spacing the voxels are assumed to be cubes
+pub fn compute_cube_scaling(dims: (usize, usize, usize), spacing: Option<(f32, f32, f32)>) -> Mat4 {
+    let (sx, sy, sz) = spacing.unwrap_or((1.0, 1.0, 1.0));
+    let (x, y, z) = (dims.0 as f32 * sx, dims.1 as f32 * sy, dims.2 as f32 * sz);
+    let mut sorted_extents = [x, y, z];
+    sorted_extents.sort_by(|a, b| a.total_cmp(b));
+    let mid_val = *sorted_extents.get(1).unwrap();
+    Mat4::from_nonuniform_scale(x / mid_val, y / mid_val, z / mid_val)
+}
+
 ///
+/// Shrinks a volume by `factor` along every axis by averaging blocks of `factor`³ voxels,
+/// a factor of 2 builds the next mip level
+///
+/// Dimensions are rounded down but stay at least 1, the remaining voxels of a dimension that is not a multiple of
+/// `factor` are averaged into the last block.
+///
+/// # Returns
+/// (downsampled data, downsampled dimensions)
+///
+pub fn downsample_volume(
+    data: &[f32],
+    dims: (usize, usize, usize),
+    factor: usize,
+) -> (Vec<f32>, (usize, usize, usize)) {
+    assert!(factor > 0, "downsample factor must be positive");
+    let (x_dim, y_dim, z_dim) = dims;
+    let new_dims = (
+        (x_dim / factor).max(1),
+        (y_dim / factor).max(1),
+        (z_dim / factor).max(1),
+    );
+    // source voxels of an output voxel along one axis
+    let source_range = |i: usize, dim: usize, new_dim: usize| {
+        let end = if i == new_dim - 1 {
+            dim
+        } else {
+            factor * (i + 1)
+        };
+        (factor * i).min(dim - 1)..end
+    };
+    let downsampled = (0..new_dims.0 * new_dims.1 * new_dims.2)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y, z) = (
+                i % new_dims.0,
+                (i / new_dims.0) % new_dims.1,
+                i / (new_dims.0 * new_dims.1),
+            );
+            let (mut sum, mut count) = (0.0, 0);
+            for sz in source_range(z, z_dim, new_dims.2) {
+                for sy in source_range(y, y_dim, new_dims.1) {
+                    for sx in source_range(x, x_dim, new_dims.0) {
+                        sum += data[sx + sy * x_dim + sz * x_dim * y_dim];
+                        count += 1;
+                    }
+                }
+            }
+            sum / count as f32
+        })
+        .collect();
+    (downsampled, new_dims)
+}
+
+///
+/// How the axes of a volume file map to the axes of the volume texture, for scanners storing them
+/// in another order or handedness
+///
+/// Texture axis `i` runs along file axis `axes[i]`, reversed if `flip[i]`.
+/// The default keeps the file as it is, e.g. `axes: [2, 1, 0]` maps file XYZ to texture ZYX.
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VolumeOrientation {
+    pub axes: [usize; 3],
+    pub flip: [bool; 3],
+}
+
+impl Default for VolumeOrientation {
+    fn default() -> Self {
+        Self {
+            axes: [0, 1, 2],
+            flip: [false; 3],
+        }
+    }
+}
+
+impl VolumeOrientation {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Dimensions, or voxel spacing, of the texture for `file_extent` along the axes of the file
+    pub fn apply<T: Copy>(&self, file_extent: (T, T, T)) -> (T, T, T) {
+        let extent = [file_extent.0, file_extent.1, file_extent.2];
+        (
+            extent[self.axes[0]],
+            extent[self.axes[1]],
+            extent[self.axes[2]],
+        )
+    }
+}
+
+///
+/// Copies a volume of `dims` voxels in the order of the file into the order of the texture given by `orientation`
+///
+/// # Returns
+/// (reoriented data, reoriented dimensions)
+///
+pub fn reorient_volume<T: Copy + Send + Sync>(
+    data: &[T],
+    dims: (usize, usize, usize),
+    orientation: VolumeOrientation,
+) -> (Vec<T>, (usize, usize, usize)) {
+    let mut sorted_axes = orientation.axes;
+    sorted_axes.sort();
+    assert_eq!(
+        sorted_axes,
+        [0, 1, 2],
+        "orientation axes must be a permutation of 0, 1, 2"
+    );
+    let new_dims = orientation.apply(dims);
+    let new_extent = [new_dims.0, new_dims.1, new_dims.2];
+    let reoriented = (0..data.len())
+        .into_par_iter()
+        .map(|i| {
+            let position = [
+                i % new_dims.0,
+                (i / new_dims.0) % new_dims.1,
+                i / (new_dims.0 * new_dims.1),
+            ];
+            let mut source = [0; 3];
+            for axis in 0..3 {
+                source[orientation.axes[axis]] = if orientation.flip[axis] {
+                    new_extent[axis] - 1 - position[axis]
+                } else {
+                    position[axis]
+                };
+            }
+            data[source[0] + source[1] * dims.0 + source[2] * dims.0 * dims.1]
+        })
+        .collect();
+    (reoriented, new_dims)
+}
+
+///
+/// Computes central-difference gradients of a volume in voxel units, one-sided at the borders
+///
+/// # Returns
+/// 4 values per voxel: the normalized gradient and its magnitude, ready for [`crate::shading::Tex::create_3d_texture_rgba16f`]
+///
+pub fn compute_gradients(data: &[f32], dims: (usize, usize, usize)) -> Vec<f16> {
+    let (x_dim, y_dim, z_dim) = dims;
+    let index = |x: usize, y: usize, z: usize| x + y * x_dim + z * x_dim * y_dim;
+    // difference along one axis, divided by the actual distance of the two samples
+    let difference = |lower: usize, upper: usize, lower_index: usize, upper_index: usize| {
+        (data[upper_index] - data[lower_index]) / (upper - lower).max(1) as f32
+    };
+    (0..x_dim * y_dim * z_dim)
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let (x, y, z) = (i % x_dim, (i / x_dim) % y_dim, i / (x_dim * y_dim));
+            let (x0, x1) = (x.saturating_sub(1), (x + 1).min(x_dim - 1));
+            let (y0, y1) = (y.saturating_sub(1), (y + 1).min(y_dim - 1));
+            let (z0, z1) = (z.saturating_sub(1), (z + 1).min(z_dim - 1));
+            let gradient = V3::new(
+                difference(x0, x1, index(x0, y, z), index(x1, y, z)),
+                difference(y0, y1, index(x, y0, z), index(x, y1, z)),
+                difference(z0, z1, index(x, y, z0), index(x, y, z1)),
+            );
+            let magnitude = gradient.magnitude();
+            let normal = if magnitude > 0.0 {
+                gradient / magnitude
+            } else {
+                gradient
+            };
+            [normal.x, normal.y, normal.z, magnitude].map(f16::from_f32)
+        })
+        .collect()
+}
+
+/// Coarse min/max summary of a volume, used to skip blocks that the transfer function maps to zero opacity
+#[derive(Debug, Clone)]
+pub struct OccupancyGrid {
+    /// Edge length of a block in voxels
+    pub block_size: usize,
+    /// Number of blocks along each axis
+    pub dims: (usize, usize, usize),
+    /// Minimum and maximum scalar of every block, x varies fastest
+    pub min_max: Vec<(f32, f32)>,
+}
+
+impl OccupancyGrid {
+    pub fn extent(&self) -> wgpu::Extent3d {
+        wgpu::Extent3d {
+            width: self.dims.0 as u32,
+            height: self.dims.1 as u32,
+            depth_or_array_layers: self.dims.2 as u32,
+        }
+    }
+
+    ///
+    /// Classifies every block against a transfer function looked up with `remap` of the scalars,
+    /// e.g. the window/level of the canvas pass. `remap` must never decrease, so a block range maps to a range
+    ///
+    /// # Returns
+    /// One value per block, 255 if any scalar in the block range may become visible and 0 if the block can be skipped
+    ///
+    pub fn classify(
+        &self,
+        transfer_function: &[cgmath::Vector4<u8>],
+        remap: impl Fn(f32) -> f32,
+    ) -> Vec<u8> {
+        let resolution = transfer_function.len();
+        // texel range the linearly filtered lookup of a scalar may touch
+        let texel = |scalar: f32| (scalar * resolution as f32 - 0.5).floor().max(0.0) as usize;
+        self.min_max
+            .iter()
+            .map(|&(min, max)| {
+                let first = texel(remap(min)).min(resolution - 1);
+                let last = (texel(remap(max)) + 1).min(resolution - 1);
+                if transfer_function[first..=last].iter().any(|c| c.w > 0) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+}
+
+///
+/// Combines several scalar channels of the same volume, e.g. stains in microscopy, into one RGBA volume
+///
+/// Every channel emits its color in proportion to its value, the opacity is the largest value of all channels.
+/// The result is ready for [`crate::shading::Tex::create_3d_texture_rgba16f`] and rendered without a transfer function.
+///
+pub fn combine_channels(channels: &[&Vec<f32>], colors: &[[f32; 3]]) -> Vec<f16> {
+    assert_eq!(channels.len(), colors.len(), "Every channel needs a color");
+    let voxel_count = channels.first().map_or(0, |channel| channel.len());
+    assert!(
+        channels.iter().all(|channel| channel.len() == voxel_count),
+        "All channels must have the same number of voxels"
+    );
+    (0..voxel_count)
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let mut rgba = [0.0f32; 4];
+            for (channel, color) in channels.iter().zip(colors) {
+                let value = channel[i];
+                for c in 0..3 {
+                    rgba[c] += value * color[c];
+                }
+                rgba[3] = rgba[3].max(value);
+            }
+            rgba.map(|v| f16::from_f32(v.min(1.0)))
+        })
+        .collect()
+}
+
+///
+/// Builds an [`OccupancyGrid`] of `block_size`³ blocks.
+/// Each block also covers a one voxel apron so that trilinear samples near its faces are accounted for
+///
+pub fn build_occupancy_grid(
+    data: &[f32],
+    dims: (usize, usize, usize),
+    block_size: usize,
+) -> OccupancyGrid {
+    assert!(
+        block_size > 0,
+        "Block size of an occupancy grid must be positive"
+    );
+    let (x_dim, y_dim, z_dim) = dims;
+    let grid_dims = (
+        x_dim.div_ceil(block_size),
+        y_dim.div_ceil(block_size),
+        z_dim.div_ceil(block_size),
+    );
+    let voxel_range = |block: usize, dim: usize| {
+        (block * block_size).saturating_sub(1)..((block + 1) * block_size + 1).min(dim)
+    };
+    let min_max = (0..grid_dims.0 * grid_dims.1 * grid_dims.2)
+        .into_par_iter()
+        .map(|i| {
+            let (bx, by, bz) = (
+                i % grid_dims.0,
+                (i / grid_dims.0) % grid_dims.1,
+                i / (grid_dims.0 * grid_dims.1),
+            );
+            let mut range = (f32::MAX, f32::MIN);
+            for z in voxel_range(bz, z_dim) {
+                for y in voxel_range(by, y_dim) {
+                    for x in voxel_range(bx, x_dim) {
+                        let value = data[x + y * x_dim + z * x_dim * y_dim];
+                        range = (range.0.min(value), range.1.max(value));
+                    }
+                }
+            }
+            range
+        })
+        .collect();
+    OccupancyGrid {
+        block_size,
+        dims: grid_dims,
+        min_max,
+    }
+}
+
+/// Byte order of the values stored in a raw volume file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Endianness {
+    Native,
+    Little,
+    Big,
+}
+
+impl Endianness {
+    fn read_u16(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Native => u16::from_ne_bytes(bytes),
+            Endianness::Little => u16::from_le_bytes(bytes),
+            Endianness::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn read_f32(&self, bytes: [u8; 4]) -> f32 {
+        match self {
+            Endianness::Native => f32::from_ne_bytes(bytes),
+            Endianness::Little => f32::from_le_bytes(bytes),
+            Endianness::Big => f32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Dimensions, samples normalized to [0, 1], samples as u16 and voxel spacing, what the volume loaders return.
+/// `Spacing` is not optional for the formats that always store it
+pub type LoadedVolume<Spacing = Option<(f32, f32, f32)>> =
+    ((usize, usize, usize), Vec<f32>, Vec<u16>, Spacing);
+
+/// A [`LoadedVolume`] followed by the raw (min, max) mapped to [0, 1]
+pub type LoadedVolumeWithRange = (
+    (usize, usize, usize),
+    Vec<f32>,
+    Vec<u16>,
+    Option<(f32, f32, f32)>,
+    (f32, f32),
+);
+
+///
+/// Reads a whole raw volume file, decompressing it if its extension is `.gz`
+fn read_raw_file<P: AsRef<Path>>(data_path: P) -> std::io::Result<Vec<u8>> {
+    let data_path = data_path.as_ref();
+    let bytes = std::fs::read(data_path)?;
+    let gzipped = data_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"));
+    if gzipped {
+        let mut decoded = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut decoded)?;
+        Ok(decoded)
+    } else {
+        Ok(bytes)
+    }
+}
+
 /// Reads raw 16-bit data into arrays
 ///
 /// First 3 2-byte unsigned integers should be dimensions
 ///
-/// Following 2-byte integers should only use lower 12bits
+/// Following 2-byte integers are the samples, of any bit depth
+///
+/// Files with a `.gz` extension are decompressed first, this holds for all raw loaders
 ///
 /// # Returns
 /// * dimensions
-/// * normalized(data << 4) float array
+/// * data normalized to [0, 1] by its min/max, see [`load_volume_data_with_range`] for the range
 /// * original u16 data array
+/// * voxel spacing, always `None` since the format does not store it
+///
+/// Fails if the file cannot be read or its size does not match the dimensions in the header,
+/// e.g. for a truncated file. The raw loaders check the size before decoding the samples
 ///
 /// # Endian
-/// Native endian of your machine, change `u16::from_ne_bytes` to `u16::from_be_bytes` or `u16::from_le_bytes` if necessary
+/// Native endian of your machine, use [`load_volume_data_with_endianness`] for files written with another byte order
+///
+pub fn load_volume_data<P: AsRef<Path>>(data_path: P) -> Result<LoadedVolume> {
+    load_volume_data_with_endianness(data_path, Endianness::Native)
+}
+
+///
+/// Same as [`load_volume_data`], but both the dimensions and the samples are read with the given byte order
+///
+pub fn load_volume_data_with_endianness<P: AsRef<Path>>(
+    data_path: P,
+    endianness: Endianness,
+) -> Result<LoadedVolume> {
+    let (dims, data, uint_data, spacing, _) =
+        load_volume_data_with_range(data_path, endianness, None)?;
+    Ok((dims, data, uint_data, spacing))
+}
+
+///
+/// Same as [`load_volume_data_with_endianness`], but `range` maps raw values to [0, 1] instead of their min/max,
+/// values outside of it are clamped
 ///
-pub fn load_volume_data<P: AsRef<Path>>(
+/// # Returns
+/// The same as [`load_volume_data`] followed by the raw (min, max) mapped to [0, 1],
+/// e.g. for showing window and level in raw units
+///
+pub fn load_volume_data_with_range<P: AsRef<Path>>(
     data_path: P,
-) -> ((usize, usize, usize), Vec<f32>, Vec<u16>) {
-    let bytes = std::fs::read(data_path).expect("Error when reading file");
-    let unsigned_shorts: Vec<u16> = bytes
+    endianness: Endianness,
+    range: Option<(f32, f32)>,
+) -> Result<LoadedVolumeWithRange> {
+    let bytes = read_raw_file(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    let ((x, y, z), uint_data) = parse_volume_data(&bytes, endianness, true)
+        .with_context(|| format!("Invalid volume file {}", data_path.as_ref().display()))?;
+    let (data, range) = normalize_u16_by_range(&uint_data, range, true);
+    Ok(((x, y, z), data, uint_data, None, range))
+}
+
+///
+/// Same as [`load_volume_data_with_range`], but parsed and normalized on the calling thread without rayon
+///
+/// The result is the same, this is for golden tests, environments where a thread pool is undesirable
+/// and for profiling the file IO apart from the parallel decoding.
+///
+pub fn load_volume_data_sequential<P: AsRef<Path>>(
+    data_path: P,
+    endianness: Endianness,
+    range: Option<(f32, f32)>,
+) -> Result<LoadedVolumeWithRange> {
+    let bytes = read_raw_file(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    let ((x, y, z), uint_data) = parse_volume_data(&bytes, endianness, false)
+        .with_context(|| format!("Invalid volume file {}", data_path.as_ref().display()))?;
+    let (data, range) = normalize_u16_by_range(&uint_data, range, false);
+    Ok(((x, y, z), data, uint_data, None, range))
+}
+
+///
+/// Splits a file in the format of [`load_volume_data`] that is already in memory, e.g. fetched by a browser,
+/// into the dimensions and the samples. `bytes` are not decompressed, with `parallel` they are decoded with rayon.
+/// Fails if they are shorter than the header or do not hold as many samples as it says
+///
+pub fn parse_volume_data(
+    bytes: &[u8],
+    endianness: Endianness,
+    parallel: bool,
+) -> Result<((usize, usize, usize), Vec<u16>)> {
+    let dims = parse_volume_header(bytes, endianness)?;
+    let samples = &bytes[VOLUME_HEADER_SIZE..];
+    let decode = |bytes: &[u8]| endianness.read_u16([bytes[0], bytes[1]]);
+    let uint_data: Vec<u16> = if parallel {
+        samples.par_chunks_exact(2).map(decode).collect()
+    } else {
+        samples.chunks_exact(2).map(decode).collect()
+    };
+    Ok((dims, uint_data))
+}
+
+/// The three shorts of dimensions in front of the samples of [`load_volume_data`]
+const VOLUME_HEADER_SIZE: usize = 6;
+
+/// Dimensions in the header of `bytes`, fails unless the samples after it fill exactly that many voxels
+fn parse_volume_header(bytes: &[u8], endianness: Endianness) -> Result<(usize, usize, usize)> {
+    ensure!(
+        bytes.len() >= VOLUME_HEADER_SIZE,
+        "Volume data of {} bytes is too short for its dimension header",
+        bytes.len()
+    );
+    let dim = |i: usize| endianness.read_u16([bytes[2 * i], bytes[2 * i + 1]]) as usize;
+    let dims = (dim(0), dim(1), dim(2));
+    check_volume_size(dims, 2, bytes.len() - VOLUME_HEADER_SIZE)?;
+    Ok(dims)
+}
+
+///
+/// Fails unless `found_bytes` hold exactly the voxels of `dims` at `bytes_per_sample`,
+/// before anything is allocated for them, e.g. for a truncated file or a wrong header
+///
+fn check_volume_size(
+    dims: (usize, usize, usize),
+    bytes_per_sample: usize,
+    found_bytes: usize,
+) -> Result<()> {
+    let (x, y, z) = dims;
+    let expected_bytes = volume_byte_size(dims, bytes_per_sample)?;
+    ensure!(
+        expected_bytes == found_bytes,
+        "Volume data does not match its dimensions: expected {}x{}x{} samples ({} bytes), found {} bytes",
+        x,
+        y,
+        z,
+        expected_bytes,
+        found_bytes
+    );
+    Ok(())
+}
+
+/// Bytes of the voxels of `dims` at `bytes_per_sample`, fails instead of overflowing for bogus dimensions
+fn volume_byte_size(dims: (usize, usize, usize), bytes_per_sample: usize) -> Result<usize> {
+    let (x, y, z) = dims;
+    x.checked_mul(y)
+        .and_then(|n| n.checked_mul(z))
+        .and_then(|n| n.checked_mul(bytes_per_sample))
+        .ok_or_else(|| anyhow!("Volume dimensions {}x{}x{} are too large", x, y, z))
+}
+
+/// Scalar type of the samples in a headerless raw volume file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RawSampleType {
+    /// read with [`load_volume_data_u8`]
+    U8,
+    /// read with [`load_volume_data_with_dims`]
+    #[default]
+    U16,
+    /// read with [`load_volume_data_f32`]
+    F32,
+}
+
+impl RawSampleType {
+    /// Volume texture format storing the samples without losing precision or wasting memory
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            RawSampleType::U8 => wgpu::TextureFormat::R8Unorm,
+            RawSampleType::U16 => wgpu::TextureFormat::R16Unorm,
+            RawSampleType::F32 => wgpu::TextureFormat::R32Float,
+        }
+    }
+}
+
+///
+/// Reads a headerless 8-bit raw volume, the dimensions have to be known up front
+///
+/// # Returns
+/// * dimensions
+/// * data normalized by 255
+/// * original data widened to u16
+/// * voxel spacing, always `None`
+///
+pub fn load_volume_data_u8<P: AsRef<Path>>(
+    data_path: P,
+    dims: (usize, usize, usize),
+) -> Result<LoadedVolume> {
+    let bytes = read_raw_file(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    check_volume_size(dims, 1, bytes.len())
+        .with_context(|| format!("Invalid volume file {}", data_path.as_ref().display()))?;
+    const U8MAX_F: f32 = u8::MAX as f32;
+    let data: Vec<f32> = bytes.par_iter().map(|v| *v as f32 / U8MAX_F).collect();
+    let uint_data: Vec<u16> = bytes.par_iter().map(|v| *v as u16).collect();
+    Ok((dims, data, uint_data, None))
+}
+
+///
+/// Reads a headerless 16-bit raw volume whose dimensions come from elsewhere, e.g. a sidecar file,
+/// instead of the first three shorts like [`load_volume_data`]
+///
+/// `range` maps raw values to [0, 1] like in [`load_volume_data_with_range`], `None` uses their min/max.
+///
+/// # Returns
+/// * dimensions
+/// * data normalized to [0, 1]
+/// * original u16 data array
+/// * voxel spacing, always `None`
+/// * the raw (min, max) mapped to [0, 1]
+///
+pub fn load_volume_data_with_dims<P: AsRef<Path>>(
+    data_path: P,
+    dims: (usize, usize, usize),
+    endianness: Endianness,
+    range: Option<(f32, f32)>,
+) -> Result<LoadedVolumeWithRange> {
+    let bytes = read_raw_file(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    check_volume_size(dims, 2, bytes.len())
+        .with_context(|| format!("Invalid volume file {}", data_path.as_ref().display()))?;
+    let uint_data: Vec<u16> = bytes
         .par_chunks_exact(2)
-        .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+        .map(|bytes| endianness.read_u16([bytes[0], bytes[1]]))
         .collect();
-    let x = unsigned_shorts.get(0).unwrap().clone() as usize;
-    let y = unsigned_shorts.get(1).unwrap().clone() as usize;
-    let z = unsigned_shorts.get(2).unwrap().clone() as usize;
-    let expected_data_num = x * y * z;
-    const U16MAX_F: f32 = u16::MAX as f32;
-    let data: Vec<f32> = unsigned_shorts
+    let (data, range) = normalize_u16_by_range(&uint_data, range, true);
+    Ok((dims, data, uint_data, None, range))
+}
+
+///
+/// Reads a headerless raw volume of 32-bit floats, e.g. simulation output, whose dimensions come from elsewhere
+///
+/// `range` maps values to [0, 1] like in [`load_volume_data_with_range`], `None` uses their min/max.
+/// Upload the full precision with [`crate::shading::Tex::create_3d_texture_red_f32`].
+///
+/// # Returns
+/// * dimensions
+/// * data normalized to [0, 1]
+/// * normalized data quantized to u16
+/// * voxel spacing, always `None`
+/// * the (min, max) mapped to [0, 1]
+///
+pub fn load_volume_data_f32<P: AsRef<Path>>(
+    data_path: P,
+    dims: (usize, usize, usize),
+    endianness: Endianness,
+    range: Option<(f32, f32)>,
+) -> Result<LoadedVolumeWithRange> {
+    let bytes = read_raw_file(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    check_volume_size(dims, 4, bytes.len())
+        .with_context(|| format!("Invalid volume file {}", data_path.as_ref().display()))?;
+    let values: Vec<f32> = bytes
+        .par_chunks_exact(4)
+        .map(|bytes| endianness.read_f32([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    let (data, range) = normalize_by_range(&values, range);
+    let uint_data: Vec<u16> = data
+        .par_iter()
+        .map(|v| (v * u16::MAX as f32).round() as u16)
+        .collect();
+    Ok((dims, data, uint_data, None, range))
+}
+
+/// Scalar types found in the bodies of volume files
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SampleType {
+    U8,
+    I8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl SampleType {
+    fn size(&self) -> usize {
+        match self {
+            SampleType::U8 | SampleType::I8 => 1,
+            SampleType::I16 | SampleType::U16 => 2,
+            SampleType::I32 | SampleType::U32 | SampleType::F32 => 4,
+            SampleType::F64 => 8,
+        }
+    }
+
+    /// Decodes one sample, `b` holds exactly [`SampleType::size`] bytes
+    fn decode(&self, b: &[u8], little_endian: bool) -> f32 {
+        match (self, little_endian) {
+            (SampleType::U8, _) => b[0] as f32,
+            (SampleType::I8, _) => b[0] as i8 as f32,
+            (SampleType::I16, true) => i16::from_le_bytes([b[0], b[1]]) as f32,
+            (SampleType::I16, false) => i16::from_be_bytes([b[0], b[1]]) as f32,
+            (SampleType::U16, true) => u16::from_le_bytes([b[0], b[1]]) as f32,
+            (SampleType::U16, false) => u16::from_be_bytes([b[0], b[1]]) as f32,
+            (SampleType::I32, true) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (SampleType::I32, false) => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (SampleType::U32, true) => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (SampleType::U32, false) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32,
+            (SampleType::F32, true) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            (SampleType::F32, false) => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            (SampleType::F64, true) => {
+                f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+            }
+            (SampleType::F64, false) => {
+                f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+            }
+        }
+    }
+}
+
+///
+/// Reads a single-file NIfTI-1 volume (`.nii` or gzip-compressed `.nii.gz`)
+///
+/// Dimensions come from `dim`, voxel spacing from `pixdim` and values are scaled by `scl_slope`/`scl_inter` when slope is non-zero.
+/// Only the first time point of 4D data is loaded.
+///
+/// # Returns
+/// * dimensions
+/// * scaled values normalized to [0, 1] by their min/max
+/// * normalized values quantized to u16
+/// * voxel spacing
+///
+pub fn load_nifti<P: AsRef<Path>>(data_path: P) -> Result<LoadedVolume<(f32, f32, f32)>> {
+    const HEADER_SIZE: usize = 348;
+    let raw = std::fs::read(&data_path)
+        .with_context(|| format!("Failed to read {}", data_path.as_ref().display()))?;
+    // gzip magic number, no matter what the extension says
+    let bytes = if raw.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(raw.as_slice()).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        raw
+    };
+    ensure!(
+        bytes.len() >= HEADER_SIZE,
+        "NIfTI file is shorter than its header"
+    );
+    // sizeof_hdr must read 348, which tells us the byte order of the file
+    let header_size = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    let little_endian = if i32::from_le_bytes(header_size) == HEADER_SIZE as i32 {
+        true
+    } else if i32::from_be_bytes(header_size) == HEADER_SIZE as i32 {
+        false
+    } else {
+        bail!("Not a NIfTI-1 file: sizeof_hdr is not 348");
+    };
+    ensure!(
+        &bytes[344..347] == b"n+1",
+        "Only single-file NIfTI-1 (n+1) is supported"
+    );
+    let read_i16 = |offset: usize| {
+        let b = [bytes[offset], bytes[offset + 1]];
+        if little_endian {
+            i16::from_le_bytes(b)
+        } else {
+            i16::from_be_bytes(b)
+        }
+    };
+    let read_f32 = |offset: usize| {
+        let b = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        if little_endian {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    };
+    // dim[0] is the number of dimensions, dim[1..=3] are x, y, z
+    ensure!(read_i16(40) >= 3, "NIfTI volume has less than 3 dimensions");
+    let (x, y, z) = (read_i16(42), read_i16(44), read_i16(46));
+    ensure!(
+        x > 0 && y > 0 && z > 0,
+        "Invalid NIfTI dimensions {}x{}x{}",
+        x,
+        y,
+        z
+    );
+    let (x, y, z) = (x as usize, y as usize, z as usize);
+    let datatype = read_i16(70);
+    let spacing = (read_f32(80).abs(), read_f32(84).abs(), read_f32(88).abs());
+    let vox_offset = read_f32(108) as usize;
+    // the voxels cannot start inside the header
+    ensure!(
+        vox_offset >= HEADER_SIZE,
+        "NIfTI vox_offset {} lies inside the {} byte header",
+        vox_offset,
+        HEADER_SIZE
+    );
+    let scl_slope = read_f32(112);
+    let scl_inter = read_f32(116);
+
+    let sample_type = match datatype {
+        2 => SampleType::U8,
+        256 => SampleType::I8,
+        4 => SampleType::I16,
+        512 => SampleType::U16,
+        8 => SampleType::I32,
+        768 => SampleType::U32,
+        16 => SampleType::F32,
+        64 => SampleType::F64,
+        _ => bail!("Unsupported NIfTI datatype {}", datatype),
+    };
+    let bytes_per_voxel = sample_type.size();
+    let data_end = vox_offset
+        .checked_add(volume_byte_size((x, y, z), bytes_per_voxel)?)
+        .context("NIfTI vox_offset is too large")?;
+    ensure!(
+        bytes.len() >= data_end,
+        "NIfTI data is truncated: expected {} bytes, found {}",
+        data_end,
+        bytes.len()
+    );
+    let values: Vec<f32> = bytes[vox_offset..data_end]
+        .par_chunks_exact(bytes_per_voxel)
+        .map(|b| sample_type.decode(b, little_endian))
+        .map(|v| {
+            if scl_slope != 0.0 {
+                v * scl_slope + scl_inter
+            } else {
+                v
+            }
+        })
+        .collect();
+    let (data, uint_data) = normalize_values(&values);
+    Ok(((x, y, z), data, uint_data, spacing))
+}
+
+/// Smallest and largest value, `(f32::MAX, f32::MIN)` for no values
+pub fn value_range(values: &[f32]) -> (f32, f32) {
+    values
+        .par_iter()
+        .fold(
+            || (f32::MAX, f32::MIN),
+            |(min, max), v| (min.min(*v), max.max(*v)),
+        )
+        .reduce(|| (f32::MAX, f32::MIN), |a, b| (a.0.min(b.0), a.1.max(b.1)))
+}
+
+///
+/// Maps `range` to [0, 1] and clamps the values outside of it, `None` uses the min/max of the values
+///
+/// # Returns
+/// The normalized values and the range used
+///
+pub fn normalize_by_range(values: &[f32], range: Option<(f32, f32)>) -> (Vec<f32>, (f32, f32)) {
+    let (min, max) = range.unwrap_or_else(|| value_range(values));
+    let scale = if max > min { max - min } else { 1.0 };
+    let data: Vec<f32> = values
+        .par_iter()
+        .map(|v| ((v - min) / scale).clamp(0.0, 1.0))
+        .collect();
+    (data, (min, max))
+}
+
+///
+/// Same as [`normalize_by_range`] for raw samples of any bit depth, without widening them all to f32 first
+///
+/// Without a `range`, one reduction finds the min/max before the samples are mapped.
+/// With `parallel` both run on rayon's thread pool, the result is the same either way.
+///
+pub fn normalize_u16_by_range(
+    samples: &[u16],
+    range: Option<(f32, f32)>,
+    parallel: bool,
+) -> (Vec<f32>, (f32, f32)) {
+    let min_max = |(min, max): (u16, u16), v: &u16| (min.min(*v), max.max(*v));
+    let (min, max) = range.unwrap_or_else(|| {
+        let (min, max) = if parallel {
+            samples
+                .par_iter()
+                .fold(|| (u16::MAX, u16::MIN), min_max)
+                .reduce(|| (u16::MAX, u16::MIN), |a, b| (a.0.min(b.0), a.1.max(b.1)))
+        } else {
+            samples.iter().fold((u16::MAX, u16::MIN), min_max)
+        };
+        (min as f32, max as f32)
+    });
+    let scale = if max > min { max - min } else { 1.0 };
+    let normalize = |v: &u16| ((*v as f32 - min) / scale).clamp(0.0, 1.0);
+    let data: Vec<f32> = if parallel {
+        samples.par_iter().map(normalize).collect()
+    } else {
+        samples.iter().map(normalize).collect()
+    };
+    (data, (min, max))
+}
+
+/// Normalizes values to [0, 1] by their min/max and quantizes the result to u16
+fn normalize_values(values: &[f32]) -> (Vec<f32>, Vec<u16>) {
+    let (min, max) = value_range(values);
+    let range = if max > min { max - min } else { 1.0 };
+    let data: Vec<f32> = values.par_iter().map(|v| (v - min) / range).collect();
+    let uint_data: Vec<u16> = data
         .par_iter()
-        .skip(3)
-        .map(|num| ((*num << 4) as f32) / U16MAX_F)
+        .map(|v| (v * u16::MAX as f32).round() as u16)
         .collect();
-    let uint_data = Vec::from_iter(unsigned_shorts[3..].iter().cloned());
-    assert_eq!(expected_data_num, data.len(), "Data size not match");
-    return ((x, y, z), data, uint_data);
+    (data, uint_data)
 }
 
+///
+/// Reads a 3D NRRD volume (`.nrrd`, or a detached `.nhdr` header with its `data file`)
+///
+/// Supports `raw` and `gzip` encodings of all scalar types but 64 bit integers.
+/// Voxel spacing comes from `spacings` or the lengths of `space directions` and defaults to 1.
+///
+/// # Returns
+/// * dimensions
+/// * values normalized to [0, 1] by their min/max
+/// * normalized values quantized to u16
+/// * voxel spacing
+///
+pub fn load_nrrd<P: AsRef<Path>>(data_path: P) -> Result<LoadedVolume<(f32, f32, f32)>> {
+    let data_path = data_path.as_ref();
+    let bytes = std::fs::read(data_path)
+        .with_context(|| format!("Failed to read {}", data_path.display()))?;
+    ensure!(bytes.starts_with(b"NRRD000"), "Not a NRRD file");
+    // the header ends with the first empty line
+    let header_end = bytes
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|i| i + 2)
+        .or_else(|| {
+            bytes
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .map(|i| i + 4)
+        })
+        .unwrap_or(bytes.len());
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut fields = std::collections::HashMap::new();
+    for line in header.lines().skip(1) {
+        let line = line.trim();
+        // key/value pairs use ":=" and are not needed for rendering
+        if line.is_empty() || line.starts_with('#') || line.contains(":=") {
+            continue;
+        }
+        if let Some((field, value)) = line.split_once(':') {
+            fields.insert(field.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let field = |name: &str| {
+        fields
+            .get(name)
+            .map(|v| v.as_str())
+            .ok_or_else(|| anyhow!("NRRD header has no {} field", name))
+    };
+    let sample_type = match field("type")? {
+        "uchar" | "unsigned char" | "uint8" | "uint8_t" => SampleType::U8,
+        "signed char" | "int8" | "int8_t" => SampleType::I8,
+        "short" | "short int" | "signed short" | "signed short int" | "int16" | "int16_t" => {
+            SampleType::I16
+        }
+        "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+            SampleType::U16
+        }
+        "int" | "signed int" | "int32" | "int32_t" => SampleType::I32,
+        "uint" | "unsigned int" | "uint32" | "uint32_t" => SampleType::U32,
+        "float" => SampleType::F32,
+        "double" => SampleType::F64,
+        other => bail!("Unsupported NRRD type {}", other),
+    };
+    let dimension: usize = field("dimension")?.parse()?;
+    ensure!(
+        dimension == 3,
+        "Only 3D NRRD volumes are supported, got {}D",
+        dimension
+    );
+    let sizes = field("sizes")?
+        .split_whitespace()
+        .map(|v| v.parse::<usize>())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    ensure!(sizes.len() == 3, "NRRD sizes must have 3 values");
+    let (x, y, z) = (sizes[0], sizes[1], sizes[2]);
+    let little_endian = match fields.get("endian").map(|v| v.as_str()) {
+        None | Some("little") => true,
+        Some("big") => false,
+        Some(other) => bail!("Unknown NRRD endian {}", other),
+    };
+    let spacing = if let Some(spacings) = fields.get("spacings") {
+        let spacings: Vec<f32> = spacings
+            .split_whitespace()
+            .map(|v| v.parse::<f32>().unwrap_or(f32::NAN))
+            .collect();
+        ensure!(spacings.len() == 3, "NRRD spacings must have 3 values");
+        spacings
+    } else if let Some(directions) = fields.get("space directions") {
+        // one vector per axis like (0.5,0,0), its length is the spacing
+        let spacings: Vec<f32> = directions
+            .split_whitespace()
+            .map(|v| {
+                v.trim_matches(|c| c == '(' || c == ')')
+                    .split(',')
+                    .map(|c| c.trim().parse::<f32>().unwrap_or(f32::NAN))
+                    .map(|c| c * c)
+                    .sum::<f32>()
+                    .sqrt()
+            })
+            .collect();
+        ensure!(
+            spacings.len() == 3,
+            "NRRD space directions must have 3 vectors"
+        );
+        spacings
+    } else {
+        vec![1.0; 3]
+    };
+    let spacing_or_one = |s: f32| if s.is_finite() && s > 0.0 { s } else { 1.0 };
+    let spacing = (
+        spacing_or_one(spacing[0]),
+        spacing_or_one(spacing[1]),
+        spacing_or_one(spacing[2]),
+    );
+
+    let body = match fields.get("data file").or_else(|| fields.get("datafile")) {
+        // detached data, relative to the header
+        Some(file) => {
+            let file_path = data_path.parent().unwrap_or(Path::new(".")).join(file);
+            std::fs::read(&file_path)
+                .with_context(|| format!("Failed to read {}", file_path.display()))?
+        }
+        None => bytes[header_end..].to_vec(),
+    };
+    let data_len = volume_byte_size((x, y, z), sample_type.size())?;
+    let body = match field("encoding")? {
+        "raw" => {
+            // a byte skip of -1 means the data sits at the end of the file
+            let skip = match fields.get("byte skip").map(|v| v.parse::<i64>()) {
+                None => 0,
+                Some(Ok(-1)) => body.len().saturating_sub(data_len),
+                Some(Ok(skip)) if skip >= 0 => skip as usize,
+                Some(_) => bail!("Invalid NRRD byte skip"),
+            };
+            body[skip.min(body.len())..].to_vec()
+        }
+        "gzip" | "gz" => {
+            let mut decoded = Vec::new();
+            GzDecoder::new(body.as_slice()).read_to_end(&mut decoded)?;
+            decoded
+        }
+        other => bail!("Unsupported NRRD encoding {}", other),
+    };
+    ensure!(
+        body.len() >= data_len,
+        "NRRD data is truncated: expected {} bytes, found {}",
+        data_len,
+        body.len()
+    );
+    let values: Vec<f32> = body[..data_len]
+        .par_chunks_exact(sample_type.size())
+        .map(|b| sample_type.decode(b, little_endian))
+        .collect();
+    let (data, uint_data) = normalize_values(&values);
+    Ok(((x, y, z), data, uint_data, spacing))
+}
+
+/// Attributes of a single DICOM slice needed to assemble a volume
+struct DicomSlice {
+    rows: usize,
+    columns: usize,
+    instance_number: Option<i32>,
+    image_position: Option<[f32; 3]>,
+    image_orientation: Option<[f32; 6]>,
+    /// spacing between rows and between columns
+    pixel_spacing: Option<[f32; 2]>,
+    slice_thickness: Option<f32>,
+    spacing_between_slices: Option<f32>,
+    values: Vec<f32>,
+}
+
+/// Parses a single uncompressed DICOM file with implicit or explicit VR little endian transfer syntax
+fn parse_dicom_slice(bytes: &[u8]) -> Result<DicomSlice> {
+    const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+    const EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1";
+    let read_u16 = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let read_u32 = |offset: usize| {
+        u32::from_le_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    };
+    let text = |value: &[u8]| {
+        String::from_utf8_lossy(value)
+            .trim_matches(|c: char| c == ' ' || c == '\0')
+            .to_string()
+    };
+    let decimals = |value: &[u8]| -> Result<Vec<f32>> {
+        Ok(text(value)
+            .split('\\')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<std::result::Result<_, _>>()?)
+    };
+    // files written without the 128 byte preamble and "DICM" prefix start right with the data set
+    let has_preamble = bytes.len() >= 132 && &bytes[128..132] == b"DICM";
+    let mut pos = if has_preamble { 132 } else { 0 };
+    let mut explicit_vr = false;
+    let mut data_set_started = false;
+    let mut transfer_syntax = String::from(IMPLICIT_VR_LITTLE_ENDIAN);
+    // depth of nested sequences with undefined length, their contents are skipped
+    let mut sequence_depth = 0;
+    let (mut rows, mut columns) = (None, None);
+    let (mut bits_allocated, mut bits_stored, mut pixel_representation) = (16, None, 0);
+    let (mut samples_per_pixel, mut rescale_slope, mut rescale_intercept) = (1, 1.0, 0.0);
+    let (mut instance_number, mut image_position, mut image_orientation) = (None, None, None);
+    let (mut pixel_spacing, mut slice_thickness, mut spacing_between_slices) = (None, None, None);
+    let mut pixel_data = None;
+    while pos + 8 <= bytes.len() {
+        let (group, element) = (read_u16(pos), read_u16(pos + 2));
+        if group != 0x0002 && !data_set_started {
+            // the transfer syntax from the file meta information applies to the rest of the file
+            data_set_started = true;
+            explicit_vr = match transfer_syntax.as_str() {
+                IMPLICIT_VR_LITTLE_ENDIAN => false,
+                EXPLICIT_VR_LITTLE_ENDIAN => true,
+                _ => bail!("Unsupported DICOM transfer syntax {}", transfer_syntax),
+            };
+        }
+        // the file meta information is always explicit VR
+        let explicit = group == 0x0002 || explicit_vr;
+        let (length, header_length) = if group == 0xFFFE {
+            // items and delimiters never carry a VR
+            (read_u32(pos + 4), 8)
+        } else if explicit {
+            let vr = &bytes[pos + 4..pos + 6];
+            if matches!(
+                vr,
+                b"OB" | b"OD" | b"OF" | b"OL" | b"OW" | b"SQ" | b"UC" | b"UR" | b"UT" | b"UN"
+            ) {
+                ensure!(pos + 12 <= bytes.len(), "DICOM element header is truncated");
+                (read_u32(pos + 8), 12)
+            } else {
+                (read_u16(pos + 6) as u32, 8)
+            }
+        } else {
+            (read_u32(pos + 4), 8)
+        };
+        pos += header_length;
+        if length == u32::MAX {
+            if (group, element) == (0x7FE0, 0x0010) {
+                bail!("Compressed (encapsulated) DICOM pixel data is not supported");
+            }
+            // sequences and items of undefined length end with a delimiter
+            if group != 0xFFFE {
+                sequence_depth += 1;
+            }
+            continue;
+        }
+        if group == 0xFFFE {
+            match element {
+                0xE0DD => sequence_depth -= 1,                  // sequence delimiter
+                0xE000 if length > 0 => pos += length as usize, // item of defined length
+                _ => {}
+            }
+            continue;
+        }
+        let end = pos + length as usize;
+        ensure!(
+            end <= bytes.len(),
+            "DICOM element ({:04X},{:04X}) is truncated",
+            group,
+            element
+        );
+        let value = &bytes[pos..end];
+        let value_offset = pos;
+        pos = end;
+        if sequence_depth > 0 {
+            continue;
+        }
+        if group == 0x0028 && matches!(element, 0x0002 | 0x0010 | 0x0011 | 0x0100 | 0x0101 | 0x0103)
+        {
+            // the US elements read below hold one 16-bit value
+            ensure!(
+                value.len() >= 2,
+                "DICOM element ({:04X},{:04X}) is too short",
+                group,
+                element
+            );
+        }
+        match (group, element) {
+            (0x0002, 0x0010) => transfer_syntax = text(value),
+            (0x0018, 0x0050) => slice_thickness = text(value).parse().ok(),
+            (0x0018, 0x0088) => spacing_between_slices = text(value).parse().ok(),
+            (0x0020, 0x0013) => instance_number = text(value).parse().ok(),
+            (0x0020, 0x0032) => {
+                let v = decimals(value)?;
+                ensure!(v.len() == 3, "ImagePositionPatient needs 3 values");
+                image_position = Some([v[0], v[1], v[2]]);
+            }
+            (0x0020, 0x0037) => {
+                let v = decimals(value)?;
+                ensure!(v.len() == 6, "ImageOrientationPatient needs 6 values");
+                image_orientation = Some([v[0], v[1], v[2], v[3], v[4], v[5]]);
+            }
+            (0x0028, 0x0030) => {
+                let v = decimals(value)?;
+                ensure!(v.len() == 2, "PixelSpacing needs 2 values");
+                pixel_spacing = Some([v[0], v[1]]);
+            }
+            (0x0028, 0x0002) => samples_per_pixel = read_u16(value_offset),
+            (0x0028, 0x0010) => rows = Some(read_u16(value_offset) as usize),
+            (0x0028, 0x0011) => columns = Some(read_u16(value_offset) as usize),
+            (0x0028, 0x0100) => bits_allocated = read_u16(value_offset),
+            (0x0028, 0x0101) => bits_stored = Some(read_u16(value_offset)),
+            (0x0028, 0x0103) => pixel_representation = read_u16(value_offset),
+            (0x0028, 0x1052) => {
+                rescale_intercept = decimals(value)?
+                    .first()
+                    .copied()
+                    .context("RescaleIntercept has no value")?
+            }
+            (0x0028, 0x1053) => {
+                rescale_slope = decimals(value)?
+                    .first()
+                    .copied()
+                    .context("RescaleSlope has no value")?
+            }
+            (0x7FE0, 0x0010) => pixel_data = Some(value),
+            _ => {}
+        }
+    }
+    let (rows, columns) = match (rows, columns) {
+        (Some(rows), Some(columns)) => (rows, columns),
+        _ => bail!("DICOM file has no Rows or Columns"),
+    };
+    let pixel_data = pixel_data.ok_or_else(|| anyhow!("DICOM file has no pixel data"))?;
+    ensure!(
+        samples_per_pixel == 1,
+        "Only single-channel DICOM images are supported, got {} samples per pixel",
+        samples_per_pixel
+    );
+    ensure!(
+        bits_allocated == 8 || bits_allocated == 16,
+        "Unsupported DICOM BitsAllocated {}",
+        bits_allocated
+    );
+    let bytes_per_pixel = bits_allocated as usize / 8;
+    ensure!(
+        pixel_data.len() >= rows * columns * bytes_per_pixel,
+        "DICOM pixel data is truncated"
+    );
+    // e.g. 12 bit values stored in 16 bit words, higher bits may hold overlays
+    let bits_stored = bits_stored.unwrap_or(bits_allocated).min(bits_allocated) as u32;
+    let unused_bits = 32 - bits_stored;
+    let signed = pixel_representation == 1;
+    let values = pixel_data[..rows * columns * bytes_per_pixel]
+        .par_chunks_exact(bytes_per_pixel)
+        .map(|b| {
+            let raw = if bytes_per_pixel == 1 {
+                b[0] as u32
+            } else {
+                u16::from_le_bytes([b[0], b[1]]) as u32
+            };
+            // move the stored bits to the top, then back with sign extension if needed
+            let stored = if signed {
+                ((raw << unused_bits) as i32 >> unused_bits) as f32
+            } else {
+                ((raw << unused_bits) >> unused_bits) as f32
+            };
+            stored * rescale_slope + rescale_intercept
+        })
+        .collect();
+    Ok(DicomSlice {
+        rows,
+        columns,
+        instance_number,
+        image_position,
+        image_orientation,
+        pixel_spacing,
+        slice_thickness,
+        spacing_between_slices,
+        values,
+    })
+}
+
+///
+/// Reads all `.dcm` files of a directory as slices of one volume
+///
+/// Slices are ordered along the slice normal by `ImagePositionPatient` if every slice has a position and orientation,
+/// otherwise by `InstanceNumber`. Stored values are sign extended according to `BitsStored` and `PixelRepresentation`
+/// and scaled by `RescaleSlope`/`RescaleIntercept`.
+/// Only uncompressed little endian transfer syntaxes are supported.
+///
+/// # Returns
+/// * dimensions
+/// * rescaled values normalized to [0, 1] by their min/max
+/// * normalized values quantized to u16
+/// * voxel spacing if the slices have a `PixelSpacing`, the slice distance is taken from the slice positions,
+///   `SpacingBetweenSlices` or `SliceThickness` in that order
+///
+pub fn load_dicom_series<P: AsRef<Path>>(dir_path: P) -> Result<LoadedVolume> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir_path)? {
+        let path = entry?.path();
+        let is_dicom = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dcm"));
+        if path.is_file() && is_dicom {
+            paths.push(path);
+        }
+    }
+    ensure!(!paths.is_empty(), "No .dcm files found");
+    let mut slices = paths
+        .par_iter()
+        .map(|path| {
+            let bytes = std::fs::read(path)?;
+            parse_dicom_slice(&bytes)
+                .map_err(|e| e.context(format!("Failed to read {}", path.display())))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (rows, columns) = (slices[0].rows, slices[0].columns);
+    ensure!(
+        slices
+            .iter()
+            .all(|s| s.rows == rows && s.columns == columns),
+        "DICOM slices have different sizes"
+    );
+    let positions: Option<Vec<f32>> = slices
+        .iter()
+        .map(|s| match (s.image_position, s.image_orientation) {
+            (Some(p), Some(o)) => {
+                // distance along the slice normal, the cross product of the row and column directions
+                let normal = V3::new(o[0], o[1], o[2]).cross(V3::new(o[3], o[4], o[5]));
+                Some(normal.dot(V3::new(p[0], p[1], p[2])))
+            }
+            _ => None,
+        })
+        .collect();
+    let mut slice_distance = None;
+    match positions {
+        Some(positions) => {
+            let mut sorted: Vec<(f32, DicomSlice)> = positions.into_iter().zip(slices).collect();
+            sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+            if sorted.len() > 1 {
+                let distance = sorted[sorted.len() - 1].0 - sorted[0].0;
+                slice_distance = Some(distance / (sorted.len() - 1) as f32).filter(|d| *d > 0.0);
+            }
+            slices = sorted.into_iter().map(|(_, slice)| slice).collect();
+        }
+        None => slices.sort_by_key(|s| s.instance_number.unwrap_or(0)),
+    }
+    let spacing = slices[0]
+        .pixel_spacing
+        .map(|[row_spacing, column_spacing]| {
+            let slice_distance = slice_distance
+                .or(slices[0].spacing_between_slices)
+                .or(slices[0].slice_thickness)
+                .unwrap_or(1.0);
+            // x runs along a row, so its spacing is the distance between columns
+            (column_spacing, row_spacing, slice_distance)
+        });
+    let z = slices.len();
+    let values: Vec<f32> = slices.into_iter().flat_map(|s| s.values).collect();
+    let (data, uint_data) = normalize_values(&values);
+    Ok(((columns, rows, z), data, uint_data, spacing))
+}
+
+/// Number at the end of the file stem, e.g. 12 of `slice_012.png`
+fn slice_number(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let prefix_len = stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    stem[prefix_len..].parse().ok()
+}
+
+///
+/// Reads all `.png`, `.tif` and `.tiff` files of a directory as slices of one volume, e.g. `slice_000.png` to `slice_099.png`
+///
+/// Slices are ordered by the number at the end of their file names, so the numbers do not need leading zeros.
+/// All slices must be 8-bit or all 16-bit grayscale images of the same size,
+/// the first image row becomes y = 0.
+///
+/// # Returns
+/// * dimensions: image width, image height and number of slices
+/// * data normalized by 255 or 65535
+/// * original data widened to u16
+/// * voxel spacing, always `None`
+///
+pub fn load_volume_from_image_stack<P: AsRef<Path>>(dir_path: P) -> Result<LoadedVolume> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir_path)? {
+        let path = entry?.path();
+        let is_image = path.extension().is_some_and(|ext| {
+            ["png", "tif", "tiff"]
+                .iter()
+                .any(|image_ext| ext.eq_ignore_ascii_case(image_ext))
+        });
+        if path.is_file() && is_image {
+            paths.push(path);
+        }
+    }
+    ensure!(!paths.is_empty(), "No .png or .tiff files found");
+    paths.sort_by_cached_key(|path| (slice_number(path), path.clone()));
+    // (width, height, bits per sample, samples) of every slice
+    let slices = paths
+        .par_iter()
+        .map(|path| {
+            let image = image::open(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            match image {
+                image::DynamicImage::ImageLuma8(image) => Ok((
+                    width,
+                    height,
+                    8,
+                    image.into_raw().into_iter().map(u16::from).collect(),
+                )),
+                image::DynamicImage::ImageLuma16(image) => {
+                    Ok((width, height, 16, image.into_raw()))
+                }
+                other => bail!(
+                    "{} is {:?}, not 8- or 16-bit grayscale",
+                    path.display(),
+                    other.color()
+                ),
+            }
+        })
+        .collect::<Result<Vec<(usize, usize, u32, Vec<u16>)>>>()?;
+    let (width, height, bits) = (slices[0].0, slices[0].1, slices[0].2);
+    ensure!(
+        slices.iter().all(|s| (s.0, s.1) == (width, height)),
+        "Slices have different sizes"
+    );
+    ensure!(
+        slices.iter().all(|s| s.2 == bits),
+        "Slices mix 8-bit and 16-bit images"
+    );
+    let z = slices.len();
+    let uint_data: Vec<u16> = slices.into_iter().flat_map(|s| s.3).collect();
+    let max = ((1u32 << bits) - 1) as f32;
+    let data: Vec<f32> = uint_data.par_iter().map(|v| *v as f32 / max).collect();
+    Ok(((width, height, z), data, uint_data, None))
+}
+
+/// How the alpha channel of an exported frame is stored
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportAlpha {
+    /// color is divided by alpha, what most image viewers and editors expect
+    Straight,
+    /// color is multiplied by alpha, as written by the canvas pass
+    Premultiplied,
+}
+
+///
+/// Saves a frame rendered by the canvas pass as PNG
+///
+/// The canvas pass composites front to back, so its color is premultiplied by the ray-marched coverage
+/// and its alpha is exactly that coverage, given the canvas was cleared to transparent black.
+///
+pub fn save_frame_png<P: AsRef<Path>>(
+    frame: &image::RgbaImage,
+    path: P,
+    alpha: ExportAlpha,
+) -> Result<()> {
+    let mut frame = frame.clone();
+    if alpha == ExportAlpha::Straight {
+        frame.par_chunks_exact_mut(4).for_each(|pixel| {
+            let a = pixel[3] as f32 / u8::MAX as f32;
+            if a > 0.0 {
+                for c in &mut pixel[..3] {
+                    *c = (*c as f32 / a).round().min(u8::MAX as f32) as u8;
+                }
+            }
+        });
+    }
+    frame.save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// Transfer function of the canvas pass until another one is set, the perceptually uniform and color-blind safe
+/// `viridis` preset over [`TRANSFER_FUNCTION_RESOLUTION`] entries
 pub fn load_example_transfer_function() -> Vec<cgmath::Vector4<u8>> {
+    transfer_function_preset("viridis").unwrap()
+}
+
+/// The former example transfer function, cyan, yellow and magenta bands of varying opacity
+fn classic_transfer_function() -> Vec<cgmath::Vector4<u8>> {
     #[rustfmt::skip]
     static TF: [f32; 48] = [
         0.0, 0.0, 0.0, 0.0,
@@ -193,19 +1759,914 @@ pub fn load_example_transfer_function() -> Vec<cgmath::Vector4<u8>> {
         1.0, 0.0, 1.0, 0.0,
         1.0, 0.0, 1.0, 0.8
     ];
-    TF[..]
+    let control_points: Vec<cgmath::Vector4<u8>> = TF[..]
         .chunks_exact(4)
         .map(|x| cgmath::Vector4::new(x[0], x[1], x[2], x[3]))
         .map(|v| v * (u8::MAX as f32))
         .map(|v| cgmath::Vector4::new(v.x as u8, v.y as u8, v.z as u8, v.w as u8))
+        .collect();
+    // 12 entries would leave the linear sampler interpolating over wide texels
+    resample_transfer_function(&control_points, TRANSFER_FUNCTION_RESOLUTION)
+}
+
+///
+/// Picks the format to configure a surface with and the format of the views to render into
+///
+/// The passes composite linear color and rely on an sRGB target to encode it, see [`crate::shading::Tex::TRANSFER_FUNCTION_FORMAT`].
+/// An sRGB format the surface supports is preferred. Otherwise the first supported format is configured and
+/// rendered through its sRGB view format, which has to be listed in the view formats of the surface configuration.
+/// `supported` are the formats of the surface capabilities, in the order of preference of the adapter.
+///
+pub fn choose_surface_format(
+    supported: &[wgpu::TextureFormat],
+) -> (wgpu::TextureFormat, wgpu::TextureFormat) {
+    let format = supported
+        .iter()
+        .copied()
+        .find(wgpu::TextureFormat::is_srgb)
+        .unwrap_or(supported[0]);
+    (format, format.add_srgb_suffix())
+}
+
+/// Number of entries transfer functions loaded from files are resampled to
+pub const TRANSFER_FUNCTION_RESOLUTION: usize = 256;
+
+/// Piecewise-linear interpolation of `(scalar, rgba)` control points sorted by scalar
+fn resample_control_points(points: &[(f32, [f32; 4])], out_len: usize) -> Vec<cgmath::Vector4<u8>> {
+    (0..out_len)
+        .map(|i| {
+            let s = i as f32 / (out_len - 1).max(1) as f32;
+            let upper = points
+                .iter()
+                .position(|p| p.0 >= s)
+                .unwrap_or(points.len() - 1);
+            let lower = upper.saturating_sub(1);
+            let (s0, c0) = points[lower];
+            let (s1, c1) = points[upper];
+            let t = if s1 > s0 {
+                ((s - s0) / (s1 - s0)).clamp(0.0, 1.0)
+            } else {
+                1.0
+            };
+            let c: Vec<u8> = (0..4)
+                .map(|k| ((c0[k] + (c1[k] - c0[k]) * t) * u8::MAX as f32).round() as u8)
+                .collect();
+            cgmath::Vector4::new(c[0], c[1], c[2], c[3])
+        })
+        .collect()
+}
+
+///
+/// Piecewise-linear resampling of a transfer function of any length to `out_len` entries,
+/// the first and the last entry stay at the ends and the ones in between are evenly spaced
+///
+/// The texture sampler interpolates smoothly between entries of the result however few control points were authored.
+///
+pub fn resample_transfer_function(
+    points: &[cgmath::Vector4<u8>],
+    out_len: usize,
+) -> Vec<cgmath::Vector4<u8>> {
+    assert!(
+        !points.is_empty(),
+        "Transfer function has no control points"
+    );
+    let last = (points.len() - 1).max(1) as f32;
+    let control_points: Vec<(f32, [f32; 4])> = points
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let c = c.map(|v| v as f32 / u8::MAX as f32);
+            (i as f32 / last, [c.x, c.y, c.z, c.w])
+        })
+        .collect();
+    resample_control_points(&control_points, out_len)
+}
+
+///
+/// Loads a transfer function from control points and resamples it to [`TRANSFER_FUNCTION_RESOLUTION`] entries
+///
+/// Either CSV rows of `scalar,r,g,b,a` (lines starting with `#` and a header are skipped)
+/// or a JSON array of `[scalar, r, g, b, a]` arrays. All values must be in [0, 1].
+///
+pub fn load_transfer_function_from_file<P: AsRef<Path>>(
+    path: P,
+) -> Result<Vec<cgmath::Vector4<u8>>> {
+    let content = std::fs::read_to_string(path)?;
+    let rows: Vec<Vec<f32>> = if content.trim_start().starts_with('[') {
+        let json: Vec<Vec<f64>> = serde_json::from_str(&content)?;
+        json.into_iter()
+            .map(|row| row.into_iter().map(|v| v as f32).collect())
+            .collect()
+    } else {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter(|line| !line.starts_with(|c: char| c.is_alphabetic())) // header
+            .map(|line| {
+                line.split(',')
+                    .map(|v| v.trim().parse::<f32>())
+                    .collect::<std::result::Result<Vec<f32>, _>>()
+                    .map_err(|e| {
+                        anyhow::anyhow!("Malformed transfer function row '{}': {}", line, e)
+                    })
+            })
+            .collect::<Result<_>>()?
+    };
+    ensure!(!rows.is_empty(), "Transfer function has no control points");
+    let mut points = Vec::with_capacity(rows.len());
+    for row in rows {
+        ensure!(
+            row.len() == 5,
+            "Control point {:?} should be scalar, r, g, b, a",
+            row
+        );
+        ensure!(
+            row.iter().all(|v| (0.0..=1.0).contains(v)),
+            "Control point {:?} is out of the [0, 1] range",
+            row
+        );
+        points.push((row[0], [row[1], row[2], row[3], row[4]]));
+    }
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(resample_control_points(
+        &points,
+        TRANSFER_FUNCTION_RESOLUTION,
+    ))
+}
+
+/// Names accepted by [`transfer_function_preset`]
+pub const TRANSFER_FUNCTION_PRESETS: [&str; 7] = [
+    "grayscale",
+    "hot",
+    "cool",
+    "bone",
+    "jet",
+    "viridis",
+    "classic",
+];
+
+///
+/// Built-in transfer functions following common scientific colormaps, see [`TRANSFER_FUNCTION_PRESETS`]
+///
+/// Opacity ramps up linearly with the scalar so that low values such as air stay transparent,
+/// except for `classic`, the former example transfer function. Returns `None` for unknown names.
+///
+pub fn transfer_function_preset(name: &str) -> Option<Vec<cgmath::Vector4<u8>>> {
+    // (scalar, rgb) control points of the colormaps, matching the matplotlib definitions
+    let colors: &[(f32, [f32; 3])] = match name {
+        "classic" => return Some(classic_transfer_function()),
+        "grayscale" => &[(0.0, [0.0, 0.0, 0.0]), (1.0, [1.0, 1.0, 1.0])],
+        "hot" => &[
+            (0.0, [0.0416, 0.0, 0.0]),
+            (0.365079, [1.0, 0.0, 0.0]),
+            (0.746032, [1.0, 1.0, 0.0]),
+            (1.0, [1.0, 1.0, 1.0]),
+        ],
+        "cool" => &[(0.0, [0.0, 1.0, 1.0]), (1.0, [1.0, 0.0, 1.0])],
+        "bone" => &[
+            (0.0, [0.0, 0.0, 0.0]),
+            (0.365079, [0.319444, 0.319444, 0.444444]),
+            (0.746032, [0.652778, 0.777778, 0.777778]),
+            (1.0, [1.0, 1.0, 1.0]),
+        ],
+        "jet" => &[
+            (0.0, [0.0, 0.0, 0.5]),
+            (0.125, [0.0, 0.0, 1.0]),
+            (0.375, [0.0, 1.0, 1.0]),
+            (0.625, [1.0, 1.0, 0.0]),
+            (0.875, [1.0, 0.0, 0.0]),
+            (1.0, [0.5, 0.0, 0.0]),
+        ],
+        // 16 evenly spaced samples of the matplotlib table, close enough for linear interpolation
+        "viridis" => &[
+            (0.0 / 15.0, [0.267004, 0.004874, 0.329415]),
+            (1.0 / 15.0, [0.282327, 0.094955, 0.417331]),
+            (2.0 / 15.0, [0.277018, 0.185228, 0.489898]),
+            (3.0 / 15.0, [0.253935, 0.265254, 0.529983]),
+            (4.0 / 15.0, [0.221989, 0.339161, 0.548752]),
+            (5.0 / 15.0, [0.190631, 0.407061, 0.556089]),
+            (6.0 / 15.0, [0.163625, 0.471133, 0.558148]),
+            (7.0 / 15.0, [0.139147, 0.533812, 0.555298]),
+            (8.0 / 15.0, [0.120565, 0.596422, 0.543611]),
+            (9.0 / 15.0, [0.134692, 0.658636, 0.517649]),
+            (10.0 / 15.0, [0.208030, 0.718701, 0.472873]),
+            (11.0 / 15.0, [0.327796, 0.773980, 0.406640]),
+            (12.0 / 15.0, [0.477504, 0.821444, 0.318195]),
+            (13.0 / 15.0, [0.647257, 0.858400, 0.209861]),
+            (14.0 / 15.0, [0.824940, 0.884720, 0.106217]),
+            (1.0, [0.993248, 0.906157, 0.143936]),
+        ],
+        _ => return None,
+    };
+    let points: Vec<(f32, [f32; 4])> = colors
+        .iter()
+        .map(|&(s, [r, g, b])| (s, [r, g, b, s]))
+        .collect();
+    Some(resample_control_points(
+        &points,
+        TRANSFER_FUNCTION_RESOLUTION,
+    ))
+}
+
+/// Blends two transfer functions entry by entry, `t` = 0 gives `from` and 1 gives `to`.
+/// `from` is resampled to the length of `to` if they differ, e.g. for a loaded transfer function and a preset
+pub fn interpolate_transfer_functions(
+    from: &[cgmath::Vector4<u8>],
+    to: &[cgmath::Vector4<u8>],
+    t: f32,
+) -> Vec<cgmath::Vector4<u8>> {
+    let resampled;
+    let from = if from.len() == to.len() {
+        from
+    } else {
+        resampled = resample_transfer_function(from, to.len());
+        &resampled
+    };
+    let t = t.clamp(0.0, 1.0);
+    let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    from.iter()
+        .zip(to)
+        .map(|(a, b)| {
+            cgmath::Vector4::new(
+                lerp(a.x, b.x),
+                lerp(a.y, b.y),
+                lerp(a.z, b.z),
+                lerp(a.w, b.w),
+            )
+        })
         .collect()
 }
 
+///
+/// Collapses a 2D transfer function of `width` scalars by `height` gradient magnitudes, stored row by row,
+/// to the 1D transfer function with the highest opacity of every scalar column
+///
+/// A block that this maps to zero opacity stays invisible at any gradient magnitude,
+/// so [`OccupancyGrid::classify`] can use it for the 2D transfer function.
+///
+pub fn max_opacity_over_gradients(
+    width: usize,
+    height: usize,
+    transfer_function_2d: &[cgmath::Vector4<u8>],
+) -> Vec<cgmath::Vector4<u8>> {
+    assert_eq!(
+        transfer_function_2d.len(),
+        width * height,
+        "Transfer function size not match"
+    );
+    (0..width)
+        .map(|x| {
+            (0..height)
+                .map(|y| transfer_function_2d[x + y * width])
+                .max_by_key(|c| c.w)
+                .unwrap()
+        })
+        .collect()
+}
+
+///
+/// Pre-integrates a 1D transfer function for ray segments, entry `front + back * n` of the `n` by `n` table
+/// holds the color and opacity of a segment of one sample step whose scalar goes linearly from `front` to `back`
+///
+/// Opacities are integrated as extinction `-ln(1 - alpha)` and the average extinction over the scalars
+/// converted back with `1 - exp(-average)`, so a thin opaque spike still occludes the segment.
+/// Colors are weighted by extinction so a transparent entry does not darken the segment.
+/// The diagonal is the transfer function itself. Upload it with [`crate::shading::Tex::create_2d_texture_rgba8`],
+/// see [`crate::rendering::CanvasPass::set_preintegration_enabled`].
+///
+pub fn compute_preintegration_table(
+    transfer_function: &[cgmath::Vector4<u8>],
+) -> Vec<cgmath::Vector4<u8>> {
+    // fully opaque entries get a large finite extinction, which still converts back to 255
+    const MAX_OPACITY: f64 = 1.0 - 1e-6;
+    let n = transfer_function.len();
+    // prefix sums of extinction weighted colors and of extinction, so each entry is O(1)
+    let mut sums = vec![[0.0f64; 4]; n + 1];
+    for (i, c) in transfer_function.iter().enumerate() {
+        let extinction = -(1.0 - (c.w as f64 / 255.0).min(MAX_OPACITY)).ln();
+        sums[i + 1] = [
+            sums[i][0] + c.x as f64 * extinction,
+            sums[i][1] + c.y as f64 * extinction,
+            sums[i][2] + c.z as f64 * extinction,
+            sums[i][3] + extinction,
+        ];
+    }
+    let mut table = Vec::with_capacity(n * n);
+    for back in 0..n {
+        for front in 0..n {
+            let (lo, hi) = (front.min(back), front.max(back) + 1);
+            let s: [f64; 4] = std::array::from_fn(|k| sums[hi][k] - sums[lo][k]);
+            let color = |k: usize| {
+                if s[3] > 0.0 {
+                    (s[k] / s[3]).round() as u8
+                } else {
+                    0
+                }
+            };
+            let alpha = ((1.0 - (-s[3] / (hi - lo) as f64).exp()) * 255.0).round() as u8;
+            table.push(cgmath::Vector4::new(color(0), color(1), color(2), alpha));
+        }
+    }
+    table
+}
+
 #[cfg(test)]
 mod util_tests {
     use super::*;
+
+    /// `name` in the temp directory, unique per process so that concurrent test runs do not share the file
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("wenderer_test_{}_{}", std::process::id(), name))
+    }
+
     #[test]
     fn test_load_data() {
-        let (_, _, _data) = load_volume_data("./data/stagbeetle277x277x164.dat");
+        let (_, _, _data, _) = load_volume_data("./data/stagbeetle277x277x164.dat").unwrap();
+    }
+
+    #[test]
+    fn test_load_nifti() {
+        let mut bytes = vec![0u8; 352];
+        bytes[0..4].copy_from_slice(&348i32.to_le_bytes());
+        for (i, d) in [3i16, 2, 2, 1].iter().enumerate() {
+            bytes[40 + 2 * i..42 + 2 * i].copy_from_slice(&d.to_le_bytes());
+        }
+        bytes[70..72].copy_from_slice(&4i16.to_le_bytes()); // i16
+        for (i, s) in [1.0f32, 0.5, 0.5, 3.0].iter().enumerate() {
+            bytes[76 + 4 * i..80 + 4 * i].copy_from_slice(&s.to_le_bytes());
+        }
+        bytes[108..112].copy_from_slice(&352f32.to_le_bytes());
+        bytes[112..116].copy_from_slice(&2f32.to_le_bytes());
+        bytes[344..348].copy_from_slice(b"n+1\0");
+        for v in [-4i16, 0, 4, 12] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let path = temp_path("volume.nii");
+        std::fs::write(&path, &bytes).unwrap();
+        let (dims, data, _, spacing) = load_nifti(&path).unwrap();
+        assert_eq!(dims, (2, 2, 1));
+        assert_eq!(spacing, (0.5, 0.5, 3.0));
+        assert_eq!(data, vec![0.0, 0.25, 0.5, 1.0]);
+        // voxels starting inside the header would decode header bytes
+        bytes[108..112].copy_from_slice(&0f32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(load_nifti(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_transfer_function_from_file() {
+        let path = temp_path("tf.csv");
+        std::fs::write(&path, "scalar,r,g,b,a\n0.0,0,0,0,0\n1.0,1,1,1,1\n").unwrap();
+        let tf = load_transfer_function_from_file(&path).unwrap();
+        assert_eq!(tf.len(), TRANSFER_FUNCTION_RESOLUTION);
+        assert_eq!(tf[0], cgmath::Vector4::new(0, 0, 0, 0));
+        assert_eq!(tf[255], cgmath::Vector4::new(255, 255, 255, 255));
+        assert_eq!(tf[51].x, 51);
+        std::fs::write(&path, "[[0.0, 0, 0, 0, 0], [1.5, 1, 1, 1, 1]]").unwrap();
+        assert!(load_transfer_function_from_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_occupancy_grid() {
+        // a bright voxel in the last corner of a 4x4x4 volume
+        let mut data = vec![0.0f32; 64];
+        data[63] = 1.0;
+        data[0] = 0.3;
+        let grid = build_occupancy_grid(&data, (4, 4, 4), 2);
+        assert_eq!(grid.dims, (2, 2, 2));
+        assert_eq!(grid.min_max[0], (0.0, 0.3));
+        assert_eq!(grid.min_max[7], (0.0, 1.0));
+        // only high scalars are visible
+        let mut tf = vec![cgmath::Vector4::new(0u8, 0, 0, 0); 256];
+        tf[255].w = 255;
+        let occupancy = grid.classify(&tf, |scalar| scalar);
+        assert_eq!(occupancy[0], 0);
+        assert_eq!(occupancy[7], 255);
+        // a narrow window stretches the dim corner over the visible end
+        let uniforms = crate::data::CanvasShaderUniforms {
+            window_center: 0.15,
+            window_width: 0.3,
+            ..Default::default()
+        };
+        let occupancy = grid.classify(&tf, |scalar| uniforms.transfer_function_scalar(scalar));
+        assert_eq!((occupancy[0], occupancy[1]), (255, 0));
+        // a gamma below 1 lifts the dim corner as well
+        let uniforms = crate::data::CanvasShaderUniforms {
+            scalar_gamma: 0.001,
+            ..Default::default()
+        };
+        let occupancy = grid.classify(&tf, |scalar| uniforms.transfer_function_scalar(scalar));
+        assert_eq!((occupancy[0], occupancy[1]), (255, 0));
+    }
+
+    #[test]
+    fn test_downsample_volume() {
+        // 3x2x1: the odd last column is averaged into the last block
+        let data = vec![0.0f32, 2.0, 4.0, 6.0, 8.0, 10.0];
+        let (downsampled, dims) = downsample_volume(&data, (3, 2, 1), 2);
+        assert_eq!(dims, (1, 1, 1));
+        assert_eq!(downsampled, vec![5.0]);
+        let data: Vec<f32> = (0..16).map(|v| v as f32).collect();
+        let (downsampled, dims) = downsample_volume(&data, (4, 2, 2), 2);
+        assert_eq!(dims, (2, 1, 1));
+        assert_eq!(
+            downsampled,
+            vec![(0.0 + 1.0 + 4.0 + 5.0 + 8.0 + 9.0 + 12.0 + 13.0) / 8.0, 8.5]
+        );
+        // a quarter of 9 voxels keeps 2, the last one averages the remaining 5
+        let data: Vec<f32> = (0..9).map(|v| v as f32).collect();
+        let (downsampled, dims) = downsample_volume(&data, (9, 1, 1), 4);
+        assert_eq!(dims, (2, 1, 1));
+        assert_eq!(downsampled, vec![1.5, 6.0]);
+    }
+
+    #[test]
+    fn test_combine_channels() {
+        let red_stain = vec![1.0f32, 0.0, 0.5];
+        let green_stain = vec![0.0f32, 0.25, 1.0];
+        let rgba = combine_channels(
+            &[&red_stain, &green_stain],
+            &[[1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        );
+        let rgba: Vec<f32> = rgba.iter().map(|v| v.to_f32()).collect();
+        assert_eq!(rgba.len(), 12);
+        assert_eq!(&rgba[0..4], &[1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(&rgba[4..8], &[0.0, 0.25, 0.0, 0.25]);
+        // overlapping stains add up, the opacity follows the stronger one
+        assert_eq!(&rgba[8..12], &[0.5, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_load_volume_from_image_stack() {
+        let dir = temp_path("image_stack");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // 2x1 slices numbered without leading zeros, slice_10 comes last
+        for (number, values) in [(10, [5u16, 6]), (1, [1, 2]), (2, [3, 65535])] {
+            image::ImageBuffer::<image::Luma<u16>, _>::from_raw(2, 1, values.to_vec())
+                .unwrap()
+                .save(dir.join(format!("slice_{}.png", number)))
+                .unwrap();
+        }
+        let (dims, data, uint_data, spacing) = load_volume_from_image_stack(&dir).unwrap();
+        assert_eq!(dims, (2, 1, 3));
+        assert_eq!(uint_data, vec![1, 2, 3, 65535, 5, 6]);
+        assert_eq!(data[3], 1.0);
+        assert_eq!(spacing, None);
+        // 8-bit slices are normalized by 255
+        let dir_8bit = dir.join("8bit");
+        std::fs::create_dir_all(&dir_8bit).unwrap();
+        image::GrayImage::from_raw(1, 2, vec![0, 255])
+            .unwrap()
+            .save(dir_8bit.join("slice_000.tif"))
+            .unwrap();
+        let (dims, data, _, _) = load_volume_from_image_stack(&dir_8bit).unwrap();
+        assert_eq!(dims, (1, 2, 1));
+        assert_eq!(data, vec![0.0, 1.0]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_dicom_series() {
+        // explicit VR little endian element
+        fn element(group: u16, element: u16, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+            let mut bytes = [group.to_le_bytes(), element.to_le_bytes()].concat();
+            bytes.extend_from_slice(vr);
+            if vr == b"OW" {
+                bytes.extend_from_slice(&[0, 0]);
+                bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            } else {
+                bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            }
+            bytes.extend_from_slice(value);
+            bytes
+        }
+        // emptied first, so that no other slices are loaded with these
+        let dir = temp_path("dicom");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // two 2x1 slices of signed 12 bit values, the second one is written first
+        for (instance, raw) in [(2, [0x0FFFu16, 0x0001]), (1, [0x0800, 0x07FF])] {
+            let mut bytes = vec![0u8; 128];
+            bytes.extend_from_slice(b"DICM");
+            bytes.extend(element(0x0002, 0x0010, b"UI", b"1.2.840.10008.1.2.1\0"));
+            bytes.extend(element(0x0018, 0x0050, b"DS", b"3 "));
+            bytes.extend(element(
+                0x0020,
+                0x0013,
+                b"IS",
+                format!("{} ", instance).as_bytes(),
+            ));
+            bytes.extend(element(0x0028, 0x0010, b"US", &1u16.to_le_bytes()));
+            bytes.extend(element(0x0028, 0x0011, b"US", &2u16.to_le_bytes()));
+            bytes.extend(element(0x0028, 0x0030, b"DS", b"0.5\\0.5 "));
+            bytes.extend(element(0x0028, 0x0100, b"US", &16u16.to_le_bytes()));
+            bytes.extend(element(0x0028, 0x0101, b"US", &12u16.to_le_bytes()));
+            bytes.extend(element(0x0028, 0x0103, b"US", &1u16.to_le_bytes()));
+            bytes.extend(element(0x0028, 0x1053, b"DS", b"2 "));
+            let pixels: Vec<u8> = raw.iter().flat_map(|v| v.to_le_bytes()).collect();
+            bytes.extend(element(0x7FE0, 0x0010, b"OW", &pixels));
+            std::fs::write(dir.join(format!("{}.dcm", instance)), bytes).unwrap();
+        }
+        let loaded = load_dicom_series(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let ((x, y, z), data, _, spacing) = loaded.unwrap();
+        assert_eq!(spacing, Some((0.5, 0.5, 3.0)));
+        assert_eq!((x, y, z), (2, 1, 2));
+        // stored values -2048, 2047, -1, 1, all scaled by 2
+        let expected = [0.0, 1.0, 2047.0 / 4095.0, 2049.0 / 4095.0];
+        for (v, e) in data.iter().zip(expected) {
+            assert!((v - e).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_load_nrrd() {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        for v in [-10i16, 0, 10, 30] {
+            encoder.write_all(&v.to_be_bytes()).unwrap();
+        }
+        let mut bytes = b"NRRD0004\n# comment\ntype: short\ndimension: 3\nsizes: 2 2 1\n\
+            space directions: (0.5,0,0) (0,0.5,0) (0,0,3)\nendian: big\nencoding: gzip\n\n"
+            .to_vec();
+        bytes.extend(encoder.finish().unwrap());
+        let path = temp_path("volume.nrrd");
+        std::fs::write(&path, bytes).unwrap();
+        let ((x, y, z), data, _, spacing) = load_nrrd(&path).unwrap();
+        assert_eq!((x, y, z), (2, 2, 1));
+        assert_eq!(spacing, (0.5, 0.5, 3.0));
+        assert_eq!(data, vec![0.0, 0.25, 0.5, 1.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transfer_function_presets() {
+        for name in TRANSFER_FUNCTION_PRESETS {
+            let tf = transfer_function_preset(name).unwrap();
+            assert_eq!(tf.len(), TRANSFER_FUNCTION_RESOLUTION);
+            if name != "classic" {
+                assert_eq!((tf[0].w, tf[255].w), (0, 255));
+            }
+        }
+        // the default is viridis, dark purple to yellow, the former one stays available
+        let viridis = load_example_transfer_function();
+        assert_eq!(transfer_function_preset("viridis").unwrap(), viridis);
+        assert_eq!(viridis[0].truncate(), cgmath::Vector3::new(68, 1, 84));
+        assert_eq!(viridis[255].truncate(), cgmath::Vector3::new(253, 231, 37));
+        assert_eq!(
+            transfer_function_preset("classic").unwrap()[255],
+            cgmath::Vector4::new(255, 0, 255, 204)
+        );
+        let grayscale = transfer_function_preset("grayscale").unwrap();
+        assert_eq!(grayscale[255], cgmath::Vector4::new(255, 255, 255, 255));
+        assert!(transfer_function_preset("rainbow").is_none());
+    }
+
+    #[test]
+    fn test_load_volume_data_u8() {
+        let path = temp_path("u8.raw");
+        std::fs::write(&path, [0u8, 51, 255, 102]).unwrap();
+        let (dims, data, uint_data, _) = load_volume_data_u8(&path, (2, 2, 1)).unwrap();
+        assert_eq!(dims, (2, 2, 1));
+        assert_eq!(data, vec![0.0, 0.2, 1.0, 0.4]);
+        assert_eq!(uint_data, vec![0, 51, 255, 102]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_volume_data_with_dims() {
+        let shorts: [u16; 4] = [100, 300, 200, 500];
+        let bytes: Vec<u8> = shorts.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let path = temp_path("with_dims.raw");
+        std::fs::write(&path, bytes).unwrap();
+        // the min/max of the samples are mapped to [0, 1], not 0 and 65535
+        let (dims, data, uint_data, _, range) =
+            load_volume_data_with_dims(&path, (2, 2, 1), Endianness::Little, None).unwrap();
+        assert_eq!(dims, (2, 2, 1));
+        assert_eq!(data, vec![0.0, 0.5, 0.25, 1.0]);
+        assert_eq!(uint_data, shorts.to_vec());
+        assert_eq!(range, (100.0, 500.0));
+        let (_, data, _, _, _) =
+            load_volume_data_with_dims(&path, (2, 2, 1), Endianness::Little, Some((300.0, 500.0)))
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data, vec![0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_load_gzipped_volume_data() {
+        use std::io::Write;
+        let shorts: [u16; 5] = [2, 1, 1, 0x0FFF, 0];
+        let bytes: Vec<u8> = shorts.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let path = temp_path("volume.dat.gz");
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        let (dims, _, uint_data, _) = load_volume_data(&path).unwrap();
+        assert_eq!(dims, (2, 1, 1));
+        assert_eq!(uint_data, vec![0x0FFF, 0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_volume_data() {
+        let shorts: [u16; 5] = [2, 1, 1, 100, 300];
+        let bytes: Vec<u8> = shorts.iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(
+            parse_volume_data(&bytes, Endianness::Little, true).unwrap(),
+            ((2, 1, 1), vec![100, 300])
+        );
+        let swapped: Vec<u8> = shorts.iter().flat_map(|v| v.to_be_bytes()).collect();
+        assert_eq!(
+            parse_volume_data(&swapped, Endianness::Big, false).unwrap(),
+            ((2, 1, 1), vec![100, 300])
+        );
+    }
+
+    #[test]
+    fn test_load_volume_data_with_range() {
+        let shorts: [u16; 7] = [4, 1, 1, 100, 300, 200, 500];
+        let bytes: Vec<u8> = shorts.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let path = temp_path("range.dat");
+        std::fs::write(&path, bytes).unwrap();
+        // the min/max of the samples are found, whatever their bit depth
+        let (_, data, _, _, range) =
+            load_volume_data_with_range(&path, Endianness::Native, None).unwrap();
+        assert_eq!(range, (100.0, 500.0));
+        assert_eq!(data, vec![0.0, 0.5, 0.25, 1.0]);
+        // an explicit range clamps the values outside of it
+        let (_, data, _, _, range) =
+            load_volume_data_with_range(&path, Endianness::Native, Some((200.0, 400.0))).unwrap();
+        assert_eq!(range, (200.0, 400.0));
+        assert_eq!(data, vec![0.0, 0.5, 0.0, 1.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_load_volume_data_sequential() {
+        let shorts: Vec<u16> = [3, 2, 1]
+            .into_iter()
+            .chain((0..6).map(|i| i * 4099 % 1000))
+            .collect();
+        let bytes: Vec<u8> = shorts.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let path = temp_path("sequential.dat");
+        std::fs::write(&path, bytes).unwrap();
+        for range in [None, Some((100.0, 600.0))] {
+            assert_eq!(
+                load_volume_data_sequential(&path, Endianness::Big, range).unwrap(),
+                load_volume_data_with_range(&path, Endianness::Big, range).unwrap()
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reorient_volume() {
+        // 3x2x1 volume with the value 10 * y + x
+        let data = vec![0, 1, 2, 10, 11, 12];
+        let flip_x = VolumeOrientation {
+            flip: [true, false, false],
+            ..Default::default()
+        };
+        assert_eq!(
+            reorient_volume(&data, (3, 2, 1), flip_x),
+            (vec![2, 1, 0, 12, 11, 10], (3, 2, 1))
+        );
+        // file XYZ to texture ZYX
+        let transposed = VolumeOrientation {
+            axes: [2, 1, 0],
+            ..Default::default()
+        };
+        let (reoriented, dims) = reorient_volume(&data, (3, 2, 1), transposed);
+        assert_eq!(dims, (1, 2, 3));
+        assert_eq!(reoriented, vec![0, 10, 1, 11, 2, 12]);
+        assert_eq!(transposed.apply((0.5, 1.0, 2.0)), (2.0, 1.0, 0.5));
+        // swapping x and y and flipping the new y turns the volume by 90 degrees
+        let turned = VolumeOrientation {
+            axes: [1, 0, 2],
+            flip: [false, true, false],
+        };
+        assert_eq!(
+            reorient_volume(&data, (3, 2, 1), turned),
+            (vec![2, 12, 1, 11, 0, 10], (2, 3, 1))
+        );
+        assert_eq!(
+            reorient_volume(&data, (3, 2, 1), VolumeOrientation::default()),
+            (data, (3, 2, 1))
+        );
+    }
+
+    #[test]
+    fn test_malformed_volume_data() {
+        // one sample short of the 2x2x1 in the header, e.g. an interrupted download
+        let truncated: Vec<u8> = [2u16, 2, 1, 10, 20, 30]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let error = parse_volume_data(&truncated, Endianness::Little, true).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("expected 2x2x1 samples (8 bytes), found 6 bytes"));
+        // a header read with the wrong byte order claims far more than the file holds
+        let path = temp_path("malformed.dat");
+        std::fs::write(&path, &truncated).unwrap();
+        for result in [
+            load_volume_data_with_range(&path, Endianness::Big, None),
+            load_volume_data_sequential(&path, Endianness::Big, None),
+        ] {
+            assert!(format!("{:?}", result.unwrap_err()).contains("expected 512x512x256 samples"));
+        }
+        assert!(parse_volume_data(&[1, 0], Endianness::Little, true).is_err());
+        assert!(load_volume_data_u8(&path, (2, 2, 1)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_volume_data_u8(temp_path("missing.raw"), (1, 1, 1)).is_err());
+    }
+
+    #[test]
+    fn test_normalize_u16_by_range() {
+        let samples: Vec<u16> = vec![7, 4095, 300, 65535, 0, 1024];
+        let values: Vec<f32> = samples.iter().map(|v| *v as f32).collect();
+        for range in [None, Some((100.0, 5000.0))] {
+            for parallel in [true, false] {
+                assert_eq!(
+                    normalize_u16_by_range(&samples, range, parallel),
+                    normalize_by_range(&values, range)
+                );
+            }
+        }
+        // a constant volume maps to 0 instead of dividing by 0
+        assert_eq!(
+            normalize_u16_by_range(&[12, 12], None, true),
+            (vec![0.0, 0.0], (12.0, 12.0))
+        );
+    }
+
+    #[test]
+    fn test_load_volume_data_f32() {
+        let values: [f32; 4] = [-1.5, 0.5, 2.5, 0.0];
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        let path = temp_path("f32.raw");
+        std::fs::write(&path, bytes).unwrap();
+        let (dims, data, uint_data, spacing, range) =
+            load_volume_data_f32(&path, (2, 2, 1), Endianness::Big, None).unwrap();
+        assert_eq!(dims, (2, 2, 1));
+        assert_eq!(range, (-1.5, 2.5));
+        assert_eq!(data, vec![0.0, 0.5, 1.0, 0.375]);
+        assert_eq!(uint_data[2], u16::MAX);
+        assert_eq!(spacing, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_choose_surface_format() {
+        use wgpu::TextureFormat::*;
+        assert_eq!(
+            choose_surface_format(&[Bgra8Unorm, Bgra8UnormSrgb]),
+            (Bgra8UnormSrgb, Bgra8UnormSrgb)
+        );
+        // e.g. browsers only offer linear formats, the sRGB view encodes the output anyway
+        assert_eq!(
+            choose_surface_format(&[Rgba8Unorm]),
+            (Rgba8Unorm, Rgba8UnormSrgb)
+        );
+        // formats without an sRGB variant are rendered as they are
+        assert_eq!(
+            choose_surface_format(&[Rgba16Float]),
+            (Rgba16Float, Rgba16Float)
+        );
+    }
+
+    #[test]
+    fn test_max_opacity_over_gradients() {
+        let c = |w: u8| cgmath::Vector4::new(w, 0, 0, w);
+        // 3 scalars, 2 gradient magnitudes, the middle scalar is only visible at boundaries
+        let transfer_function_2d = vec![c(10), c(0), c(0), c(5), c(200), c(0)];
+        let collapsed = max_opacity_over_gradients(3, 2, &transfer_function_2d);
+        assert_eq!(collapsed, vec![c(10), c(200), c(0)]);
+    }
+
+    #[test]
+    fn test_interpolate_transfer_functions() {
+        let from = vec![cgmath::Vector4::new(0, 100, 255, 0)];
+        let to = vec![cgmath::Vector4::new(255, 100, 0, 10)];
+        assert_eq!(interpolate_transfer_functions(&from, &to, 0.0), from);
+        assert_eq!(interpolate_transfer_functions(&from, &to, 1.0), to);
+        assert_eq!(
+            interpolate_transfer_functions(&from, &to, 0.5),
+            vec![cgmath::Vector4::new(128, 100, 128, 5)]
+        );
+        // a shorter transfer function is resampled to the length of the other one
+        let c = |v: u8| cgmath::Vector4::new(v, v, v, v);
+        assert_eq!(
+            interpolate_transfer_functions(&[c(0), c(200)], &[c(0); 3], 0.0),
+            vec![c(0), c(100), c(200)]
+        );
+    }
+
+    #[test]
+    fn test_resample_transfer_function() {
+        let c = |v: u8| cgmath::Vector4::new(v, 255 - v, 0, v);
+        let resampled = resample_transfer_function(&[c(0), c(100), c(200)], 5);
+        assert_eq!(resampled, vec![c(0), c(50), c(100), c(150), c(200)]);
+        // a single entry is constant
+        assert_eq!(resample_transfer_function(&[c(7)], 3), vec![c(7); 3]);
+        assert_eq!(
+            load_example_transfer_function().len(),
+            TRANSFER_FUNCTION_RESOLUTION
+        );
+    }
+
+    #[test]
+    fn test_compute_preintegration_table() {
+        let n = 256;
+        // a spike that point sampling between entries 0 and 255 misses
+        let mut transfer_function = vec![cgmath::Vector4::new(0, 0, 0, 0); n];
+        transfer_function[128] = cgmath::Vector4::new(255, 0, 0, 255);
+        let table = compute_preintegration_table(&transfer_function);
+        assert_eq!(table.len(), n * n);
+        for i in 0..n {
+            assert_eq!(table[i + i * n], transfer_function[i]);
+        }
+        assert_eq!(table[n - 1], table[(n - 1) * n]);
+        // the opaque spike still dominates the short segment instead of averaging to a third of its opacity
+        assert_eq!(table[127 + 129 * n], cgmath::Vector4::new(255, 0, 0, 252));
+        assert_eq!(table[n - 1], cgmath::Vector4::new(255, 0, 0, 13));
+        assert_eq!(table[1 + 127 * n], cgmath::Vector4::new(0, 0, 0, 0));
+
+        // a semi-transparent one-entry spike spread over two entries keeps the transmittance of a full step
+        let mut spike = vec![cgmath::Vector4::new(0, 0, 0, 0); 2];
+        spike[1] = cgmath::Vector4::new(0, 255, 0, 192);
+        let table = compute_preintegration_table(&spike);
+        let expected = (1.0 - (1.0 - 192.0 / 255.0f64).sqrt()) * 255.0;
+        assert_eq!(
+            table[1],
+            cgmath::Vector4::new(0, 255, 0, expected.round() as u8)
+        );
+        assert_eq!(table[1], table[2]);
+
+        // a constant transfer function integrates to itself
+        let constant = vec![cgmath::Vector4::new(10, 20, 30, 40); 8];
+        assert!(compute_preintegration_table(&constant)
+            .iter()
+            .all(|c| *c == constant[0]));
+    }
+
+    /// Positions and attributes of the cube vertices and its indices, read back from the buffer contents
+    fn cube_fbo_contents() -> (Vec<[f32; 6]>, Vec<usize>) {
+        use crate::rendering::Geometry;
+        let cube = create_cube_fbo();
+        assert_eq!(cube.get_index_format(), wgpu::IndexFormat::Uint16);
+        let vertices = bytemuck::cast_slice::<u8, [f32; 6]>(cube.get_vertex_raw()).to_vec();
+        let indices = bytemuck::cast_slice::<u8, u16>(cube.get_index_raw())
+            .iter()
+            .map(|i| *i as usize)
+            .collect();
+        (vertices, indices)
+    }
+
+    #[test]
+    fn test_cube_fbo_indices_and_attribs() {
+        let (vertices, indices) = cube_fbo_contents();
+        assert_eq!(vertices.len(), 8);
+        assert_eq!(indices.len(), 36);
+        assert!(indices.iter().all(|i| *i < vertices.len()));
+        // the attribute is the volume texture coordinate of the vertex
+        for vertex in &vertices {
+            for axis in 0..3 {
+                assert_eq!(vertex[3 + axis], vertex[axis] + 0.5, "{:?}", vertex);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cube_fbo_winding() {
+        // D3Pass culls with FrontFace::Ccw, so every triangle has to be counter-clockwise seen from outside
+        let (vertices, indices) = cube_fbo_contents();
+        let position = |i: usize| V3::new(vertices[i][0], vertices[i][1], vertices[i][2]);
+        for triangle in indices.chunks(3) {
+            let (a, b, c) = (
+                position(triangle[0]),
+                position(triangle[1]),
+                position(triangle[2]),
+            );
+            let normal = (b - a).cross(c - a);
+            let centroid = (a + b + c) / 3.0;
+            // the outward normal points away from the cube center like the centroid
+            assert!(
+                normal.dot(centroid) > 0.0,
+                "{:?} is wound clockwise",
+                triangle
+            );
+            // and lies on a face of the cube, not across it
+            let zero_components = [normal.x, normal.y, normal.z]
+                .iter()
+                .filter(|v| **v == 0.0)
+                .count();
+            assert_eq!(zero_components, 2, "{:?} is not on a face", triangle);
+        }
     }
 }
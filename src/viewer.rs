@@ -0,0 +1,1384 @@
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, ensure, Result};
+use cgmath::{Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use half::f16;
+use wgpu::*;
+
+use crate::data::CanvasShaderUniforms;
+use crate::rendering::{
+    AccumulationPass, Camera, CanvasPass, D3Pass, FaceSelection, GradientComputePass, RenderPass,
+    SliceAxis, SlicePass, WireframeMode, WireframePass,
+};
+use crate::shading::{catch_allocation_error, Tex, VolumeSampling};
+use crate::timing::GpuTimer;
+use crate::utils::{
+    build_occupancy_grid, compute_cube_scaling, compute_gradients, downsample_volume,
+    load_volume_data_f32, load_volume_data_u8, load_volume_data_with_dims,
+    load_volume_data_with_range, normalize_u16_by_range, reorient_volume, save_frame_png,
+    transfer_function_preset, Endianness, ExportAlpha, OccupancyGrid, RawSampleType,
+    VolumeOrientation, TRANSFER_FUNCTION_PRESETS,
+};
+
+/// This is 1 because render buffer textures for front-face and back-face rendering is the resolved target
+/// not the multisampled target
+const FACE_RENDER_BUFFER_SAMPLE_COUNT: u32 = 1;
+/// Number of frames used to fly to a bookmarked camera
+const CAMERA_TRANSITION_FRAMES: u32 = 30;
+/// progressive refinement stops after averaging this many frames
+const MAX_ACCUMULATED_FRAMES: u32 = 64;
+/// Camera bookmarks are stored in slots 0 to 8
+pub const CAMERA_BOOKMARK_SLOTS: usize = 9;
+/// The slice view fits into a square of this fraction of the smaller side of the target
+const SLICE_VIEW_FRACTION: f32 = 1.0 / 3.0;
+
+/// Everything needed to build a [`Viewer`], start from [`RenderConfigs::default`] and override what you need
+#[derive(Debug, Clone)]
+pub struct RenderConfigs {
+    /// raw volume file, see [`crate::utils::load_volume_data`]
+    pub volume_path: PathBuf,
+    /// dimensions of a headerless volume file, see [`crate::utils::load_volume_data_with_dims`].
+    /// `None` reads them from the first three shorts of the file
+    pub volume_dims: Option<(usize, usize, usize)>,
+    /// type of the samples of a headerless volume file with `volume_dims`, files with the header store u16
+    pub volume_sample_type: RawSampleType,
+    /// raw values mapped to [0, 1], `None` uses the min/max of the volume
+    pub value_range: Option<(f32, f32)>,
+    /// order and direction of the axes of the file in the volume texture, applied on load before downsampling
+    pub orientation: VolumeOrientation,
+    /// shrinks the volume by this factor along every axis on load to save GPU memory, see [`downsample_volume`].
+    /// `None` keeps the full resolution
+    pub downsample_factor: Option<usize>,
+    /// initial camera, its aspect is replaced by the one of the render target
+    pub camera: Camera,
+    pub sample_count: NonZeroU32,
+    /// format of the front-face and back-face buffers storing ray entry and exit positions
+    pub face_buffer_format: TextureFormat,
+    /// color behind the volume, linear like all colors the passes write
+    pub background_color: Color,
+    /// precompute gradients on load instead of estimating them while ray marching
+    pub precompute_gradients: bool,
+    /// with `precompute_gradients`, computes them once on the GPU from the uploaded volume texture with
+    /// [`GradientComputePass`] instead of on the CPU while loading, which is much faster for large volumes.
+    /// [`RenderConfigs::clamp_gradients_on_gpu`] falls back to the CPU on adapters that cannot run it
+    pub gradients_on_gpu: bool,
+    /// edge length in voxels of the occupancy grid blocks used for empty-space skipping, `None` disables it.
+    /// Smaller blocks skip more tightly around the visible material but need a larger grid
+    pub occupancy_block_size: Option<usize>,
+    /// storage format of the volume texture, see [`Tex::create_volume_texture`].
+    /// `None` picks the format of the samples in the file, or R16Float if the device does not support it
+    pub volume_format: Option<TextureFormat>,
+    /// mip level increase per unit of distance marched into the volume, `None` keeps the volume without mipmaps,
+    /// see [`CanvasPass::set_level_of_detail`]
+    pub level_of_detail: Option<f32>,
+    /// what the volume sampler returns outside of the volume, see [`Tex::volume_sampler_with_address_mode`].
+    /// Falls back to [`AddressMode::ClampToEdge`] if the device does not support the border
+    pub volume_address_mode: AddressMode,
+    pub volume_border_color: SamplerBorderColor,
+    /// request the validation layers and pass labels to the backend, e.g. for RenderDoc captures,
+    /// see [`RenderConfigs::instance_descriptor`]
+    pub gpu_debug: bool,
+    /// how frames are presented to a window surface, `Fifo` waits for vsync while `Immediate` and `Mailbox`
+    /// do not cap the frame rate. Falls back to `Fifo` if the surface does not support it, see [`RenderConfigs::clamp_present_mode`]
+    pub present_mode: PresentMode,
+    /// how screenshots and turntable frames store alpha in the PNG files
+    pub export_alpha: ExportAlpha,
+}
+
+impl Default for RenderConfigs {
+    fn default() -> Self {
+        Self {
+            volume_path: PathBuf::from("./data/stagbeetle277x277x164.dat"),
+            volume_dims: None,
+            volume_sample_type: RawSampleType::U16,
+            value_range: None,
+            orientation: VolumeOrientation::default(),
+            downsample_factor: None,
+            camera: Camera::default(),
+            sample_count: NonZeroU32::new(4).unwrap(),
+            face_buffer_format: TextureFormat::Rgba16Float, // filterable format with highest precision
+            background_color: Color::TRANSPARENT,
+            precompute_gradients: false,
+            gradients_on_gpu: false,
+            occupancy_block_size: Some(16),
+            volume_format: None,
+            level_of_detail: None,
+            // data touching the boundary is not smeared outwards
+            volume_address_mode: AddressMode::ClampToBorder,
+            volume_border_color: SamplerBorderColor::Zero,
+            gpu_debug: false,
+            present_mode: PresentMode::Fifo,
+            export_alpha: ExportAlpha::Straight,
+        }
+    }
+}
+
+impl RenderConfigs {
+    /// Descriptor of the instance to create the device from. With `gpu_debug` the backend validation layers
+    /// are requested where available, in release builds too. The `WGPU_VALIDATION` and `WGPU_DEBUG`
+    /// environment variables override it either way
+    pub fn instance_descriptor(&self) -> InstanceDescriptor {
+        let mut flags = InstanceFlags::from_build_config();
+        if self.gpu_debug {
+            flags |= InstanceFlags::debugging();
+        }
+        InstanceDescriptor {
+            flags: flags.with_env(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Lowers `sample_count` to the highest count the device supports for all multisampled targets, at least 1
+    ///
+    /// The face passes multisample `face_buffer_format` and the depth buffer, the canvas pass `target_format`.
+    /// Without [`Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`] the device only allows the counts guaranteed by WebGPU.
+    /// Call before [`Viewer::new`], an unsupported count fails when the passes are created.
+    ///
+    pub fn clamp_sample_count(
+        &mut self,
+        adapter: &Adapter,
+        device: &Device,
+        target_format: &TextureFormat,
+    ) {
+        let formats = [*target_format, self.face_buffer_format, Tex::DEPTH_FORMAT];
+        let format_flags: Vec<TextureFormatFeatureFlags> = formats
+            .iter()
+            .map(|format| {
+                if device
+                    .features()
+                    .contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                {
+                    adapter.get_texture_format_features(*format).flags
+                } else {
+                    format.guaranteed_format_features(device.features()).flags
+                }
+            })
+            .collect();
+        let requested = self.sample_count.get();
+        let supported = std::iter::successors(Some(requested), |count| Some(count / 2))
+            .take_while(|count| *count > 1)
+            .find(|count| {
+                format_flags
+                    .iter()
+                    .all(|flags| flags.sample_count_supported(*count))
+            })
+            .unwrap_or(1);
+        if supported != requested {
+            log::warn!(
+                "sample count {} is not supported for {:?}, falling back to {}",
+                requested,
+                formats,
+                supported
+            );
+            self.sample_count = NonZeroU32::new(supported).unwrap();
+        }
+    }
+
+    /// Falls back to [`PresentMode::Fifo`], which every surface supports, if `present_mode` is not among
+    /// the `supported` present modes of the surface capabilities
+    pub fn clamp_present_mode(&mut self, supported: &[PresentMode]) {
+        if !supported.contains(&self.present_mode) {
+            log::warn!(
+                "present mode {:?} is not supported by the surface, falling back to Fifo",
+                self.present_mode
+            );
+            self.present_mode = PresentMode::Fifo;
+        }
+    }
+
+    /// Computes the gradients on the CPU instead if `adapter` cannot run [`GradientComputePass`],
+    /// which needs compute shaders and writes only the first slice on the OpenGL backends
+    pub fn clamp_gradients_on_gpu(&mut self, adapter: &Adapter) {
+        let supported = adapter.get_info().backend != Backend::Gl
+            && adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(DownlevelFlags::COMPUTE_SHADERS);
+        if self.gradients_on_gpu && !supported {
+            log::warn!(
+                "the adapter cannot compute the gradients on the GPU, falling back to the CPU"
+            );
+            self.gradients_on_gpu = false;
+        }
+    }
+}
+
+///
+/// Volume loaded from disk and preprocessed on the CPU as configured, ready for [`Viewer::from_volume`]
+///
+/// Loading does not touch the GPU, so it can run on a background thread while the window stays responsive.
+///
+pub struct VolumeData {
+    path: PathBuf,
+    dims: (usize, usize, usize),
+    data: Vec<f32>,
+    spacing: Option<(f32, f32, f32)>,
+    value_range: (f32, f32),
+    /// type of the samples in the file, picks the texture format unless the configs override it
+    sample_type: RawSampleType,
+    gradients: Option<Vec<f16>>,
+    occupancy_grid: Option<OccupancyGrid>,
+}
+
+impl VolumeData {
+    /// Loads `volume_path` and computes the gradients and the occupancy grid if `render_configs` asks for them.
+    /// Fails if the file cannot be read or does not hold as many voxels as its dimensions say
+    pub fn load(render_configs: &RenderConfigs) -> Result<Self> {
+        let sample_type = match render_configs.volume_dims {
+            Some(_) => render_configs.volume_sample_type,
+            None => RawSampleType::U16,
+        };
+        let (mut dims, mut data, _uint_data, mut spacing, value_range) = match (
+            render_configs.volume_dims,
+            sample_type,
+        ) {
+            (None, _) => load_volume_data_with_range(
+                &render_configs.volume_path,
+                Endianness::Native,
+                render_configs.value_range,
+            )?,
+            (Some(dims), RawSampleType::U8) => {
+                let (dims, _, uint_data, spacing) =
+                    load_volume_data_u8(&render_configs.volume_path, dims)?;
+                let (data, value_range) =
+                    normalize_u16_by_range(&uint_data, render_configs.value_range, true);
+                (dims, data, uint_data, spacing, value_range)
+            }
+            (Some(dims), RawSampleType::F32) => load_volume_data_f32(
+                &render_configs.volume_path,
+                dims,
+                Endianness::Native,
+                render_configs.value_range,
+            )?,
+            (Some(dims), RawSampleType::U16) => load_volume_data_with_dims(
+                &render_configs.volume_path,
+                dims,
+                Endianness::Native,
+                render_configs.value_range,
+            )?,
+        };
+        let orientation = render_configs.orientation;
+        if !orientation.is_identity() {
+            (data, dims) = reorient_volume(&data, dims, orientation);
+            spacing = spacing.map(|spacing| orientation.apply(spacing));
+        }
+        if let Some(factor) = render_configs
+            .downsample_factor
+            .filter(|factor| *factor > 1)
+        {
+            let full_dims = dims;
+            (data, dims) = downsample_volume(&data, full_dims, factor);
+            // the larger voxels keep the physical extent of the volume
+            let (sx, sy, sz) = spacing.unwrap_or((1.0, 1.0, 1.0));
+            spacing = Some((
+                sx * full_dims.0 as f32 / dims.0 as f32,
+                sy * full_dims.1 as f32 / dims.1 as f32,
+                sz * full_dims.2 as f32 / dims.2 as f32,
+            ));
+        }
+        let mut volume = Self::from_voxels(dims, data, render_configs)?;
+        volume.path = render_configs.volume_path.clone();
+        volume.spacing = spacing;
+        volume.value_range = value_range;
+        volume.sample_type = sample_type;
+        Ok(volume)
+    }
+
+    /// Volume of `dims` voxels in memory, `data` is normalized to [0, 1] with x varying fastest.
+    /// The gradients and the occupancy grid are computed if `render_configs` asks for them, its volume file is ignored.
+    /// Fails if `data` does not hold as many voxels as `dims` says
+    pub fn from_voxels(
+        dims: (usize, usize, usize),
+        data: Vec<f32>,
+        render_configs: &RenderConfigs,
+    ) -> Result<Self> {
+        ensure!(
+            data.len() == dims.0 * dims.1 * dims.2,
+            "Volume data does not match its dimensions: expected {}x{}x{} samples, found {}",
+            dims.0,
+            dims.1,
+            dims.2,
+            data.len()
+        );
+        let gradients = (render_configs.precompute_gradients && !render_configs.gradients_on_gpu)
+            .then(|| compute_gradients(&data, dims));
+        let occupancy_grid = render_configs
+            .occupancy_block_size
+            .map(|block_size| build_occupancy_grid(&data, dims, block_size));
+        Ok(Self {
+            path: PathBuf::new(),
+            dims,
+            data,
+            spacing: None,
+            value_range: (0.0, 1.0),
+            // uploaded like the samples of a raw volume with the dimension header
+            sample_type: RawSampleType::U16,
+            gradients,
+            occupancy_grid,
+        })
+    }
+}
+
+/// Uploads the volume and its precomputed gradients as configured, or computes the gradients from the uploaded volume.
+/// Fails if the GPU cannot hold them
+fn create_volume_textures(
+    render_configs: &RenderConfigs,
+    extent: &Extent3d,
+    data: &[f32],
+    native_format: TextureFormat,
+    gradients: Option<Vec<f16>>,
+    device: &Device,
+    queue: &Queue,
+) -> Result<(Arc<Tex>, Option<Tex>)> {
+    let gradient_texture = gradients
+        .map(|gradients| {
+            catch_allocation_error(device, || {
+                Tex::create_3d_texture_rgba16f(extent, &gradients, device, queue, "Gradients")
+            })
+            .map_err(|e| anyhow!("gradients do not fit into GPU memory: {}", e))
+        })
+        .transpose()?;
+    let volume_format = match render_configs.volume_format {
+        Some(format) => supported_volume_format(format, device, true),
+        None => supported_volume_format(native_format, device, false),
+    };
+    let address_mode = supported_address_mode(
+        render_configs.volume_address_mode,
+        render_configs.volume_border_color,
+        device,
+    );
+    let volume_texture = Tex::create_volume_texture(
+        extent,
+        data,
+        volume_format,
+        VolumeSampling {
+            mipmapped: render_configs.level_of_detail.is_some(),
+            address_mode,
+            border_color: Some(render_configs.volume_border_color),
+        },
+        device,
+        queue,
+        "Volume",
+    )?;
+    let gradient_texture = match gradient_texture {
+        None if render_configs.precompute_gradients && render_configs.gradients_on_gpu => Some(
+            catch_allocation_error(device, || {
+                GradientComputePass::new(device).compute(&volume_texture, device, queue)
+            })
+            .map_err(|e| anyhow!("failed to compute the gradients on the GPU: {}", e))?,
+        ),
+        // loaded before clamp_gradients_on_gpu ruled out the GPU
+        None if render_configs.precompute_gradients => {
+            let dims = (
+                extent.width as usize,
+                extent.height as usize,
+                extent.depth_or_array_layers as usize,
+            );
+            let gradients = compute_gradients(data, dims);
+            Some(
+                catch_allocation_error(device, || {
+                    Tex::create_3d_texture_rgba16f(extent, &gradients, device, queue, "Gradients")
+                })
+                .map_err(|e| anyhow!("gradients do not fit into GPU memory: {}", e))?,
+            )
+        }
+        gradient_texture => gradient_texture,
+    };
+    Ok((Arc::new(volume_texture), gradient_texture))
+}
+
+/// Voxel spacing for [`CanvasPass::set_voxel_spacing`], volumes without one are isotropic
+fn voxel_spacing(spacing: Option<(f32, f32, f32)>) -> Vector3<f32> {
+    spacing.map_or(Vector3::new(1.0, 1.0, 1.0), Vector3::from)
+}
+
+/// `address_mode` if `device` supports it with `border_color`, otherwise [`AddressMode::ClampToEdge`]
+fn supported_address_mode(
+    address_mode: AddressMode,
+    border_color: SamplerBorderColor,
+    device: &Device,
+) -> AddressMode {
+    let required_feature = match (address_mode, border_color) {
+        (AddressMode::ClampToBorder, SamplerBorderColor::Zero) => {
+            Features::ADDRESS_MODE_CLAMP_TO_ZERO
+        }
+        (AddressMode::ClampToBorder, _) => Features::ADDRESS_MODE_CLAMP_TO_BORDER,
+        _ => Features::empty(),
+    };
+    if device.features().contains(required_feature) {
+        return address_mode;
+    }
+    log::warn!(
+        "volume border {:?} needs {:?}, falling back to ClampToEdge",
+        border_color,
+        required_feature
+    );
+    AddressMode::ClampToEdge
+}
+
+/// `format` if `device` can sample it linearly, otherwise [`TextureFormat::R16Float`], which every device supports
+fn supported_volume_format(format: TextureFormat, device: &Device, warn: bool) -> TextureFormat {
+    let required_feature = match format {
+        TextureFormat::R16Unorm => Features::TEXTURE_FORMAT_16BIT_NORM,
+        TextureFormat::R32Float => Features::FLOAT32_FILTERABLE,
+        _ => Features::empty(),
+    };
+    if device.features().contains(required_feature) {
+        return format;
+    }
+    if warn {
+        log::warn!(
+            "volume format {:?} needs {:?}, falling back to R16Float",
+            format,
+            required_feature
+        );
+    }
+    TextureFormat::R16Float
+}
+
+/// Smooth transition from the current camera to a bookmarked one
+struct CameraTransition {
+    from: Camera,
+    to: Camera,
+    frame: u32,
+}
+
+/// Timed animation that raises the minimum opacity to peel away outer material, then holds
+struct PeelAnimation {
+    duration: Duration,
+    peeled_min_opacity: f32,
+    current: f32,
+    // start time, start value and target value of the running animation
+    running: Option<(Instant, f32, f32)>,
+}
+
+impl PeelAnimation {
+    fn new(duration: Duration, peeled_min_opacity: f32) -> Self {
+        Self {
+            duration,
+            peeled_min_opacity,
+            current: 0.0,
+            running: None,
+        }
+    }
+
+    /// Starts peeling (`peel = true`) or restoring the outer layers from the current value
+    fn start(&mut self, peel: bool) {
+        let target = if peel { self.peeled_min_opacity } else { 0.0 };
+        self.running = Some((Instant::now(), self.current, target));
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// Advances the animation, returning the new minimum opacity if it changed
+    fn step(&mut self) -> Option<f32> {
+        let (start_time, from, to) = self.running?;
+        let t = (start_time.elapsed().as_secs_f32() / self.duration.as_secs_f32()).min(1.0);
+        self.current = from + (to - from) * t;
+        if t >= 1.0 {
+            self.running = None;
+        }
+        Some(self.current)
+    }
+}
+
+///
+/// Volume renderer independent of any window: owns the face passes, their render buffers and the canvas pass
+///
+/// Every frame: [`Viewer::update`], [`Viewer::render`] into a view of the render target format,
+/// submit the encoder and call [`Viewer::frame_submitted`].
+/// The device and queue stay with the caller, so the viewer can be embedded into other wgpu applications.
+///
+pub struct Viewer {
+    /// configs of the viewer, `volume_path` follows the shown volume
+    render_configs: RenderConfigs,
+    size: (u32, u32),
+    target_format: TextureFormat,
+    value_range: (f32, f32),
+    camera: Camera,
+    /// eye separation of side-by-side stereo, `None` renders a single view
+    stereo_eye_separation: Option<f32>,
+    camera_bookmarks: Vec<Option<Camera>>,
+    camera_transition: Option<CameraTransition>,
+    peel_animation: PeelAnimation,
+    /// index into TRANSFER_FUNCTION_PRESETS, None for the example transfer function
+    transfer_function_preset: Option<usize>,
+    /// measures the canvas pass if the device supports timestamp queries
+    gpu_timer: Option<Arc<GpuTimer>>,
+    canvas_pass_time: Option<Duration>,
+    cube_scaling: Matrix4<f32>,
+    /// places the scaled cube in the world, see [`Viewer::set_volume_transform`]
+    volume_transform: Matrix4<f32>,
+    front_face_pass: D3Pass,
+    front_face_render_buffer: Tex,
+    back_face_pass: D3Pass,
+    back_face_render_buffer: Tex,
+    canvas_pass: CanvasPass,
+    wireframe_mode: WireframeMode,
+    wireframe_pass: WireframePass,
+    /// slice next to the volume, `None` if it is hidden
+    slice_pass: Option<SlicePass>,
+    /// averages the frames of a still camera, `None` shows every frame as is
+    accumulation_pass: Option<AccumulationPass>,
+    /// camera, stereo eye separation and canvas pass revision of the frames in the average
+    accumulated_state: Option<(Camera, Option<f32>, u64)>,
+}
+
+impl Viewer {
+    /// Loads the configured volume and creates all passes rendering into `resolution` sized targets of `target_format`.
+    /// GPU timing is enabled if `device` was created with [`Features::TIMESTAMP_QUERY`].
+    /// Fails if the volume cannot be loaded or is too large for the GPU, see [`Tex::create_volume_texture`]
+    pub fn new(
+        render_configs: &RenderConfigs,
+        device: &Device,
+        queue: &Queue,
+        resolution: (u32, u32),
+        target_format: &TextureFormat,
+    ) -> Result<Self> {
+        let volume = VolumeData::load(render_configs)?;
+        Self::from_volume(
+            render_configs,
+            volume,
+            device,
+            queue,
+            resolution,
+            target_format,
+        )
+    }
+
+    /// Same as [`Viewer::new`] with a volume loaded up front, e.g. on another thread.
+    /// `volume` should be loaded with the same `render_configs`
+    ///
+    /// The passes composite linear color, so `target_format` should be an sRGB format that encodes it on write,
+    /// see [`crate::utils::choose_surface_format`]. Other formats show the linear values and the image looks too dark.
+    pub fn from_volume(
+        render_configs: &RenderConfigs,
+        volume: VolumeData,
+        device: &Device,
+        queue: &Queue,
+        resolution: (u32, u32),
+        target_format: &TextureFormat,
+    ) -> Result<Self> {
+        let VolumeData {
+            path: _,
+            dims: (x, y, z),
+            data,
+            spacing,
+            value_range,
+            sample_type,
+            gradients,
+            occupancy_grid,
+        } = volume;
+        // float formats are linear, e.g. for HDR displays
+        if target_format.add_srgb_suffix() != *target_format {
+            log::warn!(
+                "render target format {:?} is not sRGB, colors will look too dark",
+                target_format
+            );
+        }
+        let sample_count = render_configs.sample_count;
+        let (width, height) = resolution;
+        let camera = Camera {
+            aspect: width as f32 / height as f32,
+            ..render_configs.camera
+        };
+        // load volume into textures
+        let extent = Extent3d {
+            width: x as u32,
+            height: y as u32,
+            depth_or_array_layers: z as u32,
+        };
+        let (volume_texture, gradient_texture) = create_volume_textures(
+            render_configs,
+            &extent,
+            &data,
+            sample_type.texture_format(),
+            gradients,
+            device,
+            queue,
+        )?;
+        // prepare volume cube scaling for correct shape
+        let cube_scaling = compute_cube_scaling((x, y, z), spacing);
+
+        // prepare front-face and back-face passes
+        let face_buffer_format = render_configs.face_buffer_format;
+        let front_face_render_buffer = Tex::create_render_buffer(
+            resolution,
+            device,
+            Some("Front Face Render Buffer"),
+            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
+            &face_buffer_format,
+        );
+        let front_face_pass = D3Pass::new(
+            device,
+            width,
+            height,
+            &front_face_render_buffer.format,
+            FaceSelection::new(true),
+            &camera,
+            sample_count,
+            cube_scaling,
+        );
+        let back_face_render_buffer = Tex::create_render_buffer(
+            resolution,
+            device,
+            Some("Back Face Render Buffer"),
+            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
+            &face_buffer_format,
+        );
+        let back_face_pass = D3Pass::new(
+            device,
+            width,
+            height,
+            &back_face_render_buffer.format,
+            FaceSelection::new(false),
+            &camera,
+            sample_count,
+            cube_scaling,
+        );
+        let mut canvas_pass = CanvasPass::new(
+            &front_face_render_buffer,
+            &back_face_render_buffer,
+            volume_texture,
+            device,
+            queue,
+            resolution,
+            target_format,
+            sample_count,
+        );
+        if let Some(ref gradient_texture) = gradient_texture {
+            canvas_pass = canvas_pass.with_gradients(gradient_texture, device, queue);
+        }
+        // keep the gradient setting of the canvas pass, only the sampling follows the volume
+        canvas_pass.set_uniforms(
+            &CanvasShaderUniforms {
+                precomputed_gradient: canvas_pass.uniforms().precomputed_gradient,
+                ..CanvasShaderUniforms::for_volume((x, y, z))
+            },
+            queue,
+        );
+        canvas_pass.set_occupancy_grid(occupancy_grid, device, queue);
+        canvas_pass.set_voxel_spacing(voxel_spacing(spacing), queue);
+        if let Some(lod_per_distance) = render_configs.level_of_detail {
+            canvas_pass.set_level_of_detail(lod_per_distance, queue);
+        }
+        canvas_pass.set_background_color(render_configs.background_color);
+        let gpu_timer = GpuTimer::new(device, queue).map(Arc::new);
+        canvas_pass.set_gpu_timer(gpu_timer.clone());
+        let wireframe_pass =
+            WireframePass::new(device, width, height, target_format, &camera, cube_scaling);
+        Ok(Self {
+            render_configs: render_configs.clone(),
+            size: resolution,
+            target_format: *target_format,
+            value_range,
+            camera,
+            stereo_eye_separation: None,
+            camera_bookmarks: vec![None; CAMERA_BOOKMARK_SLOTS],
+            camera_transition: None,
+            peel_animation: PeelAnimation::new(Duration::from_secs(3), 0.3),
+            transfer_function_preset: None,
+            gpu_timer,
+            canvas_pass_time: None,
+            cube_scaling,
+            volume_transform: Matrix4::identity(),
+            front_face_pass,
+            front_face_render_buffer,
+            back_face_pass,
+            back_face_render_buffer,
+            canvas_pass,
+            wireframe_mode: WireframeMode::Hidden,
+            wireframe_pass,
+            slice_pass: None,
+            accumulation_pass: None,
+            accumulated_state: None,
+        })
+    }
+
+    ///
+    /// Builds the passes for a volume in memory without a window or surface, e.g. for tests and batch rendering
+    ///
+    /// `volume` holds `dims` voxels normalized to [0, 1] with x varying fastest. The configs are the defaults
+    /// with a single sample, which every renderable `target_format` supports. Render with [`Viewer::render_to_texture`].
+    ///
+    pub fn new_offscreen(
+        device: &Device,
+        queue: &Queue,
+        dims: (usize, usize, usize),
+        volume: Vec<f32>,
+        resolution: (u32, u32),
+        target_format: &TextureFormat,
+    ) -> Result<Self> {
+        let render_configs = RenderConfigs {
+            volume_path: PathBuf::new(),
+            sample_count: NonZeroU32::MIN,
+            ..Default::default()
+        };
+        let volume = VolumeData::from_voxels(dims, volume, &render_configs)?;
+        Self::from_volume(
+            &render_configs,
+            volume,
+            device,
+            queue,
+            resolution,
+            target_format,
+        )
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Loads another volume and swaps it in, keeping the camera and the rendering parameters.
+    /// Blocks while loading, see [`Viewer::set_volume`] for loading in the background
+    pub fn load_volume<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<()> {
+        let render_configs = RenderConfigs {
+            volume_path: path.as_ref().to_path_buf(),
+            ..self.render_configs.clone()
+        };
+        self.set_volume(VolumeData::load(&render_configs)?, device, queue)
+    }
+
+    ///
+    /// Replaces the volume with one loaded by [`VolumeData::load`]
+    ///
+    /// Only the volume dependent resources change: the volume textures, the occupancy grid, the cube scaling
+    /// and the step size, which follows the volume resolution. The passes and all other parameters are kept.
+    /// A mask bound with [`CanvasPass::change_bound_volume_textures`] is removed.
+    /// If the volume is too large for the GPU, the shown volume is kept and an error is returned.
+    ///
+    pub fn set_volume(&mut self, volume: VolumeData, device: &Device, queue: &Queue) -> Result<()> {
+        let VolumeData {
+            path,
+            dims: (x, y, z),
+            data,
+            spacing,
+            value_range,
+            sample_type,
+            gradients,
+            occupancy_grid,
+        } = volume;
+        let extent = Extent3d {
+            width: x as u32,
+            height: y as u32,
+            depth_or_array_layers: z as u32,
+        };
+        let (volume_texture, gradient_texture) = create_volume_textures(
+            &self.render_configs,
+            &extent,
+            &data,
+            sample_type.texture_format(),
+            gradients,
+            device,
+            queue,
+        )?;
+        self.canvas_pass.change_bound_volume_textures(
+            device,
+            queue,
+            volume_texture,
+            None,
+            gradient_texture.as_ref(),
+        );
+        let sampling = CanvasShaderUniforms::for_volume((x, y, z));
+        let mut uniforms = *self.canvas_pass.uniforms();
+        uniforms.step_size = sampling.step_size;
+        uniforms.base_distance = sampling.base_distance;
+        uniforms.voxel_spacing = voxel_spacing(spacing);
+        self.canvas_pass.set_uniforms(&uniforms, queue);
+        self.canvas_pass
+            .set_occupancy_grid(occupancy_grid, device, queue);
+        self.cube_scaling = compute_cube_scaling((x, y, z), spacing);
+        self.update_model_view_proj(queue);
+        self.value_range = value_range;
+        self.render_configs.volume_path = path;
+        Ok(())
+    }
+
+    /// File of the shown volume
+    pub fn volume_path(&self) -> &Path {
+        &self.render_configs.volume_path
+    }
+
+    /// Raw values mapped to 0 and 1 in the volume texture, e.g. for converting window and level to raw units
+    pub fn value_range(&self) -> (f32, f32) {
+        self.value_range
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Maps the unit cube centered at the origin, which the volume is drawn on, to clip space of the render target.
+    /// Projects points of the volume into the rendered image, e.g. for annotations. Stereo eyes are not accounted for
+    pub fn model_view_proj(&self) -> Matrix4<f32> {
+        self.camera
+            .build_view_projection_matrix(self.model_transformation())
+    }
+
+    pub fn volume_transform(&self) -> Matrix4<f32> {
+        self.volume_transform
+    }
+
+    ///
+    /// Places the volume in the world, e.g. to correct its orientation or to move it off the origin
+    ///
+    /// `transform` is composed of any scaling, rotation and translation and applies after the cube scaling,
+    /// which keeps the proportions of the volume. The identity, the default, centers the volume at the origin.
+    /// Both face passes use the same transform, so the rays still enter and exit the same volume.
+    /// Takes effect with the next [`Viewer::update`]
+    ///
+    pub fn set_volume_transform(&mut self, transform: Matrix4<f32>) {
+        self.volume_transform = transform;
+        // the frames in the average show the volume elsewhere
+        self.accumulated_state = None;
+    }
+
+    /// Cube scaling followed by the volume transform
+    fn model_transformation(&self) -> Matrix4<f32> {
+        self.volume_transform * self.cube_scaling
+    }
+
+    /// World space center of the volume, the origin moved by the volume transform
+    pub fn volume_center(&self) -> Point3<f32> {
+        // the cube is centered on the origin before the model transformation
+        Point3::from_homogeneous(self.model_transformation() * Vector4::new(0.0, 0.0, 0.0, 1.0))
+    }
+
+    /// Replaces the camera, its aspect is replaced by the one of the render target
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = Camera {
+            aspect: self.camera.aspect,
+            ..camera
+        };
+    }
+
+    pub fn canvas_pass(&self) -> &CanvasPass {
+        &self.canvas_pass
+    }
+
+    /// For adjusting rendering parameters like the render mode or the background color
+    pub fn canvas_pass_mut(&mut self) -> &mut CanvasPass {
+        &mut self.canvas_pass
+    }
+
+    /// GPU duration of the canvas pass of a recent frame, `None` without timestamp queries
+    pub fn canvas_pass_time(&self) -> Option<Duration> {
+        self.canvas_pass_time
+    }
+
+    /// Eye separation if side-by-side stereo is enabled
+    pub fn stereo(&self) -> Option<f32> {
+        self.stereo_eye_separation
+    }
+
+    /// Renders the left eye into the left half and the right eye into the right half of the target,
+    /// e.g. for VR headsets or cross-eyed viewing. `eye_separation` is in the units of the camera,
+    /// the volume spans about 1. Takes effect with the next [`Viewer::update`]
+    pub fn set_stereo(&mut self, enabled: bool, eye_separation: f32) {
+        self.stereo_eye_separation = enabled.then_some(eye_separation);
+    }
+
+    pub fn wireframe_mode(&self) -> WireframeMode {
+        self.wireframe_mode
+    }
+
+    /// Shows or hides the bounding box edges drawn over the volume
+    pub fn set_wireframe_mode(&mut self, mode: WireframeMode) {
+        self.wireframe_mode = mode;
+        self.wireframe_pass
+            .set_back_edges_hidden(mode == WireframeMode::FrontEdges);
+    }
+
+    /// Axis and position of the slice view, `None` if it is hidden
+    pub fn slice_view(&self) -> Option<(SliceAxis, f32)> {
+        self.slice_pass
+            .as_ref()
+            .map(|slice_pass| (slice_pass.axis(), slice_pass.position()))
+    }
+
+    /// Shows the slice orthogonal to an axis at a position in [0, 1] along it in the bottom right corner
+    /// of the target, next to the rendered volume, see [`SlicePass`]. `None` hides it
+    pub fn set_slice_view(
+        &mut self,
+        slice: Option<(SliceAxis, f32)>,
+        device: &Device,
+        queue: &Queue,
+    ) {
+        let Some((axis, position)) = slice else {
+            self.slice_pass = None;
+            return;
+        };
+        let slice_pass = self
+            .slice_pass
+            .get_or_insert_with(|| SlicePass::new(device, &self.canvas_pass, &self.target_format));
+        slice_pass.set_slice(axis, position, queue);
+    }
+
+    /// Viewport (x, y, width, height) of the slice view, keeping the physical aspect of the slice
+    fn slice_viewport(&self, axis: SliceAxis) -> (f32, f32, f32, f32) {
+        let extent = Vector3::new(
+            self.cube_scaling.x.x,
+            self.cube_scaling.y.y,
+            self.cube_scaling.z.z,
+        );
+        let (right, up) = match axis {
+            SliceAxis::Sagittal => (extent.y, extent.z),
+            SliceAxis::Coronal => (extent.x, extent.z),
+            SliceAxis::Axial => (extent.x, extent.y),
+        };
+        let (width, height) = (self.size.0 as f32, self.size.1 as f32);
+        let scale = width.min(height) * SLICE_VIEW_FRACTION / right.max(up);
+        let (slice_width, slice_height) = (right * scale, up * scale);
+        (
+            width - slice_width,
+            height - slice_height,
+            slice_width,
+            slice_height,
+        )
+    }
+
+    /// True while a camera transition or a peel animation is running or progressive refinement has not converged,
+    /// keep rendering frames until it is false
+    pub fn is_animating(&self) -> bool {
+        let refining = self
+            .accumulation_pass
+            .as_ref()
+            .is_some_and(|pass| pass.frame_count() < MAX_ACCUMULATED_FRAMES);
+        self.peel_animation.is_running() || self.camera_transition.is_some() || refining
+    }
+
+    /// World space position where the ray through pixel (`x`, `y`) hits the volume, see [`CanvasPass::pick`].
+    /// Distances between picks are in the units of the cube scaling, which keeps the volume proportions,
+    /// unless the volume transform scales
+    pub fn pick(&self, x: u32, y: u32, device: &Device, queue: &Queue) -> Option<Point3<f32>> {
+        let volume_coord = self.canvas_pass.pick(x, y, self.size, device, queue)?;
+        // the cube spans [-0.5, 0.5]³ before the scaling, its volume texture coordinates [0, 1]³
+        let position = volume_coord - Vector3::new(0.5, 0.5, 0.5);
+        Some(Point3::from_homogeneous(
+            self.model_transformation() * position.extend(1.0),
+        ))
+    }
+
+    /// Number of frames averaged by progressive refinement, `None` if it is disabled
+    pub fn accumulated_frames(&self) -> Option<u32> {
+        self.accumulation_pass
+            .as_ref()
+            .map(AccumulationPass::frame_count)
+    }
+
+    ///
+    /// Averages the frames rendered while the camera and all rendering parameters stay the same
+    ///
+    /// Enabling it also jitters the ray starts, so that every frame samples the volume at different depths
+    /// and the average converges to the image of a much smaller step size. Any change starts a new average.
+    ///
+    pub fn set_progressive_refinement(&mut self, enabled: bool, device: &Device, queue: &Queue) {
+        self.accumulated_state = None;
+        self.accumulation_pass = enabled
+            .then(|| AccumulationPass::new(device, self.size.0, self.size.1, &self.target_format));
+        self.canvas_pass.set_frame_index(0, queue);
+        if enabled {
+            self.canvas_pass.set_jitter_enabled(true, queue);
+        }
+    }
+
+    /// Saves the current camera into a bookmark slot, `slot` < [`CAMERA_BOOKMARK_SLOTS`]
+    pub fn bookmark_camera(&mut self, slot: usize) {
+        self.camera_bookmarks[slot] = Some(self.camera);
+    }
+
+    /// Starts flying to a bookmarked camera, returns false if the slot is empty
+    pub fn recall_camera(&mut self, slot: usize) -> bool {
+        match self.camera_bookmarks[slot] {
+            None => false,
+            Some(mut target) => {
+                // the target may have been resized since the bookmark was taken
+                target.aspect = self.camera.aspect;
+                self.camera_transition = Some(CameraTransition {
+                    from: self.camera,
+                    to: target,
+                    frame: 0,
+                });
+                true
+            }
+        }
+    }
+
+    /// Peels away the outer layers (`peel = true`) or brings them back over a few seconds
+    pub fn start_peel_animation(&mut self, peel: bool) {
+        self.peel_animation.start(peel);
+    }
+
+    /// Switches to the next built-in transfer function preset and returns its name
+    pub fn cycle_transfer_function_preset(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+    ) -> &'static str {
+        let preset = self
+            .transfer_function_preset
+            .map_or(0, |i| (i + 1) % TRANSFER_FUNCTION_PRESETS.len());
+        let name = TRANSFER_FUNCTION_PRESETS[preset];
+        let transfer_function = transfer_function_preset(name).unwrap();
+        self.canvas_pass
+            .update_transfer_function(&transfer_function, device, queue);
+        self.transfer_function_preset = Some(preset);
+        name
+    }
+
+    /// Recreates all render buffers for a new render target size, zero sizes are ignored
+    pub fn resize(&mut self, device: &Device, queue: &Queue, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.size = (width, height);
+        self.camera.aspect = width as f32 / height as f32;
+        self.front_face_pass.resize(device, width, height);
+        self.back_face_pass.resize(device, width, height);
+        self.update_model_view_proj(queue);
+        self.canvas_pass.resize(device, width, height);
+        self.wireframe_pass.resize(device, width, height);
+        if let Some(accumulation_pass) = self.accumulation_pass.as_mut() {
+            accumulation_pass.resize(device, width, height);
+        }
+
+        self.front_face_render_buffer = Tex::create_render_buffer(
+            self.size,
+            device,
+            Some("Front Face Render Buffer"),
+            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
+            &self.front_face_render_buffer.format,
+        );
+        self.back_face_render_buffer = Tex::create_render_buffer(
+            self.size,
+            device,
+            Some("Back Face Render Buffer"),
+            NonZeroU32::new(FACE_RENDER_BUFFER_SAMPLE_COUNT).unwrap(),
+            &self.back_face_render_buffer.format,
+        );
+        self.canvas_pass.change_bound_face_textures(
+            device,
+            &self.front_face_render_buffer,
+            &self.back_face_render_buffer,
+        );
+    }
+
+    pub fn sample_count(&self) -> NonZeroU32 {
+        self.render_configs.sample_count
+    }
+
+    ///
+    /// Switches multisampling of the face passes and the canvas pass to `sample_count` samples per pixel
+    ///
+    /// This is expensive: the multisample buffers are reallocated and the pipelines are compiled again,
+    /// so it is meant for toggling, not for every frame.
+    /// The device must support the count for the target formats, see [`RenderConfigs::clamp_sample_count`].
+    ///
+    pub fn set_sample_count(&mut self, sample_count: NonZeroU32, device: &Device) {
+        if sample_count == self.render_configs.sample_count {
+            return;
+        }
+        self.render_configs.sample_count = sample_count;
+        self.front_face_pass.set_sample_count(device, sample_count);
+        self.back_face_pass.set_sample_count(device, sample_count);
+        self.canvas_pass
+            .set_sample_count(device, self.size, sample_count);
+    }
+
+    /// Advances the running animations, uploads the camera and adds the next frame to progressive refinement
+    pub fn update(&mut self, queue: &Queue) {
+        if let Some(transition) = self.camera_transition.as_mut() {
+            transition.frame += 1;
+            let t = transition.frame as f32 / CAMERA_TRANSITION_FRAMES as f32;
+            let t = t * t * (3.0 - 2.0 * t); // smoothstep easing
+            self.camera = Camera {
+                // keep the aspect of the target, it may have been resized during the transition
+                aspect: self.camera.aspect,
+                ..transition.from.interpolate(&transition.to, t)
+            };
+            if transition.frame >= CAMERA_TRANSITION_FRAMES {
+                self.camera_transition = None;
+            }
+        }
+        self.update_model_view_proj(queue);
+        if let Some(min_opacity) = self.peel_animation.step() {
+            let mut uniforms = *self.canvas_pass.uniforms();
+            uniforms.min_opacity = min_opacity;
+            self.canvas_pass.set_uniforms(&uniforms, queue);
+        }
+        if let Some(accumulation_pass) = self.accumulation_pass.as_mut() {
+            let state = Some((
+                self.camera,
+                self.stereo_eye_separation,
+                self.canvas_pass.revision(),
+            ));
+            if state != self.accumulated_state {
+                accumulation_pass.reset();
+                self.accumulated_state = state;
+            }
+            accumulation_pass.advance(queue);
+            self.canvas_pass
+                .set_frame_index(accumulation_pass.frame_count(), queue);
+        }
+    }
+
+    fn update_model_view_proj(&mut self, queue: &Queue) {
+        let model_transformation = self.model_transformation();
+        // both stereo eyes measure view depths from the center camera
+        self.canvas_pass
+            .update_view_depth_plane(model_transformation, &self.camera, queue);
+        let Some(eye_separation) = self.stereo_eye_separation else {
+            self.front_face_pass.update_model_view_proj_uniform(
+                model_transformation,
+                &self.camera,
+                queue,
+            );
+            self.back_face_pass.update_model_view_proj_uniform(
+                model_transformation,
+                &self.camera,
+                queue,
+            );
+            self.wireframe_pass.update_model_view_proj_uniform(
+                model_transformation,
+                &self.camera,
+                queue,
+            );
+            return;
+        };
+        // the face buffers hold both eyes side by side, so the canvas pass marches both in one draw
+        let (left_eye, right_eye) = self.camera.stereo_eyes(eye_separation);
+        for pass in [&mut self.front_face_pass, &mut self.back_face_pass] {
+            pass.update_stereo_model_view_proj_uniforms(
+                model_transformation,
+                &left_eye,
+                &right_eye,
+                queue,
+            );
+        }
+        self.wireframe_pass.update_stereo_model_view_proj_uniforms(
+            model_transformation,
+            &left_eye,
+            &right_eye,
+            queue,
+        );
+    }
+
+    /// Records the face passes, the canvas pass and the wireframe if shown, rendering into `render_into_view`
+    pub fn render(&self, render_into_view: &TextureView, encoder: &mut CommandEncoder) {
+        self.render_passes(render_into_view, encoder);
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.resolve(encoder);
+        }
+    }
+
+    /// Call after submitting the encoder passed to [`Viewer::render`], picks up finished GPU timings
+    pub fn frame_submitted(&mut self, device: &Device) {
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.map();
+            if let Some(canvas_pass_time) = gpu_timer.read(device) {
+                self.canvas_pass_time = Some(canvas_pass_time);
+            }
+        }
+    }
+
+    /// Renders a frame into an owned texture and reads it back, e.g. for screenshots.
+    /// Color is premultiplied by alpha, see [`crate::utils::save_frame_png`]
+    pub fn capture(&self, device: &Device, queue: &Queue) -> Result<image::RgbaImage> {
+        self.render_to_texture(device, queue)
+            .read_rgba8_image(device, queue)
+    }
+
+    /// Renders a frame into a new texture of the target format and size, which can be copied from.
+    /// The rendering is submitted to `queue`
+    pub fn render_to_texture(&self, device: &Device, queue: &Queue) -> Tex {
+        let target = Tex::create_readable_render_target(
+            self.size,
+            device,
+            Some("Capture Render Target"),
+            &self.target_format,
+        );
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Capture Encoder"),
+        });
+        self.render_passes(&target.view, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+        target
+    }
+
+    ///
+    /// Renders a 360° turntable animation as `frames` PNG files named `frame_0000.png` and so on into `output_dir`
+    ///
+    /// The camera orbits its center about its up axis by 360° / `frames` per frame, so the frames loop seamlessly.
+    /// Alpha is stored as configured by [`RenderConfigs::export_alpha`]. The camera is restored afterwards.
+    ///
+    pub fn render_turntable<P: AsRef<Path>>(
+        &mut self,
+        frames: u32,
+        output_dir: P,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<()> {
+        let output_dir = output_dir.as_ref();
+        std::fs::create_dir_all(output_dir)?;
+        let start_camera = self.camera;
+        let result = (0..frames).try_for_each(|frame| {
+            self.camera = start_camera.orbit(360.0 * frame as f32 / frames as f32);
+            self.update(queue);
+            let image = self.capture(device, queue)?;
+            let path = output_dir.join(format!("frame_{:04}.png", frame));
+            save_frame_png(&image, path, self.render_configs.export_alpha)
+        });
+        self.camera = start_camera;
+        self.update_model_view_proj(queue);
+        result
+    }
+
+    ///
+    /// Rebuilds the pipelines using the shader file `file_name` from its WGSL `source`, e.g. while editing the shaders
+    ///
+    /// `shader3d.wgsl` goes to both face passes and `canvas_shader.wgsl` to the canvas pass, other files are ignored.
+    /// An invalid shader is returned as error and the passes keep their pipelines.
+    ///
+    pub fn reload_shader(&mut self, file_name: &str, source: &str, device: &Device) -> Result<()> {
+        match file_name {
+            "shader3d.wgsl" => {
+                self.front_face_pass.reload_shader(device, source)?;
+                self.back_face_pass.reload_shader(device, source)
+            }
+            "canvas_shader.wgsl" => self.canvas_pass.reload_shader(device, source),
+            _ => Ok(()),
+        }
+    }
+
+    fn render_passes(&self, render_into_view: &TextureView, encoder: &mut CommandEncoder) {
+        self.front_face_pass
+            .render(&self.front_face_render_buffer.view, None, encoder);
+        self.back_face_pass
+            .render(&self.back_face_render_buffer.view, None, encoder);
+        match &self.accumulation_pass {
+            None => self.canvas_pass.render(render_into_view, None, encoder),
+            Some(accumulation_pass) => {
+                self.canvas_pass
+                    .render(accumulation_pass.frame_view(), None, encoder);
+                accumulation_pass.render(render_into_view, None, encoder);
+            }
+        }
+        // drawn after averaging, the edges do not need refinement
+        if self.wireframe_mode != WireframeMode::Hidden {
+            self.wireframe_pass.render(render_into_view, None, encoder);
+        }
+        if let Some(slice_pass) = &self.slice_pass {
+            let viewport = self.slice_viewport(slice_pass.axis());
+            slice_pass.render(&self.canvas_pass, render_into_view, viewport, encoder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod viewer_tests {
+    use super::*;
+    use crate::test_utils::request_device;
+
+    #[test]
+    fn test_clamp_sample_count() {
+        let Some((adapter, device, _queue)) = request_device() else {
+            return;
+        };
+        let format = TextureFormat::Rgba8UnormSrgb;
+        // WebGPU guarantees 4x multisampling for these formats
+        let mut render_configs = RenderConfigs::default();
+        render_configs.clamp_sample_count(&adapter, &device, &format);
+        assert_eq!(render_configs.sample_count.get(), 4);
+        // 64x is not supported anywhere without adapter specific format features
+        render_configs.sample_count = NonZeroU32::new(64).unwrap();
+        render_configs.clamp_sample_count(&adapter, &device, &format);
+        assert_eq!(render_configs.sample_count.get(), 4);
+    }
+
+    #[test]
+    fn test_clamp_present_mode() {
+        let mut render_configs = RenderConfigs {
+            present_mode: PresentMode::Mailbox,
+            ..Default::default()
+        };
+        render_configs.clamp_present_mode(&[PresentMode::Fifo, PresentMode::Mailbox]);
+        assert_eq!(render_configs.present_mode, PresentMode::Mailbox);
+        render_configs.clamp_present_mode(&[PresentMode::Fifo, PresentMode::Immediate]);
+        assert_eq!(render_configs.present_mode, PresentMode::Fifo);
+    }
+
+    #[test]
+    fn test_from_voxels_size_mismatch() {
+        let render_configs = RenderConfigs::default();
+        assert!(VolumeData::from_voxels((2, 2, 2), vec![0.0; 7], &render_configs).is_err());
+        assert!(VolumeData::from_voxels((2, 2, 2), vec![0.0; 8], &render_configs).is_ok());
+    }
+
+    #[test]
+    fn test_load_native_sample_type() {
+        let path = std::env::temp_dir().join(format!(
+            "wenderer_test_viewer_u8_{}.raw",
+            std::process::id()
+        ));
+        std::fs::write(&path, [0u8, 64, 128, 255]).unwrap();
+        let render_configs = RenderConfigs {
+            volume_path: path.clone(),
+            volume_dims: Some((2, 2, 1)),
+            volume_sample_type: RawSampleType::U8,
+            occupancy_block_size: None,
+            ..Default::default()
+        };
+        let volume = VolumeData::load(&render_configs);
+        std::fs::remove_file(&path).unwrap();
+        let volume = volume.unwrap();
+        // 8-bit samples take a byte per voxel on the GPU too
+        assert_eq!(volume.sample_type.texture_format(), TextureFormat::R8Unorm);
+        assert_eq!(volume.dims, (2, 2, 1));
+        assert_eq!(volume.data[0], 0.0);
+        assert_eq!(volume.data[3], 1.0);
+    }
+
+    #[test]
+    fn test_render_offscreen() {
+        let Some((_, device, queue)) = request_device() else {
+            return;
+        };
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let size = (32, 32);
+        let mut viewer =
+            Viewer::new_offscreen(&device, &queue, (4, 4, 4), vec![1.0; 64], size, &format)
+                .unwrap();
+        viewer.update(&queue);
+        let target = viewer.render_to_texture(&device, &queue);
+        assert_eq!(target.format, format);
+        let image = target.read_rgba8_image(&device, &queue).unwrap();
+        // the opaque end of the transfer function covers the middle, the transparent background the corner
+        assert!(image.get_pixel(16, 16).0[3] > 128);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        // the same frame is rendered every time, so it can be compared against a reference
+        assert_eq!(viewer.capture(&device, &queue).unwrap(), image);
+        // moved out of the view, the middle shows the background
+        viewer.set_volume_transform(Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)));
+        viewer.update(&queue);
+        let moved = viewer.capture(&device, &queue).unwrap();
+        assert_eq!(moved.get_pixel(16, 16).0, [0, 0, 0, 0]);
+        assert_eq!(viewer.volume_center(), Point3::new(5.0, 0.0, 0.0));
+        assert_eq!(
+            viewer.model_view_proj(),
+            viewer.camera().build_view_projection_matrix(
+                Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)) * viewer.cube_scaling
+            )
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//!
+//! Browser entry point, the counterpart of `main.rs` for `wasm32` builds
+//!
+//! Build the module with e.g. `wasm-pack build --target web --release`, then call [`run`] from the page
+//! with the id of a canvas and a raw volume in the format of [`crate::utils::load_volume_data`],
+//! fetched by the page since there is no file system. WebGPU is used where the browser supports it, WebGL 2 otherwise.
+//!
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::HtmlCanvasElement;
+use wgpu::*;
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop, EventLoopProxy};
+use winit::platform::web::{EventLoopExtWebSys, WindowAttributesExtWebSys};
+use winit::window::{Window, WindowId};
+
+use crate::utils::{
+    choose_surface_format, normalize_u16_by_range, parse_volume_data, CameraController, Endianness,
+};
+use crate::{RenderConfigs, Viewer, VolumeData};
+
+///
+/// Renders `volume` into the canvas with the id `canvas_id` and lets the mouse drive the camera
+///
+/// Returns once the event loop runs, the device is requested asynchronously and the first frame follows when it is ready.
+/// Errors are logged to the browser console.
+///
+#[wasm_bindgen]
+pub fn run(canvas_id: &str, volume: &[u8]) -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+    // fails only if a logger is set already, e.g. when the page runs a second canvas
+    let _ = console_log::init_with_level(log::Level::Warn);
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(canvas_id))
+        .and_then(|element| element.dyn_into::<HtmlCanvasElement>().ok())
+        .ok_or_else(|| JsValue::from_str(&format!("No canvas with the id {}", canvas_id)))?;
+    let render_configs = RenderConfigs::default();
+    // files written on x86 and ARM machines are little endian, like WebAssembly
+    let (dims, samples) = parse_volume_data(volume, Endianness::Little, true)
+        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+    let (data, _) = normalize_u16_by_range(&samples, None, true);
+    let volume = VolumeData::from_voxels(dims, data, &render_configs)
+        .map_err(|e| JsValue::from_str(&format!("{:?}", e)))?;
+    let event_loop = EventLoop::<GpuState>::with_user_event()
+        .build()
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let app = WebApp {
+        canvas,
+        render_configs,
+        volume: Some(volume),
+        event_loop_proxy: event_loop.create_proxy(),
+        window: None,
+        gpu: None,
+        viewer: None,
+        camera_controller: CameraController::new(0.2),
+    };
+    // unlike run_app, returns to the browser right away
+    event_loop.spawn_app(app);
+    Ok(())
+}
+
+/// Device and surface of the canvas with the render configs adjusted to them
+struct GpuState {
+    surface: Surface<'static>,
+    surface_configs: SurfaceConfiguration, // its only view format is the sRGB format the viewer renders into
+    device: Device,
+    queue: Queue,
+    render_configs: RenderConfigs,
+}
+
+impl GpuState {
+    /// Same as `RenderState::new` of `main.rs`, but awaited on the browser's event loop instead of blocking it
+    async fn new(window: Arc<Window>, mut render_configs: RenderConfigs) -> Self {
+        let size = window.inner_size();
+        let size = PhysicalSize::new(size.width.max(1), size.height.max(1));
+        let instance = Instance::new(render_configs.instance_descriptor());
+        let surface = instance
+            .create_surface(window)
+            .expect("Failed to create surface");
+        let adapter = instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: PowerPreference::default(),
+                force_fallback_adapter: false,
+                compatible_surface: Some(&surface),
+            })
+            .await
+            .expect("Neither WebGPU nor WebGL 2 is available");
+        let (device, queue) = adapter
+            .request_device(
+                &DeviceDescriptor {
+                    label: Some("Wenderer Device"),
+                    required_features: adapter.features()
+                        & (Features::TIMESTAMP_QUERY
+                            | Features::TEXTURE_FORMAT_16BIT_NORM
+                            | Features::FLOAT32_FILTERABLE
+                            | Features::ADDRESS_MODE_CLAMP_TO_ZERO
+                            | Features::ADDRESS_MODE_CLAMP_TO_BORDER),
+                    // WebGL 2 falls short of the default limits, what it offers is enough for most volumes
+                    required_limits: adapter.limits(),
+                    memory_hints: MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .expect("Failed to request the device");
+        // the passes output linear color, which an sRGB view of the surface encodes
+        let surface_capabilities = surface.get_capabilities(&adapter);
+        let (surface_format, render_format) = choose_surface_format(&surface_capabilities.formats);
+        render_configs.clamp_present_mode(&surface_capabilities.present_modes);
+        let surface_configs = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode: render_configs.present_mode,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Auto,
+            view_formats: vec![render_format],
+        };
+        surface.configure(&device, &surface_configs);
+        render_configs.clamp_sample_count(&adapter, &device, &render_format);
+        render_configs.clamp_gradients_on_gpu(&adapter);
+        Self {
+            surface,
+            surface_configs,
+            device,
+            queue,
+            render_configs,
+        }
+    }
+}
+
+struct WebApp {
+    canvas: HtmlCanvasElement,
+    render_configs: RenderConfigs,
+    volume: Option<VolumeData>, // handed to the viewer once the device is ready
+    event_loop_proxy: EventLoopProxy<GpuState>,
+    window: Option<Arc<Window>>,
+    gpu: Option<GpuState>,
+    viewer: Option<Viewer>,
+    camera_controller: CameraController,
+}
+
+impl WebApp {
+    fn resize(&mut self, new_size: PhysicalSize<u32>) {
+        // a hidden canvas has no size
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.camera_controller
+            .resize(new_size.width, new_size.height);
+        let Some(gpu) = self.gpu.as_mut() else {
+            return;
+        };
+        gpu.surface_configs.width = new_size.width;
+        gpu.surface_configs.height = new_size.height;
+        gpu.surface.configure(&gpu.device, &gpu.surface_configs);
+        if let Some(viewer) = self.viewer.as_mut() {
+            viewer.resize(&gpu.device, &gpu.queue, new_size.width, new_size.height);
+        }
+    }
+
+    fn render(&mut self) {
+        let (Some(gpu), Some(viewer)) = (self.gpu.as_ref(), self.viewer.as_mut()) else {
+            return;
+        };
+        let mut camera = *viewer.camera();
+        self.camera_controller
+            .update_camera(&mut camera, viewer.volume_center());
+        viewer.set_camera(camera);
+        viewer.update(&gpu.queue);
+        let frame = match gpu.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::warn!("Skipping the frame: {:?}", e);
+                return;
+            }
+        };
+        let view = frame.texture.create_view(&TextureViewDescriptor {
+            label: Some("Render Texture View"),
+            format: Some(gpu.surface_configs.view_formats[0]),
+            ..Default::default()
+        });
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        viewer.render(&view, &mut encoder);
+        gpu.queue.submit(std::iter::once(encoder.finish()));
+        viewer.frame_submitted(&gpu.device);
+        frame.present();
+        if viewer.is_animating() {
+            if let Some(window) = &self.window {
+                window.request_redraw();
+            }
+        }
+    }
+}
+
+impl ApplicationHandler<GpuState> for WebApp {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
+        }
+        let window_attributes = Window::default_attributes().with_canvas(Some(self.canvas.clone()));
+        let window = Arc::new(
+            event_loop
+                .create_window(window_attributes)
+                .expect("Failed to attach to the canvas"),
+        );
+        let event_loop_proxy = self.event_loop_proxy.clone();
+        let render_configs = self.render_configs.clone();
+        let gpu_window = window.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let gpu = GpuState::new(gpu_window, render_configs).await;
+            // fails only if the event loop is gone already
+            let _ = event_loop_proxy.send_event(gpu);
+        });
+        self.window = Some(window);
+    }
+
+    /// The device is ready, builds the viewer
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, gpu: GpuState) {
+        let Some(window) = self.window.clone() else {
+            return;
+        };
+        let Some(volume) = self.volume.take() else {
+            return;
+        };
+        self.render_configs = gpu.render_configs.clone();
+        self.gpu = Some(gpu);
+        // the canvas may have been resized while the device was requested
+        self.resize(window.inner_size());
+        let gpu = self.gpu.as_ref().unwrap();
+        let viewer = Viewer::from_volume(
+            &self.render_configs,
+            volume,
+            &gpu.device,
+            &gpu.queue,
+            (gpu.surface_configs.width, gpu.surface_configs.height),
+            &gpu.surface_configs.view_formats[0],
+        );
+        match viewer {
+            Ok(viewer) => self.viewer = Some(viewer),
+            Err(e) => log::error!("Volume too large for this GPU: {:?}", e),
+        }
+        window.request_redraw();
+    }
+
+    fn window_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        let Some(window) = self.window.clone() else {
+            return;
+        };
+        if window.id() != window_id {
+            return;
+        }
+        if self.camera_controller.process_events(&event) {
+            window.request_redraw();
+            return;
+        }
+        match event {
+            WindowEvent::Resized(physical_size) => {
+                self.resize(physical_size);
+                window.request_redraw();
+            }
+            WindowEvent::RedrawRequested => self.render(),
+            _ => {}
+        }
+    }
+}